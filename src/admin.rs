@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use crate::{
+    internal::{
+        dht::service::grpc,
+        util::{HEX_BASE, U64_HEX_NUM_OF_DIGITS},
+    },
+    NodeInfo, Version,
+};
+
+pub use crate::internal::dht::node::NodeState;
+
+/// Number of keys and bytes of keys and values stored in a node.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StoreStats {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// Progress of a key transfer between a node and a neighbor joining next to
+/// it.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub peer_id: u64,
+    pub outgoing: bool,
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// The full state of a node.
+///
+#[derive(Debug, Clone)]
+pub struct NodeDump {
+    pub id: u64,
+    pub pub_addr: String,
+    pub state: NodeState,
+    pub uptime: Duration,
+    pub version: String,
+    /// Number of neighbors on each side of the leaf set.
+    pub k: usize,
+    pub leaf_set: Vec<NodeInfo>,
+    /// Every row of the routing table, with `None` for empty entries.
+    pub routing_table: Vec<Vec<Option<NodeInfo>>>,
+    pub store: StoreStats,
+    pub transfers: Vec<Transfer>,
+}
+
+/// A key stored in a node.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredKey {
+    /// The ID of the key in the ring.
+    pub id: u64,
+    pub key: Vec<u8>,
+    pub version: Version,
+    pub value_size: u64,
+}
+
+/// A page of the keys stored in a node, in key order.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyPage {
+    pub keys: Vec<StoredKey>,
+    /// The key to list the next page after, or `None` on the last page.
+    pub next: Option<Vec<u8>>,
+}
+
+impl From<NodeState> for grpc::NodeStatus {
+    fn from(state: NodeState) -> Self {
+        match state {
+            NodeState::Uninitialized => grpc::NodeStatus::Uninitialized,
+            NodeState::Initializing => grpc::NodeStatus::Initializing,
+            NodeState::UpdatingConnections => grpc::NodeStatus::UpdatingConnections,
+            NodeState::RoutingRequests => grpc::NodeStatus::RoutingRequests,
+        }
+    }
+}
+
+impl From<grpc::NodeStatus> for NodeState {
+    fn from(status: grpc::NodeStatus) -> Self {
+        match status {
+            grpc::NodeStatus::Uninitialized => NodeState::Uninitialized,
+            grpc::NodeStatus::Initializing => NodeState::Initializing,
+            grpc::NodeStatus::UpdatingConnections => NodeState::UpdatingConnections,
+            grpc::NodeStatus::RoutingRequests => NodeState::RoutingRequests,
+        }
+    }
+}
+
+impl From<StoreStats> for grpc::StoreStats {
+    fn from(stats: StoreStats) -> Self {
+        grpc::StoreStats {
+            keys: stats.keys,
+            bytes: stats.bytes,
+        }
+    }
+}
+
+impl From<grpc::StoreStats> for StoreStats {
+    fn from(stats: grpc::StoreStats) -> Self {
+        StoreStats {
+            keys: stats.keys,
+            bytes: stats.bytes,
+        }
+    }
+}
+
+impl From<Transfer> for grpc::Transfer {
+    fn from(transfer: Transfer) -> Self {
+        grpc::Transfer {
+            peer_id: transfer.peer_id,
+            outgoing: transfer.outgoing,
+            keys: transfer.keys,
+            bytes: transfer.bytes,
+        }
+    }
+}
+
+impl From<grpc::Transfer> for Transfer {
+    fn from(transfer: grpc::Transfer) -> Self {
+        Transfer {
+            peer_id: transfer.peer_id,
+            outgoing: transfer.outgoing,
+            keys: transfer.keys,
+            bytes: transfer.bytes,
+        }
+    }
+}
+
+impl From<grpc::DumpStateResponse> for NodeDump {
+    fn from(dump: grpc::DumpStateResponse) -> Self {
+        let mut routing_table = vec![vec![None; HEX_BASE as usize]; U64_HEX_NUM_OF_DIGITS as usize];
+        for entry in &dump.routing_table {
+            let cell = routing_table
+                .get_mut(entry.row as usize)
+                .and_then(|row| row.get_mut(entry.column as usize));
+            if let (Some(cell), Some(node)) = (cell, &entry.node) {
+                *cell = Some(NodeInfo::from_node_entry(node));
+            }
+        }
+
+        NodeDump {
+            id: dump.id,
+            pub_addr: dump.pub_addr,
+            state: grpc::NodeStatus::try_from(dump.status)
+                .unwrap_or(grpc::NodeStatus::Uninitialized)
+                .into(),
+            uptime: Duration::from_millis(dump.uptime_ms),
+            version: dump.version,
+            k: dump.k as usize,
+            leaf_set: dump
+                .leaf_set
+                .iter()
+                .map(NodeInfo::from_node_entry)
+                .collect(),
+            routing_table,
+            store: dump.store.unwrap_or_default().into(),
+            transfers: dump.transfers.into_iter().map(Transfer::from).collect(),
+        }
+    }
+}
+
+impl From<grpc::ListKeysResponse> for KeyPage {
+    fn from(page: grpc::ListKeysResponse) -> Self {
+        KeyPage {
+            keys: page
+                .keys
+                .into_iter()
+                .map(|key| StoredKey {
+                    id: key.key,
+                    key: key.raw_key,
+                    version: key.version.unwrap_or_default().into(),
+                    value_size: key.value_size,
+                })
+                .collect(),
+            next: page.next_start_after,
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use log::{debug, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Serialize, Serializer};
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{ClientConfig, PastryClient, QueryKind},
+    error::*,
+    ring,
+    testkit::TestNetwork,
+};
+
+const QUERY_TIMEOUT_SECS: u64 = 10;
+const CONNECT_RETRY_MILLIS: u64 = 10;
+const CHURN_POLL_MILLIS: u64 = 10;
+const DEFAULT_ZIPF_EXPONENT: f64 = 0.99;
+
+/// Relative weights of gets, sets and deletes in a workload.
+///
+/// Parsed from strings such as `get=80,set=15,delete=5`, where omitted
+/// queries have no weight.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Mix {
+    pub get: u32,
+    pub set: u32,
+    pub delete: u32,
+}
+
+impl Mix {
+    fn total(&self) -> u64 {
+        self.get as u64 + self.set as u64 + self.delete as u64
+    }
+
+    fn pick(&self, rng: &mut StdRng) -> QueryKind {
+        let n = rng.gen_range(0..self.total());
+        if n < self.get as u64 {
+            QueryKind::Get
+        } else if n < self.get as u64 + self.set as u64 {
+            QueryKind::Set
+        } else {
+            QueryKind::Delete
+        }
+    }
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Mix {
+            get: 80,
+            set: 15,
+            delete: 5,
+        }
+    }
+}
+
+impl FromStr for Mix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut mix = Mix {
+            get: 0,
+            set: 0,
+            delete: 0,
+        };
+
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| Error::Parse(format!("expected query=weight, got {}", part)))?;
+            let weight = weight.trim().parse()?;
+            match name.trim() {
+                "get" => mix.get = weight,
+                "set" => mix.set = weight,
+                "delete" => mix.delete = weight,
+                name => return Err(Error::Parse(format!("unknown query: {}", name))),
+            }
+        }
+
+        if mix.total() == 0 {
+            return Err(Error::Parse(format!("mix without queries: {}", s)));
+        }
+        Ok(mix)
+    }
+}
+
+/// How the keys of a workload are drawn.
+///
+/// Parsed from `uniform`, `zipfian`, or `zipfian:<exponent>`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum KeyDistribution {
+    /// Every key is equally likely.
+    Uniform,
+    /// The n-th most popular key is drawn with a probability proportional
+    /// to `1 / n^exponent`.
+    Zipfian { exponent: f64 },
+}
+
+impl FromStr for KeyDistribution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "uniform" => Ok(KeyDistribution::Uniform),
+            None if s == "zipfian" => Ok(KeyDistribution::Zipfian {
+                exponent: DEFAULT_ZIPF_EXPONENT,
+            }),
+            Some(("zipfian", exponent)) => {
+                let exponent = exponent
+                    .parse()
+                    .map_err(|_| Error::Parse(format!("invalid exponent: {}", exponent)))?;
+                Ok(KeyDistribution::Zipfian { exponent })
+            }
+            _ => Err(Error::Parse(format!("unknown key distribution: {}", s))),
+        }
+    }
+}
+
+/// Draws key indexes from a distribution.
+enum KeySampler {
+    Uniform(u64),
+    /// The cumulative probability of every key, by popularity.
+    Zipfian(Vec<f64>),
+}
+
+impl KeySampler {
+    fn new(distribution: KeyDistribution, keys: u64) -> Self {
+        match distribution {
+            KeyDistribution::Uniform => KeySampler::Uniform(keys),
+            KeyDistribution::Zipfian { exponent } => {
+                let mut sum = 0.0;
+                let mut cdf: Vec<f64> = (1..=keys)
+                    .map(|rank| {
+                        sum += 1.0 / (rank as f64).powf(exponent);
+                        sum
+                    })
+                    .collect();
+                cdf.iter_mut().for_each(|p| *p /= sum);
+                KeySampler::Zipfian(cdf)
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> u64 {
+        match self {
+            KeySampler::Uniform(keys) => rng.gen_range(0..*keys),
+            KeySampler::Zipfian(cdf) => {
+                let p: f64 = rng.gen();
+                cdf.partition_point(|&c| c < p).min(cdf.len() - 1) as u64
+            }
+        }
+    }
+}
+
+fn key_name(idx: u64) -> Vec<u8> {
+    format!("bench_{}", idx).into_bytes()
+}
+
+/// The load driven against the network.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct Workload {
+    /// The weights of gets, sets and deletes.
+    pub mix: Mix,
+    /// The number of distinct keys queried.
+    pub keys: u64,
+    /// How keys are drawn.
+    pub distribution: KeyDistribution,
+    /// The size in bytes of the values set.
+    pub value_size: usize,
+    /// The number of workers, each with its own connection and at most one
+    /// query in flight.
+    pub concurrency: usize,
+    /// How long the load is driven for.
+    #[serde(rename = "duration_secs", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    /// Stops once this many queries were sent, if sooner than `duration`.
+    pub max_ops: Option<u64>,
+    /// Whether every key is set before the load starts, so gets and deletes
+    /// find them.
+    pub preload: bool,
+    /// How often a node of a local network is killed, or the last killed
+    /// node restarted, while the load runs.
+    #[serde(rename = "churn_interval_secs", serialize_with = "serialize_opt_secs")]
+    pub churn_interval: Option<Duration>,
+    /// The seed queries and keys are drawn from.
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            mix: Mix::default(),
+            keys: 10_000,
+            distribution: KeyDistribution::Uniform,
+            value_size: 100,
+            concurrency: 16,
+            duration: Duration::from_secs(10),
+            max_ops: None,
+            preload: false,
+            churn_interval: None,
+            seed: 0,
+        }
+    }
+}
+
+fn serialize_secs<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn serialize_opt_secs<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => serializer.serialize_f64(duration.as_secs_f64()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// The nodes a workload is driven against.
+///
+pub enum Target<'a> {
+    /// Running nodes, each worker connecting to one of them at random.
+    Nodes {
+        addrs: Vec<String>,
+        config: ClientConfig,
+    },
+    /// A local network, which may be churned.
+    Network(&'a mut TestNetwork),
+}
+
+/// The results of a workload.
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub workload: Workload,
+    /// The number of nodes in the ring when the load started, if known.
+    pub nodes: Option<usize>,
+    /// The leaf set parameter of the ring, if known.
+    pub k: Option<usize>,
+    pub elapsed_secs: f64,
+    /// The number of queries sent, including failed ones.
+    pub operations: u64,
+    pub errors: u64,
+    /// Successful queries per second.
+    pub throughput: f64,
+    /// The latency of successful queries.
+    pub latency: Latency,
+    /// The results by query, keyed by `get`, `set` and `delete`.
+    pub queries: BTreeMap<String, QueryReport>,
+    /// How many times successful queries were forwarded.
+    pub hops: Hops,
+    pub churn: Churn,
+}
+
+/// The results of one kind of query.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueryReport {
+    pub operations: u64,
+    pub errors: u64,
+    /// Successful gets and deletes of keys that were not stored.
+    pub misses: u64,
+    pub latency: Latency,
+}
+
+/// Latency percentiles, in microseconds.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Latency {
+    pub min_us: u64,
+    pub mean_us: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub p999_us: u64,
+    pub max_us: u64,
+}
+
+impl Latency {
+    fn from_samples(mut micros: Vec<u64>) -> Self {
+        if micros.is_empty() {
+            return Latency::default();
+        }
+
+        micros.sort_unstable();
+        let percentile = |p: f64| {
+            let rank = (p * micros.len() as f64).ceil() as usize;
+            micros[rank.clamp(1, micros.len()) - 1]
+        };
+
+        Latency {
+            min_us: micros[0],
+            mean_us: micros.iter().sum::<u64>() as f64 / micros.len() as f64,
+            p50_us: percentile(0.5),
+            p90_us: percentile(0.9),
+            p99_us: percentile(0.99),
+            p999_us: percentile(0.999),
+            max_us: micros[micros.len() - 1],
+        }
+    }
+}
+
+/// The distribution of hops.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Hops {
+    pub mean: f64,
+    pub max: u32,
+    /// The number of queries by number of hops.
+    pub histogram: BTreeMap<u32, u64>,
+}
+
+/// The failures injected while the load ran.
+///
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Churn {
+    pub kills: u64,
+    pub restarts: u64,
+}
+
+impl Report {
+    /// Exports the report to pretty-printed JSON.
+    ///
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+struct Sample {
+    kind: QueryKind,
+    latency: Duration,
+    /// The hops of a successful query and whether the key was stored.
+    outcome: Option<(u32, bool)>,
+}
+
+/// The state shared by the workers.
+struct Shared {
+    addrs: RwLock<Vec<String>>,
+    config: ClientConfig,
+    workload: Workload,
+    sampler: KeySampler,
+    value: Vec<u8>,
+    issued: AtomicU64,
+}
+
+impl Shared {
+    async fn connect(&self, rng: &mut StdRng) -> Result<PastryClient> {
+        let addr = {
+            let addrs = self.addrs.read()?;
+            if addrs.is_empty() {
+                return Err(Error::Config("no node to connect to".into()));
+            }
+            addrs[rng.gen_range(0..addrs.len())].clone()
+        };
+        PastryClient::connect_with_config(&addr, &self.config).await
+    }
+
+    /// Claims the next query, unless the maximum was reached.
+    fn claim(&self) -> bool {
+        self.workload
+            .max_ops
+            .is_none_or(|max| self.issued.fetch_add(1, Ordering::Relaxed) < max)
+    }
+}
+
+/// Drives a workload against a network, measuring the latency and hops of
+/// every query.
+///
+/// # Arguments
+///
+/// * `workload` - The load to drive.
+/// * `target` - The nodes to query. Only a local network can be churned.
+///
+/// # Returns
+///
+/// A Result containing the report of the run.
+///
+pub async fn run(workload: &Workload, target: Target<'_>) -> Result<Report> {
+    if workload.concurrency == 0 || workload.keys == 0 || workload.mix.total() == 0 {
+        return Err(Error::Config(
+            "workload needs workers, keys and queries".into(),
+        ));
+    }
+
+    let (addrs, config, nodes, k, mut network) = match target {
+        Target::Nodes { addrs, config } => {
+            if workload.churn_interval.is_some() {
+                return Err(Error::Config("churn requires a local network".into()));
+            }
+            let nodes = match addrs.first() {
+                Some(addr) => match ring::crawl(addr, &config, false).await {
+                    Ok(snapshot) => Some(snapshot.nodes.len()),
+                    Err(err) => {
+                        warn!("Could not crawl the ring from {}: {}", addr, err);
+                        None
+                    }
+                },
+                None => None,
+            };
+            (addrs, config, nodes, None, None)
+        }
+        Target::Network(network) => {
+            let addrs = network
+                .nodes()
+                .into_iter()
+                .map(|node| node.pub_addr)
+                .collect();
+            let nodes = Some(network.nodes().len());
+            let k = Some(network.config().k);
+            (addrs, network.client_config(), nodes, k, Some(network))
+        }
+    };
+
+    let shared = Arc::new(Shared {
+        addrs: RwLock::new(addrs),
+        config,
+        workload: workload.clone(),
+        sampler: KeySampler::new(workload.distribution, workload.keys),
+        value: vec![b'x'; workload.value_size],
+        issued: AtomicU64::new(0),
+    });
+
+    if workload.preload {
+        preload(&shared).await?;
+    }
+
+    info!(
+        "Driving {:?} with {} workers for {:?}",
+        workload.mix, workload.concurrency, workload.duration
+    );
+    let start = Instant::now();
+    let deadline = start + workload.duration;
+    let workers: Vec<_> = (0..workload.concurrency as u64)
+        .map(|i| {
+            let rng = StdRng::seed_from_u64(workload.seed.wrapping_add(i));
+            tokio::spawn(worker(shared.clone(), rng, deadline))
+        })
+        .collect();
+
+    let mut churn = Churn::default();
+    if let (Some(interval), Some(network)) = (workload.churn_interval, network.as_mut()) {
+        let mut killed = Vec::new();
+        let mut next_churn = start + interval;
+        while !workers.iter().all(|worker| worker.is_finished()) {
+            tokio::time::sleep(Duration::from_millis(CHURN_POLL_MILLIS)).await;
+            if Instant::now() >= next_churn && Instant::now() < deadline {
+                churn_step(network, &shared, &mut killed, &mut churn).await?;
+                next_churn += interval;
+            }
+        }
+    }
+
+    let mut samples = Vec::new();
+    for worker in workers {
+        samples.extend(worker.await?);
+    }
+
+    Ok(report(workload, nodes, k, start.elapsed(), samples, churn))
+}
+
+/// Sets every key of the workload, spreading them over the workers.
+async fn preload(shared: &Arc<Shared>) -> Result<()> {
+    info!("Preloading {} keys", shared.workload.keys);
+
+    let concurrency = shared.workload.concurrency as u64;
+    let loaders: Vec<_> = (0..concurrency)
+        .map(|i| {
+            let shared = shared.clone();
+            tokio::spawn(async move {
+                let mut rng = StdRng::seed_from_u64(shared.workload.seed.wrapping_add(i));
+                let mut client = shared.connect(&mut rng).await?;
+                for idx in (i..shared.workload.keys).step_by(concurrency as usize) {
+                    client.set_kv(&key_name(idx), &shared.value).await?;
+                }
+                Ok::<_, Error>(())
+            })
+        })
+        .collect();
+
+    for loader in loaders {
+        loader.await??;
+    }
+    Ok(())
+}
+
+/// Kills a random node or, if one is down, restarts the last one killed.
+async fn churn_step(
+    network: &mut TestNetwork,
+    shared: &Shared,
+    killed: &mut Vec<u64>,
+    churn: &mut Churn,
+) -> Result<()> {
+    if let Some(id) = killed.pop() {
+        let node = network.restart(id).await?;
+        shared.addrs.write()?.push(node.pub_addr);
+        churn.restarts += 1;
+        info!("Restarted #{:016X}", id);
+    } else if network.nodes().len() > 1 {
+        let node = network
+            .random_node()
+            .ok_or_else(|| Error::Internal("no running node".into()))?;
+        network.kill(node.id).await?;
+        shared.addrs.write()?.retain(|addr| addr != &node.pub_addr);
+        killed.push(node.id);
+        churn.kills += 1;
+        info!("Killed #{:016X}", node.id);
+    }
+    Ok(())
+}
+
+/// Sends queries over a single connection until the deadline, reconnecting
+/// to a random node after a failure.
+async fn worker(shared: Arc<Shared>, mut rng: StdRng, deadline: Instant) -> Vec<Sample> {
+    let mut samples = Vec::new();
+    let mut client: Option<PastryClient> = None;
+
+    while Instant::now() < deadline {
+        let mut conn = match client.take() {
+            Some(conn) => conn,
+            None => match shared.connect(&mut rng).await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    debug!("Could not connect: {}", err);
+                    tokio::time::sleep(Duration::from_millis(CONNECT_RETRY_MILLIS)).await;
+                    continue;
+                }
+            },
+        };
+        if !shared.claim() {
+            break;
+        }
+
+        let kind = shared.workload.mix.pick(&mut rng);
+        let key = key_name(shared.sampler.sample(&mut rng));
+        let start = Instant::now();
+        let res = tokio::time::timeout(
+            Duration::from_secs(QUERY_TIMEOUT_SECS),
+            conn.query_kv(kind, &key, Some(&shared.value)),
+        )
+        .await
+        .map_err(|_| Error::Internal("query timed out".into()))
+        .and_then(|res| res);
+        let latency = start.elapsed();
+
+        let outcome = match res {
+            Ok(outcome) => {
+                client = Some(conn);
+                Some((outcome.hops, outcome.value.is_some()))
+            }
+            Err(err) => {
+                debug!("{:?} query failed: {}", kind, err);
+                None
+            }
+        };
+        samples.push(Sample {
+            kind,
+            latency,
+            outcome,
+        });
+    }
+
+    samples
+}
+
+fn report(
+    workload: &Workload,
+    nodes: Option<usize>,
+    k: Option<usize>,
+    elapsed: Duration,
+    samples: Vec<Sample>,
+    churn: Churn,
+) -> Report {
+    let mut latencies = Vec::new();
+    let mut queries = BTreeMap::new();
+    let mut hops = Hops::default();
+
+    for (kind, name) in [
+        (QueryKind::Get, "get"),
+        (QueryKind::Set, "set"),
+        (QueryKind::Delete, "delete"),
+    ] {
+        let mut query = QueryReport::default();
+        let mut query_latencies = Vec::new();
+
+        for sample in samples.iter().filter(|sample| sample.kind == kind) {
+            query.operations += 1;
+            match sample.outcome {
+                Some((sample_hops, found)) => {
+                    query_latencies.push(sample.latency.as_micros() as u64);
+                    if !found && kind != QueryKind::Set {
+                        query.misses += 1;
+                    }
+                    *hops.histogram.entry(sample_hops).or_default() += 1;
+                    hops.max = hops.max.max(sample_hops);
+                }
+                None => query.errors += 1,
+            }
+        }
+
+        latencies.extend_from_slice(&query_latencies);
+        query.latency = Latency::from_samples(query_latencies);
+        queries.insert(name.to_owned(), query);
+    }
+
+    let succeeded = latencies.len() as u64;
+    if succeeded > 0 {
+        let total: u64 = hops
+            .histogram
+            .iter()
+            .map(|(&hops, &count)| hops as u64 * count)
+            .sum();
+        hops.mean = total as f64 / succeeded as f64;
+    }
+
+    Report {
+        workload: workload.clone(),
+        nodes,
+        k,
+        elapsed_secs: elapsed.as_secs_f64(),
+        operations: samples.len() as u64,
+        errors: samples.len() as u64 - succeeded,
+        throughput: succeeded as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        latency: Latency::from_samples(latencies),
+        queries,
+        hops,
+        churn,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mix() -> Result<()> {
+        assert_eq!(
+            "get=90, delete=10".parse::<Mix>()?,
+            Mix {
+                get: 90,
+                set: 0,
+                delete: 10
+            }
+        );
+        assert!("get=0".parse::<Mix>().is_err());
+        assert!("scan=1".parse::<Mix>().is_err());
+        assert!("get".parse::<Mix>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_distribution() -> Result<()> {
+        assert_eq!(
+            "uniform".parse::<KeyDistribution>()?,
+            KeyDistribution::Uniform
+        );
+        assert_eq!(
+            "zipfian:1.5".parse::<KeyDistribution>()?,
+            KeyDistribution::Zipfian { exponent: 1.5 }
+        );
+        assert!("zipfian:high".parse::<KeyDistribution>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zipfian_skew() {
+        let sampler = KeySampler::new(KeyDistribution::Zipfian { exponent: 1.0 }, 1000);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = vec![0u32; 1000];
+        for _ in 0..10_000 {
+            counts[sampler.sample(&mut rng) as usize] += 1;
+        }
+
+        // the most popular key is drawn about 1 / H(1000) ~ 13% of the time
+        assert!(counts[0] > 1000 && counts[0] < 1700);
+        assert!(counts[0] > counts[1] && counts[1] > counts[100]);
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let latency = Latency::from_samples((1..=1000).rev().collect());
+
+        assert_eq!(latency.min_us, 1);
+        assert_eq!(latency.p50_us, 500);
+        assert_eq!(latency.p99_us, 990);
+        assert_eq!(latency.p999_us, 999);
+        assert_eq!(latency.max_us, 1000);
+        assert_eq!(Latency::from_samples(Vec::new()).max_us, 0);
+    }
+}
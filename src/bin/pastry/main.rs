@@ -0,0 +1,508 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+#[cfg(feature = "bench")]
+use std::time::Duration;
+use std::{
+    fs,
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    process::ExitCode,
+};
+
+#[cfg(feature = "bench")]
+use pastry_dht::{
+    bench::{self, KeyDistribution, Mix, Workload},
+    testkit::TestNetwork,
+    Config,
+};
+use pastry_dht::{
+    builder::{IdStrategy, PastryNodeBuilder, TlsSettings},
+    client::{ClientConfig, PastryClient},
+    error::*,
+    ring,
+    topology::Topology,
+    NodeInfo, Tls,
+};
+
+/// Runs and operates Pastry DHT nodes.
+#[derive(Parser)]
+#[command(name = "pastry", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manages a local node.
+    Node {
+        #[command(subcommand)]
+        command: NodeCommand,
+    },
+    /// Prints the value stored under a key.
+    Get {
+        #[command(flatten)]
+        target: Target,
+        key: String,
+    },
+    /// Stores a value under a key.
+    Set {
+        #[command(flatten)]
+        target: Target,
+        key: String,
+        value: String,
+    },
+    /// Deletes a key.
+    Delete {
+        #[command(flatten)]
+        target: Target,
+        key: String,
+    },
+    /// Prints the state, leaf set and routing table of a node.
+    Inspect {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Crawls the ring from a node and reports broken invariants.
+    Check {
+        #[command(flatten)]
+        target: Target,
+        /// Also check that every node only stores keys it owns.
+        #[arg(long)]
+        keys: bool,
+    },
+    /// Prints the overlay seen by a node, or by the whole ring.
+    Topology {
+        #[command(flatten)]
+        target: Target,
+        /// Crawl the whole ring instead of exporting a single node.
+        #[arg(long)]
+        ring: bool,
+        #[arg(short, long, value_enum, default_value_t = Format::Dot)]
+        format: Format,
+    },
+    /// Drives a load against a local ring, or running nodes, and prints a
+    /// JSON report of throughput, latency and hops.
+    #[cfg(feature = "bench")]
+    Bench(BenchArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Graphviz DOT digraph.
+    Dot,
+    /// JSON document with IDs as hexadecimal strings.
+    Json,
+}
+
+#[derive(Subcommand)]
+enum NodeCommand {
+    /// Runs a node until interrupted.
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// TOML or JSON configuration file. `PASTRY_*` environment variables
+    /// and the flags below override its settings.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Address of the socket to listen on [default: 0.0.0.0:50000].
+    #[arg(long)]
+    listen: Option<SocketAddr>,
+    /// Address the node is exposed on. Defaults to the listen address.
+    #[arg(long)]
+    public: Option<SocketAddr>,
+    /// Node to join the network through, tried in order. Starts a new
+    /// network if none is given.
+    #[arg(long = "bootstrap", value_name = "URL")]
+    bootstrap: Vec<String>,
+    /// Number of neighbors on each side of the leaf set [default: 8].
+    #[arg(short, long)]
+    k: Option<usize>,
+    /// How the node picks its ID: address, random, persisted or a
+    /// hexadecimal ID.
+    #[arg(long)]
+    id: Option<IdStrategy>,
+    /// Directory where the node keeps its persistent state. Unless `--id`
+    /// is given, the node keeps its ID there across restarts.
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// PEM certificate chain the node presents. Serves and connects to
+    /// other nodes over TLS when given.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the node's certificate.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// PEM CA certificate other nodes and clients are verified against.
+    /// Requires them to present a certificate signed by it.
+    #[arg(long, requires = "tls_cert")]
+    tls_ca: Option<PathBuf>,
+}
+
+#[cfg(feature = "bench")]
+#[derive(Args)]
+struct BenchArgs {
+    /// Running node to drive the load against, instead of starting a local
+    /// ring. May be repeated.
+    #[arg(long = "target", value_name = "URL")]
+    targets: Vec<String>,
+    #[command(flatten)]
+    credentials: Credentials,
+    /// Number of nodes of the local ring.
+    #[arg(long, default_value_t = 16, conflicts_with = "targets")]
+    nodes: usize,
+    /// Number of neighbors on each side of the leaf set of the local ring.
+    #[arg(short, long, default_value_t = 8, conflicts_with = "targets")]
+    k: usize,
+    /// Connects the local ring through in-memory pipes instead of TCP.
+    #[arg(long, conflicts_with = "targets")]
+    in_memory: bool,
+    /// First port the local ring listens on.
+    #[arg(long, default_value_t = 40000, conflicts_with = "targets")]
+    first_port: u16,
+    /// Seconds the load is driven for.
+    #[arg(short, long, default_value_t = 10.0)]
+    duration: f64,
+    /// Stops after this many queries, if sooner than the duration.
+    #[arg(long)]
+    ops: Option<u64>,
+    /// Number of workers, each with at most one query in flight.
+    #[arg(short, long, default_value_t = 16)]
+    concurrency: usize,
+    /// Weights of gets, sets and deletes.
+    #[arg(long, default_value = "get=80,set=15,delete=5")]
+    mix: Mix,
+    /// Number of distinct keys.
+    #[arg(long, default_value_t = 10_000)]
+    keys: u64,
+    /// How keys are drawn: uniform, zipfian or zipfian:<exponent>.
+    #[arg(long, default_value = "uniform")]
+    distribution: KeyDistribution,
+    /// Size in bytes of the values set.
+    #[arg(long, default_value_t = 100)]
+    value_size: usize,
+    /// Sets every key before the load starts.
+    #[arg(long)]
+    preload: bool,
+    /// Kills a node of the local ring, or restarts the last one killed,
+    /// every this many seconds.
+    #[arg(long, value_name = "SECONDS", conflicts_with = "targets")]
+    churn: Option<f64>,
+    /// Seed queries and keys are drawn from.
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Writes the report to a file instead of the standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct Target {
+    /// Public address of the node to send the request to.
+    #[arg(short, long, default_value = "http://127.0.0.1:50000")]
+    node: String,
+    #[command(flatten)]
+    credentials: Credentials,
+}
+
+#[derive(Args)]
+struct Credentials {
+    /// PEM CA certificate the node is verified against. Connects over TLS
+    /// when given.
+    #[arg(long)]
+    ca: Option<PathBuf>,
+    /// PEM client certificate, for nodes requiring mutual TLS.
+    #[arg(long, requires = "key")]
+    cert: Option<PathBuf>,
+    /// PEM private key of the client certificate.
+    #[arg(long, requires = "cert")]
+    key: Option<PathBuf>,
+    /// Bearer token sent with every request, for nodes requiring
+    /// authentication.
+    #[arg(long)]
+    token: Option<String>,
+}
+
+impl Credentials {
+    fn config(&self) -> Result<ClientConfig> {
+        let mut config = ClientConfig::new();
+
+        if self.ca.is_some() || self.cert.is_some() {
+            let mut tls = Tls::new();
+            if let (Some(cert), Some(key)) = (&self.cert, &self.key) {
+                tls = tls.with_identity(fs::read(cert)?, fs::read(key)?);
+            }
+            if let Some(ca) = &self.ca {
+                tls = tls.with_ca(fs::read(ca)?);
+            }
+            config = config.with_tls(tls);
+        }
+        if let Some(token) = &self.token {
+            config = config.with_token(token);
+        }
+
+        Ok(config)
+    }
+}
+
+impl Target {
+    fn config(&self) -> Result<ClientConfig> {
+        self.credentials.config()
+    }
+
+    async fn connect(&self) -> Result<PastryClient> {
+        PastryClient::connect_with_config(&self.node, &self.config()?).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli.command).await {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command) -> Result<ExitCode> {
+    match command {
+        Command::Node {
+            command: NodeCommand::Run(args),
+        } => run_node(args).await?,
+        Command::Get { target, key } => {
+            let mut client = target.connect().await?;
+            match client.get_kv(key.as_bytes()).await? {
+                Some(value) => print_value(&value)?,
+                None => {
+                    eprintln!("key not found");
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+        Command::Set { target, key, value } => {
+            let mut client = target.connect().await?;
+            client.set_kv(key.as_bytes(), value.as_bytes()).await?;
+        }
+        Command::Delete { target, key } => {
+            let mut client = target.connect().await?;
+            if client.delete_kv(key.as_bytes()).await?.is_none() {
+                eprintln!("key not found");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Inspect { target } => inspect(&target).await?,
+        Command::Check { target, keys } => return check(&target, keys).await,
+        Command::Topology {
+            target,
+            ring,
+            format,
+        } => topology(&target, ring, format).await?,
+        #[cfg(feature = "bench")]
+        Command::Bench(args) => bench(args).await?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn run_node(args: RunArgs) -> Result<()> {
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+
+    let mut builder = match &args.config {
+        Some(path) => PastryNodeBuilder::from_file(path)?,
+        None => PastryNodeBuilder::new(),
+    }
+    .with_env()?;
+
+    if let Some(addr) = args.listen {
+        builder = builder.listen_addr(addr);
+    }
+    if let Some(addr) = args.public {
+        builder = builder.public_addr(addr);
+    }
+    if !args.bootstrap.is_empty() {
+        builder = builder.bootstrap(args.bootstrap);
+    }
+    if let Some(k) = args.k {
+        builder = builder.k(k);
+    }
+    if let Some(dir) = args.data_dir {
+        builder = builder.data_dir(dir).id(IdStrategy::Persisted);
+    }
+    if let Some(id) = args.id {
+        builder = builder.id(id);
+    }
+    if let (Some(cert), Some(key)) = (args.tls_cert, args.tls_key) {
+        builder = builder.tls(TlsSettings {
+            cert,
+            key,
+            ca: args.tls_ca,
+        });
+    }
+
+    builder.bootstrap_and_serve().await
+}
+
+fn print_value(value: &[u8]) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(value)?;
+    stdout.write_all(b"\n")?;
+    Ok(())
+}
+
+async fn inspect(target: &Target) -> Result<()> {
+    let mut client = target.connect().await?;
+    let dump = client.dump_state().await?;
+    let id = dump.id;
+
+    println!("Node #{:016X} {}", id, dump.pub_addr);
+    println!("  State:   {:?}", dump.state);
+    println!("  Uptime:  {}s", dump.uptime.as_secs());
+    println!("  Version: {}", dump.version);
+    println!(
+        "  Store:   {} keys, {} bytes",
+        dump.store.keys, dump.store.bytes
+    );
+    for transfer in &dump.transfers {
+        println!(
+            "  Transfer {} #{:016X}: {} keys, {} bytes",
+            if transfer.outgoing { "to" } else { "from" },
+            transfer.peer_id,
+            transfer.keys,
+            transfer.bytes
+        );
+    }
+
+    println!("\nLeaf set:");
+    for entry in &dump.leaf_set {
+        println!("  {}", format_entry(entry, id));
+    }
+
+    println!("\nRouting table:");
+    for (row, entries) in dump.routing_table.iter().enumerate() {
+        if entries.iter().flatten().all(|entry| entry.id == id) {
+            continue;
+        }
+
+        println!("  Row {:X}:", row);
+        for (column, entry) in entries.iter().enumerate() {
+            if let Some(entry) = entry {
+                println!("    {:X}: {}", column, format_entry(entry, id));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn check(target: &Target, with_keys: bool) -> Result<ExitCode> {
+    let snapshot = ring::crawl(&target.node, &target.config()?, with_keys).await?;
+    let violations = snapshot.check();
+
+    println!(
+        "Crawled {} nodes, {} unreachable",
+        snapshot.nodes.len(),
+        snapshot.unreachable.len()
+    );
+    for node in snapshot.unreachable.values() {
+        println!("  Unreachable #{:016X} {}", node.id, node.pub_addr);
+    }
+
+    if violations.is_empty() {
+        println!("No violations found");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!("\n{} violations:", violations.len());
+    for violation in &violations {
+        println!("  {}", violation);
+    }
+
+    Ok(ExitCode::FAILURE)
+}
+
+async fn topology(target: &Target, whole_ring: bool, format: Format) -> Result<()> {
+    let topology = if whole_ring {
+        Topology::from_ring(&ring::crawl(&target.node, &target.config()?, false).await?)
+    } else {
+        let mut client = target.connect().await?;
+        Topology::from_node(&client.dump_state().await?)
+    };
+
+    match format {
+        Format::Dot => print!("{}", topology.to_dot()),
+        Format::Json => println!("{}", topology.to_json()?),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "bench")]
+async fn bench(args: BenchArgs) -> Result<()> {
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Error)
+        .init();
+
+    let workload = Workload {
+        mix: args.mix,
+        keys: args.keys,
+        distribution: args.distribution,
+        value_size: args.value_size,
+        concurrency: args.concurrency,
+        duration: Duration::try_from_secs_f64(args.duration)
+            .map_err(|err| Error::Config(err.to_string()))?,
+        max_ops: args.ops,
+        preload: args.preload,
+        churn_interval: args
+            .churn
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|err| Error::Config(err.to_string()))?,
+        seed: args.seed,
+    };
+
+    let report = if args.targets.is_empty() {
+        let mut builder = TestNetwork::builder(Config::new(args.k))
+            .nodes(args.nodes)
+            .first_port(args.first_port);
+        if args.in_memory {
+            builder = builder.in_memory();
+        }
+        let mut network = builder.build().await?;
+        bench::run(&workload, bench::Target::Network(&mut network)).await?
+    } else {
+        let target = bench::Target::Nodes {
+            addrs: args.targets,
+            config: args.credentials.config()?,
+        };
+        bench::run(&workload, target).await?
+    };
+
+    eprintln!(
+        "{} queries, {} errors, {:.0} queries/s, p50 {}us, p99 {}us, {:.2} hops on average",
+        report.operations,
+        report.errors,
+        report.throughput,
+        report.latency.p50_us,
+        report.latency.p99_us,
+        report.hops.mean
+    );
+    match args.output {
+        Some(path) => fs::write(path, report.to_json()? + "\n")?,
+        None => println!("{}", report.to_json()?),
+    }
+
+    Ok(())
+}
+
+fn format_entry(entry: &NodeInfo, id: u64) -> String {
+    let marker = if entry.id == id { " (self)" } else { "" };
+    format!("#{:016X} {}{}", entry.id, entry.pub_addr, marker)
+}
@@ -0,0 +1,924 @@
+use serde::Deserialize;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tonic::transport::Uri;
+
+use crate::{
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::dht::trace::SpanExporter,
+    node::PastryNode,
+    Admission, Authenticator, Config, Principal, Role, Timeouts, Tls, TokenAuthenticator,
+    Transport,
+};
+
+const NODE_ID_FILE: &str = "node_id";
+const ENV_PREFIX: &str = "PASTRY_";
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:50000";
+const DEFAULT_K: usize = 8;
+
+/// How a node picks its ID.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IdStrategy {
+    /// The hash of the node's public address.
+    #[default]
+    Address,
+    /// A random ID.
+    Random,
+    /// The hash of the node's public address on first start, kept in the
+    /// data directory afterwards so the node keeps its place in the ring.
+    Persisted,
+    /// A fixed ID.
+    Fixed(u64),
+}
+
+impl FromStr for IdStrategy {
+    type Err = Error;
+
+    /// Parses `address`, `random`, `persisted` or a hexadecimal fixed ID.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "address" => Ok(IdStrategy::Address),
+            "random" => Ok(IdStrategy::Random),
+            "persisted" => Ok(IdStrategy::Persisted),
+            id => u64::from_str_radix(id.trim_start_matches("0x"), 16)
+                .map(IdStrategy::Fixed)
+                .map_err(|_| Error::Config(format!("invalid node ID strategy: {}", s))),
+        }
+    }
+}
+
+/// Certificate files used to secure a node's connections.
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    /// PEM certificate chain presented by the node.
+    pub cert: PathBuf,
+    /// PEM private key of the certificate.
+    pub key: PathBuf,
+    /// PEM certificate authority used to verify other nodes. When set, nodes
+    /// and clients connecting to the node must present a certificate signed
+    /// by it.
+    pub ca: Option<PathBuf>,
+}
+
+impl TlsSettings {
+    /// Reads the certificate files.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the TLS settings of the node.
+    ///
+    pub fn load(&self) -> Result<Tls> {
+        let tls = Tls::new().with_identity(fs::read(&self.cert)?, fs::read(&self.key)?);
+        Ok(match &self.ca {
+            Some(ca) => tls.with_ca(fs::read(ca)?),
+            None => tls,
+        })
+    }
+}
+
+/// Builds a `PastryNode` from code, a configuration file or environment
+/// variables.
+///
+/// Configuration files are TOML or JSON, picked by extension, and hold the
+/// same settings as the builder methods:
+///
+/// ```toml
+/// listen_addr = "0.0.0.0:50000"
+/// public_addr = "10.0.0.1:50000"
+/// id = "persisted"
+/// k = 8
+/// bootstrap = ["http://10.0.0.2:50000"]
+/// metrics_addr = "0.0.0.0:9100"
+/// http_addr = "0.0.0.0:8080"
+/// resp_addr = "0.0.0.0:6379"
+/// memcache_addr = "0.0.0.0:11211"
+///
+/// [timeouts]
+/// connect_ms = 1000
+/// retry_interval_ms = 1000
+/// max_retries = 10
+///
+/// [storage]
+/// data_dir = "/var/lib/pastry"
+///
+/// [admission]
+/// mode = "secret"
+/// secret = "change me"
+///
+/// [auth]
+/// peer_token = "shared by every node"
+///
+/// [[auth.tokens]]
+/// token = "alice's token"
+/// name = "alice"
+/// role = "client"
+/// key_prefix = "alice/"
+///
+/// [tls]
+/// cert = "/etc/pastry/cert.pem"
+/// key = "/etc/pastry/key.pem"
+/// ca = "/etc/pastry/ca.pem"
+/// ```
+///
+/// Environment variables are the upper-case setting names prefixed with
+/// `PASTRY_`, such as `PASTRY_K` or `PASTRY_TIMEOUTS_CONNECT_MS`, with
+/// bootstrap seeds separated by commas.
+///
+#[derive(Debug, Clone)]
+pub struct PastryNodeBuilder {
+    listen_addr: SocketAddr,
+    public_addr: Option<SocketAddr>,
+    id: IdStrategy,
+    config: Config,
+    data_dir: Option<PathBuf>,
+    tls: Option<TlsSettings>,
+    bootstrap: Vec<String>,
+    /// The client tokens of the settings, accepted together with the peer
+    /// token by the authenticator derived when the node is built.
+    tokens: Vec<(String, Principal)>,
+}
+
+impl Default for PastryNodeBuilder {
+    fn default() -> Self {
+        PastryNodeBuilder {
+            listen_addr: DEFAULT_LISTEN_ADDR.parse().unwrap(),
+            public_addr: None,
+            id: IdStrategy::default(),
+            config: Config::new(DEFAULT_K),
+            data_dir: None,
+            tls: None,
+            bootstrap: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    listen_addr: Option<SocketAddr>,
+    public_addr: Option<SocketAddr>,
+    id: Option<String>,
+    k: Option<usize>,
+    bootstrap: Option<Vec<String>>,
+    metrics_addr: Option<SocketAddr>,
+    http_addr: Option<SocketAddr>,
+    resp_addr: Option<SocketAddr>,
+    memcache_addr: Option<SocketAddr>,
+    timeouts: TimeoutSettings,
+    storage: StorageSettings,
+    tls: Option<TlsSettings>,
+    admission: AdmissionSettings,
+    auth: AuthSettings,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TimeoutSettings {
+    connect_ms: Option<u64>,
+    retry_interval_ms: Option<u64>,
+    max_retries: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StorageSettings {
+    data_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSettings {
+    peer_token: Option<String>,
+    tokens: Vec<TokenSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenSettings {
+    token: String,
+    name: String,
+    role: Role,
+    key_prefix: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AdmissionSettings {
+    mode: Option<String>,
+    secret: Option<String>,
+}
+
+impl PastryNodeBuilder {
+    /// Creates a builder with the default settings: listening on
+    /// 0.0.0.0:50000 with k=8 and an ID derived from the public address.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a builder from a TOML or JSON configuration file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file, ending in `.toml` or `.json`.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the builder, or `Error::Config` if the file is
+    /// invalid.
+    ///
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+
+        let settings: Settings = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|err| err.to_string()),
+            Some("json") => serde_json::from_str(&contents).map_err(|err| err.to_string()),
+            _ => Err("expected a .toml or .json file".to_owned()),
+        }
+        .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+
+        Self::new().apply(settings)
+    }
+
+    /// Overrides the settings with the `PASTRY_*` environment variables.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the builder, or `Error::Config` if a variable is
+    /// invalid.
+    ///
+    pub fn with_env(self) -> Result<Self> {
+        self.with_vars(std::env::vars())
+    }
+
+    fn with_vars<I>(self, vars: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        fn parse<T: FromStr>(name: &str, value: &str) -> Result<T> {
+            value
+                .parse()
+                .map_err(|_| Error::Config(format!("invalid {}{}: {}", ENV_PREFIX, name, value)))
+        }
+
+        let mut settings = Settings::default();
+        for (name, value) in vars {
+            let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+
+            match name {
+                "LISTEN_ADDR" => settings.listen_addr = Some(parse(name, &value)?),
+                "PUBLIC_ADDR" => settings.public_addr = Some(parse(name, &value)?),
+                "ID" => settings.id = Some(value),
+                "K" => settings.k = Some(parse(name, &value)?),
+                "BOOTSTRAP" => {
+                    settings.bootstrap = Some(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|seed| !seed.is_empty())
+                            .map(str::to_owned)
+                            .collect(),
+                    )
+                }
+                "METRICS_ADDR" => settings.metrics_addr = Some(parse(name, &value)?),
+                "HTTP_ADDR" => settings.http_addr = Some(parse(name, &value)?),
+                "RESP_ADDR" => settings.resp_addr = Some(parse(name, &value)?),
+                "MEMCACHE_ADDR" => settings.memcache_addr = Some(parse(name, &value)?),
+                "TIMEOUTS_CONNECT_MS" => settings.timeouts.connect_ms = Some(parse(name, &value)?),
+                "TIMEOUTS_RETRY_INTERVAL_MS" => {
+                    settings.timeouts.retry_interval_ms = Some(parse(name, &value)?)
+                }
+                "TIMEOUTS_MAX_RETRIES" => {
+                    settings.timeouts.max_retries = Some(parse(name, &value)?)
+                }
+                "STORAGE_DATA_DIR" => settings.storage.data_dir = Some(value.into()),
+                "ADMISSION_MODE" => settings.admission.mode = Some(value),
+                "ADMISSION_SECRET" => settings.admission.secret = Some(value),
+                "AUTH_PEER_TOKEN" => settings.auth.peer_token = Some(value),
+                "TLS_CERT" | "TLS_KEY" | "TLS_CA" => {
+                    let tls = settings.tls.get_or_insert_with(|| {
+                        self.tls.clone().unwrap_or(TlsSettings {
+                            cert: PathBuf::new(),
+                            key: PathBuf::new(),
+                            ca: None,
+                        })
+                    });
+                    match name {
+                        "TLS_CERT" => tls.cert = value.into(),
+                        "TLS_KEY" => tls.key = value.into(),
+                        _ => tls.ca = Some(value.into()),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.apply(settings)
+    }
+
+    fn apply(mut self, settings: Settings) -> Result<Self> {
+        if let Some(addr) = settings.listen_addr {
+            self.listen_addr = addr;
+        }
+        if let Some(addr) = settings.public_addr {
+            self.public_addr = Some(addr);
+        }
+        if let Some(id) = settings.id {
+            self.id = id.parse()?;
+        }
+        if let Some(k) = settings.k {
+            self.config.k = k;
+        }
+        if let Some(seeds) = settings.bootstrap {
+            self.bootstrap = seeds;
+        }
+        if let Some(addr) = settings.metrics_addr {
+            self.config.metrics_addr = Some(addr);
+        }
+        if let Some(addr) = settings.http_addr {
+            self.config.http_addr = Some(addr);
+        }
+        if let Some(addr) = settings.resp_addr {
+            self.config.resp_addr = Some(addr);
+        }
+        if let Some(addr) = settings.memcache_addr {
+            self.config.memcache_addr = Some(addr);
+        }
+        if let Some(ms) = settings.timeouts.connect_ms {
+            self.config.timeouts.connect = Duration::from_millis(ms);
+        }
+        if let Some(ms) = settings.timeouts.retry_interval_ms {
+            self.config.timeouts.retry_interval = Duration::from_millis(ms);
+        }
+        if let Some(retries) = settings.timeouts.max_retries {
+            self.config.timeouts.max_retries = retries;
+        }
+        if let Some(dir) = settings.storage.data_dir {
+            self.data_dir = Some(dir);
+        }
+        if let Some(tls) = settings.tls {
+            self.tls = Some(tls);
+        }
+
+        if let Some(token) = &settings.auth.peer_token {
+            self.config.peer_token = Some(token.clone());
+        }
+        if !settings.auth.tokens.is_empty() {
+            self.tokens = settings
+                .auth
+                .tokens
+                .into_iter()
+                .map(|token| {
+                    let mut principal = Principal::new(&token.name, token.role);
+                    if let Some(prefix) = token.key_prefix {
+                        principal = principal.with_key_prefix(prefix.as_bytes());
+                    }
+                    (token.token, principal)
+                })
+                .collect();
+        }
+
+        let secret = settings.admission.secret.map(String::into_bytes);
+        match (settings.admission.mode.as_deref(), secret) {
+            (None, None) => {}
+            (Some("open"), _) => self.config.admission = Admission::Open,
+            (Some("address"), _) => self.config.admission = Admission::Address,
+            (Some("secret") | None, Some(secret)) => {
+                self.config.admission = Admission::Secret(secret)
+            }
+            (Some("secret"), None) => {
+                if !matches!(self.config.admission, Admission::Secret(_)) {
+                    return Err(Error::Config(
+                        "admission mode \"secret\" requires a secret".into(),
+                    ));
+                }
+            }
+            (Some(mode), _) => {
+                return Err(Error::Config(format!(
+                    "invalid admission mode: {} (expected open, address or secret)",
+                    mode
+                )))
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Sets the address of the socket the node listens on.
+    pub fn listen_addr(mut self, addr: SocketAddr) -> Self {
+        self.listen_addr = addr;
+        self
+    }
+
+    /// Sets the address the node is exposed on. Defaults to the listen
+    /// address.
+    pub fn public_addr(mut self, addr: SocketAddr) -> Self {
+        self.public_addr = Some(addr);
+        self
+    }
+
+    /// Sets how the node picks its ID.
+    pub fn id(mut self, id: IdStrategy) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets the number of neighbors on each side of the leaf set.
+    pub fn k(mut self, k: usize) -> Self {
+        self.config.k = k;
+        self
+    }
+
+    /// Sets the timeouts used when connecting to other nodes.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.config.timeouts = timeouts;
+        self
+    }
+
+    /// Sets the directory where the node keeps its persistent state.
+    pub fn data_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the certificate files used to secure the node's connections.
+    pub fn tls(mut self, tls: TlsSettings) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets how the node verifies that peers own the IDs they claim.
+    pub fn admission(mut self, admission: Admission) -> Self {
+        self.config.admission = admission;
+        self
+    }
+
+    /// Authenticates and authorizes every request served by the node.
+    pub fn authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.config.authenticator = Some(authenticator);
+        self.tokens.clear();
+        self
+    }
+
+    /// Sets the bearer token the node sends to other nodes.
+    pub fn peer_token(mut self, token: &str) -> Self {
+        self.config.peer_token = Some(token.to_owned());
+        self
+    }
+
+    /// Sets how the node serves requests and reaches other nodes, instead
+    /// of TCP sockets.
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    /// Sets the nodes to join the network through, tried in order.
+    pub fn bootstrap<I, S>(mut self, seeds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.bootstrap = seeds.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Serves the node's Prometheus metrics over HTTP on `/metrics`.
+    pub fn metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Serves key-value operations and the node's state over HTTP.
+    pub fn http_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.http_addr = Some(addr);
+        self
+    }
+
+    /// Serves a Redis-compatible listener.
+    pub fn resp_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.resp_addr = Some(addr);
+        self
+    }
+
+    /// Serves a listener speaking the memcached text protocol.
+    pub fn memcache_addr(mut self, addr: SocketAddr) -> Self {
+        self.config.memcache_addr = Some(addr);
+        self
+    }
+
+    /// Records the spans of the requests handled by the node.
+    pub fn span_exporter(mut self, exporter: Arc<dyn SpanExporter>) -> Self {
+        self.config.span_exporter = Some(exporter);
+        self
+    }
+
+    /// Checks that the settings are consistent.
+    ///
+    /// # Returns
+    ///
+    /// An empty Result, or `Error::Config` describing the first invalid
+    /// setting.
+    ///
+    pub fn validate(&self) -> Result<()> {
+        if self.config.k == 0 {
+            return Err(Error::Config("k must be at least 1".into()));
+        }
+
+        let timeouts = &self.config.timeouts;
+        if timeouts.connect.is_zero() {
+            return Err(Error::Config("connect timeout must not be zero".into()));
+        }
+        if timeouts.max_retries == 0 {
+            return Err(Error::Config("max retries must be at least 1".into()));
+        }
+
+        if self.id == IdStrategy::Persisted && self.data_dir.is_none() {
+            return Err(Error::Config(
+                "persisted node IDs require a data directory".into(),
+            ));
+        }
+
+        match &self.config.admission {
+            Admission::Address if self.id != IdStrategy::Address => {
+                return Err(Error::Config(
+                    "address admission requires node IDs derived from the address".into(),
+                ));
+            }
+            Admission::Secret(secret) if secret.is_empty() => {
+                return Err(Error::Config("admission secret must not be empty".into()));
+            }
+            _ => {}
+        }
+
+        for seed in &self.bootstrap {
+            match seed.parse::<Uri>() {
+                Ok(uri) if matches!(uri.scheme_str(), Some("http" | "https")) => {}
+                _ => {
+                    return Err(Error::Config(format!(
+                        "invalid bootstrap node address: {}",
+                        seed
+                    )))
+                }
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            for path in [Some(&tls.cert), Some(&tls.key), tls.ca.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if !path.is_file() {
+                    return Err(Error::Config(format!(
+                        "TLS file not found: {}",
+                        path.display()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Derives the node's config from the settings. With client tokens, the
+    /// node authenticates them and the peer token as they are after every
+    /// file and environment override.
+    fn node_config(&self) -> Config {
+        let mut config = self.config.clone();
+        if !self.tokens.is_empty() {
+            // nodes accept each other's peer token
+            let mut authenticator = TokenAuthenticator::new();
+            if let Some(token) = &config.peer_token {
+                authenticator = authenticator.with_token(token, Principal::new("peer", Role::Peer));
+            }
+            for (token, principal) in &self.tokens {
+                authenticator = authenticator.with_token(token, principal.clone());
+            }
+            config.authenticator = Some(Arc::new(authenticator));
+        }
+        config
+    }
+
+    /// Validates the settings and registers the node.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the newly registered node.
+    ///
+    pub fn build(self) -> Result<PastryNode> {
+        self.validate()?;
+
+        let pub_addr = self.public_addr.unwrap_or(self.listen_addr);
+        let config = self.node_config();
+        let config = match &self.tls {
+            Some(tls) => config.with_tls(tls.load()?),
+            None => config,
+        };
+
+        match self.id {
+            IdStrategy::Address => PastryNode::new(config, self.listen_addr, pub_addr),
+            IdStrategy::Random => {
+                PastryNode::from_id(config, self.listen_addr, pub_addr, rand::random())
+            }
+            IdStrategy::Fixed(id) => PastryNode::from_id(config, self.listen_addr, pub_addr, id),
+            IdStrategy::Persisted => {
+                let dir = self.data_dir.unwrap_or_default();
+                match read_node_id(&dir)? {
+                    Some(id) => PastryNode::from_id(config, self.listen_addr, pub_addr, id),
+                    None => {
+                        let node = PastryNode::new(config, self.listen_addr, pub_addr)?;
+                        write_node_id(&dir, node.get_id())?;
+                        Ok(node)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Registers the node, joins the network through the first reachable
+    /// bootstrap node, or starts a new one if none is configured, and serves
+    /// the node server.
+    ///
+    /// # Returns
+    ///
+    /// An empty Result, or `Error::Config` if bootstrap nodes are configured
+    /// but none of them is reachable.
+    ///
+    pub async fn bootstrap_and_serve(self) -> Result<()> {
+        self.validate()?;
+
+        let seeds = self.bootstrap.clone();
+        let client_config = ClientConfig {
+            tls: self.tls.as_ref().map(TlsSettings::load).transpose()?,
+            token: self.config.peer_token.clone(),
+            transport: Some(self.config.transport.clone()),
+        };
+        let node = self.build()?;
+
+        let mut bootstrap_addr = None;
+        for seed in &seeds {
+            match PastryClient::connect_with_config(seed, &client_config).await {
+                Ok(_) => {
+                    bootstrap_addr = Some(seed.as_str());
+                    break;
+                }
+                Err(err) => log::warn!("Bootstrap node {} is unreachable: {}", seed, err),
+            }
+        }
+
+        if !seeds.is_empty() && bootstrap_addr.is_none() {
+            return Err(Error::Config("no bootstrap node is reachable".into()));
+        }
+
+        node.bootstrap_and_serve(bootstrap_addr).await
+    }
+}
+
+fn read_node_id(dir: &Path) -> Result<Option<u64>> {
+    match fs::read_to_string(dir.join(NODE_ID_FILE)) {
+        Ok(id) => Ok(Some(u64::from_str_radix(id.trim(), 16).map_err(|_| {
+            Error::Config(format!("invalid node ID in {}", dir.display()))
+        })?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_node_id(dir: &Path, id: u64) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(NODE_ID_FILE), format!("{:016X}\n", id))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pastry-{}-{}", rand::random::<u32>(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_from_toml_file() -> Result<()> {
+        let path = write_temp(
+            "node.toml",
+            r#"
+            listen_addr = "127.0.0.1:40000"
+            id = "C4D22D90FE2D56F6"
+            k = 4
+            bootstrap = ["http://127.0.0.1:40001"]
+
+            [timeouts]
+            connect_ms = 250
+
+            [auth]
+            peer_token = "peer-token"
+
+            [[auth.tokens]]
+            token = "alice-token"
+            name = "alice"
+            role = "client"
+            key_prefix = "alice/"
+            "#,
+        );
+
+        let builder = PastryNodeBuilder::from_file(&path)?;
+        assert_eq!(builder.listen_addr, "127.0.0.1:40000".parse()?);
+        assert_eq!(builder.id, IdStrategy::Fixed(0xC4D22D90FE2D56F6));
+        assert_eq!(builder.config.k, 4);
+        assert_eq!(builder.config.timeouts.connect, Duration::from_millis(250));
+        assert_eq!(builder.config.timeouts.max_retries, 10);
+        assert_eq!(builder.bootstrap, vec!["http://127.0.0.1:40001"]);
+        assert_eq!(builder.config.peer_token.as_deref(), Some("peer-token"));
+        builder.validate()?;
+
+        let authenticator = builder.node_config().authenticator.unwrap();
+        for (token, name, role) in [
+            ("peer-token", "peer", Role::Peer),
+            ("alice-token", "alice", Role::Client),
+        ] {
+            let mut request = tonic::Request::new(());
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+
+            let principal = authenticator.authenticate(&request)?.unwrap();
+            assert_eq!((principal.name.as_str(), principal.role), (name, role));
+        }
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_json_file() -> Result<()> {
+        let path = write_temp("node.json", r#"{"k": 16, "storage": {"data_dir": "/tmp"}}"#);
+
+        let builder = PastryNodeBuilder::from_file(&path)?;
+        assert_eq!(builder.config.k, 16);
+        assert_eq!(builder.data_dir, Some(PathBuf::from("/tmp")));
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let unknown = write_temp("unknown.toml", "leaf_size = 8");
+        let format = write_temp("node.yaml", "k: 8");
+
+        for path in [&unknown, &format] {
+            assert!(matches!(
+                PastryNodeBuilder::from_file(path),
+                Err(Error::Config(_))
+            ));
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_env_overrides() -> Result<()> {
+        let vars = [
+            ("PASTRY_K", "2"),
+            ("PASTRY_ID", "random"),
+            ("PASTRY_BOOTSTRAP", "http://a:1, http://b:2"),
+            ("PASTRY_TIMEOUTS_MAX_RETRIES", "3"),
+            ("PASTRY_ADMISSION_SECRET", "secret"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+
+        let builder = PastryNodeBuilder::new().k(8).with_vars(vars)?;
+        assert_eq!(builder.config.k, 2);
+        assert_eq!(builder.id, IdStrategy::Random);
+        assert_eq!(builder.bootstrap, vec!["http://a:1", "http://b:2"]);
+        assert_eq!(builder.config.timeouts.max_retries, 3);
+        assert_eq!(
+            builder.config.admission,
+            Admission::Secret(b"secret".to_vec())
+        );
+
+        for (name, value) in [("PASTRY_K", "eight"), ("PASTRY_ADMISSION_MODE", "secret")] {
+            let invalid = [(name.to_owned(), value.to_owned())];
+            assert!(matches!(
+                PastryNodeBuilder::new().with_vars(invalid),
+                Err(Error::Config(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_peer_token() -> Result<()> {
+        let path = write_temp(
+            "auth.toml",
+            r#"
+            [auth]
+            peer_token = "file-token"
+
+            [[auth.tokens]]
+            token = "alice-token"
+            name = "alice"
+            role = "client"
+            "#,
+        );
+
+        let vars = [("PASTRY_AUTH_PEER_TOKEN".to_owned(), "env-token".to_owned())];
+        let builder = PastryNodeBuilder::from_file(&path)?.with_vars(vars)?;
+        assert_eq!(builder.config.peer_token.as_deref(), Some("env-token"));
+
+        let authenticator = builder.node_config().authenticator.unwrap();
+        let authenticate = |token: &str| {
+            let mut request = tonic::Request::new(());
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            authenticator.authenticate(&request)
+        };
+        assert_eq!(authenticate("env-token")?.unwrap().role, Role::Peer);
+        assert_eq!(authenticate("alice-token")?.unwrap().role, Role::Client);
+        assert!(authenticate("file-token").is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_validates_tls_files() {
+        let builder = PastryNodeBuilder::new().tls(TlsSettings {
+            cert: "/nonexistent/cert.pem".into(),
+            key: "/nonexistent/key.pem".into(),
+            ca: None,
+        });
+        assert!(matches!(
+            builder.bootstrap_and_serve().await,
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = [
+            PastryNodeBuilder::new().k(0),
+            PastryNodeBuilder::new().id(IdStrategy::Persisted),
+            PastryNodeBuilder::new().bootstrap(["127.0.0.1:50000"]),
+            PastryNodeBuilder::new().timeouts(Timeouts {
+                max_retries: 0,
+                ..Timeouts::default()
+            }),
+            PastryNodeBuilder::new()
+                .id(IdStrategy::Random)
+                .admission(Admission::Address),
+            PastryNodeBuilder::new().admission(Admission::Secret(Vec::new())),
+            PastryNodeBuilder::new().tls(TlsSettings {
+                cert: "/nonexistent/cert.pem".into(),
+                key: "/nonexistent/key.pem".into(),
+                ca: None,
+            }),
+        ];
+
+        for builder in invalid {
+            assert!(matches!(builder.validate(), Err(Error::Config(_))));
+        }
+        assert!(PastryNodeBuilder::new().validate().is_ok());
+    }
+
+    #[test]
+    fn test_persisted_id() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("pastry-{}", rand::random::<u32>()));
+        let builder = PastryNodeBuilder::new()
+            .listen_addr("127.0.0.1:40000".parse()?)
+            .id(IdStrategy::Persisted)
+            .data_dir(&dir);
+
+        let first = builder.clone().build()?.get_id();
+        let second = builder
+            .public_addr("127.0.0.1:40001".parse()?)
+            .build()?
+            .get_id();
+        assert_eq!(first, second);
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
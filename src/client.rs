@@ -1,18 +1,108 @@
-use tonic::transport::Channel;
+use log::warn;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{
+    codegen::InterceptedService,
+    transport::{Channel, Endpoint},
+};
 
 use crate::{
+    admin::{KeyPage, NodeDump},
     error::*,
     internal::{
-        dht::service::grpc::{NodeServiceClient, QueryRequest, QueryType},
+        blob::{self, Manifest, BLOB_CHUNK_SIZE},
+        dht::{
+            auth::BearerToken,
+            node::{Lookup, NodeInfo, MAX_MESSAGE_SIZE},
+            service::grpc::{
+                BatchQueryRequest, GetNodeTableEntryRequest, ListKeysRequest, LookupRequest,
+                NodeServiceClient, QueryRequest, QueryType, WatchRequest,
+            },
+            transport::Transport,
+        },
         hring::hasher::Sha256Hasher,
+        pastry::shared::Tls,
+        util::{HEX_BASE, U64_HEX_NUM_OF_DIGITS},
     },
+    Version, WatchEvent,
 };
 
+const WATCH_RETRY_SECONDS: u64 = 1;
+
+/// Settings of a client's connections to nodes.
+///
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// The TLS settings, if the nodes serve over TLS.
+    pub tls: Option<Tls>,
+    /// The bearer token sent with every request, if the nodes require
+    /// authentication.
+    pub token: Option<String>,
+    /// The transport the nodes are reached through, or `None` for TCP.
+    pub transport: Option<Arc<dyn Transport>>,
+}
+
+impl ClientConfig {
+    /// Creates a config for plaintext, unauthenticated connections.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connects over TLS.
+    ///
+    pub fn with_tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sends a bearer token with every request.
+    ///
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_owned());
+        self
+    }
+
+    /// Reaches the nodes through a transport other than TCP, such as the
+    /// `MemoryTransport` of nodes running in the same process.
+    ///
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+}
+
+/// A single-key query.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum QueryKind {
+    Get,
+    Set,
+    Delete,
+}
+
+/// The outcome of a single-key query and how it was routed.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryOutcome {
+    /// The value read, replaced or deleted, if the key existed.
+    pub value: Option<Vec<u8>>,
+    /// The ID of the node that answered the query.
+    pub owner: u64,
+    /// The number of times the query was forwarded before reaching the
+    /// node that answered it.
+    pub hops: u32,
+}
+
 /// A client for Pastry nodes.
 ///
 #[derive(Clone)]
 pub struct PastryClient {
-    client: NodeServiceClient<Channel>,
+    client: NodeServiceClient<InterceptedService<Channel, BearerToken>>,
 }
 
 impl PastryClient {
@@ -27,8 +117,52 @@ impl PastryClient {
     /// Returns a `Result` containing the client.
     ///
     pub async fn connect(address: &str) -> Result<Self> {
+        PastryClient::connect_with_config(address, &ClientConfig::new()).await
+    }
+
+    /// Connects to a node in the Pastry network over TLS.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The public `https` address of the node.
+    /// * `tls` - The CA the node is verified against and, if the node
+    ///   requires mutual TLS, the client's certificate and key.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the client.
+    ///
+    pub async fn connect_with_tls(address: &str, tls: &Tls) -> Result<Self> {
+        PastryClient::connect_with_config(address, &ClientConfig::new().with_tls(tls.clone())).await
+    }
+
+    /// Connects to a node in the Pastry network with the given TLS settings
+    /// and credentials.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The public address of the node.
+    /// * `config` - The TLS settings and bearer token to connect with.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the client.
+    ///
+    pub async fn connect_with_config(address: &str, config: &ClientConfig) -> Result<Self> {
+        let mut endpoint = Endpoint::from_shared(address.to_owned())?;
+        if let Some(tls) = &config.tls {
+            endpoint = endpoint.tls_config(tls.client_config())?;
+        }
+
+        let channel = match &config.transport {
+            Some(transport) => transport.connect(endpoint).await?,
+            None => endpoint.connect().await?,
+        };
+
+        let token = BearerToken::new(config.token.as_deref())?;
         Ok(PastryClient {
-            client: NodeServiceClient::connect(address.to_owned()).await?,
+            client: NodeServiceClient::with_interceptor(channel, token)
+                .max_decoding_message_size(MAX_MESSAGE_SIZE),
         })
     }
 
@@ -53,20 +187,43 @@ impl PastryClient {
     pub async fn get_kv(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let response = self
             .client
-            .query(QueryRequest {
-                from_id: 0,
-                matched_digits: 0,
-                hops: 0,
-                query_type: QueryType::Get.into(),
-                key: Sha256Hasher::hash_once(key),
-                value: None,
-            })
+            .query(QueryRequest::new(QueryType::Get, key.to_vec(), None))
             .await?
             .into_inner();
 
         Ok(response.value)
     }
 
+    /// Retrieves a value and its version for the given key stored in the
+    /// Pastry network.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key for which the value is
+    ///   requested.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` which is:
+    ///
+    /// - `Ok(Some((Vec<u8>, Version)))` if the key exists, containing the
+    ///   associated value and the version it was written with.
+    /// - `Ok(None)` if the key does not exist.
+    /// - `Err(e)` where `e` encapsulates any error encountered during the
+    ///   operation.
+    ///
+    pub async fn get_kv_versioned(&mut self, key: &[u8]) -> Result<Option<(Vec<u8>, Version)>> {
+        let response = self
+            .client
+            .query(QueryRequest::new(QueryType::Get, key.to_vec(), None))
+            .await?
+            .into_inner();
+
+        Ok(response
+            .value
+            .map(|value| (value, response.version.unwrap_or_default().into())))
+    }
+
     /// Sets a value for a given key in the Pastry network.
     ///
     /// # Arguments
@@ -88,14 +245,11 @@ impl PastryClient {
     pub async fn set_kv(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
         let response = self
             .client
-            .query(QueryRequest {
-                from_id: 0,
-                matched_digits: 0,
-                hops: 0,
-                query_type: QueryType::Set.into(),
-                key: Sha256Hasher::hash_once(key),
-                value: Some(value.to_vec()),
-            })
+            .query(QueryRequest::new(
+                QueryType::Set,
+                key.to_vec(),
+                Some(value.to_vec()),
+            ))
             .await?
             .into_inner();
 
@@ -120,17 +274,399 @@ impl PastryClient {
     pub async fn delete_kv(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let response = self
             .client
-            .query(QueryRequest {
-                from_id: 0,
-                matched_digits: 0,
-                hops: 0,
-                query_type: QueryType::Delete.into(),
-                key: Sha256Hasher::hash_once(key),
-                value: None,
-            })
+            .query(QueryRequest::new(QueryType::Delete, key.to_vec(), None))
             .await?
             .into_inner();
 
         Ok(response.value)
     }
+
+    /// Runs a single-key query, reporting which node answered it and after
+    /// how many hops.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Whether to get, set or delete the key.
+    /// * `key` - The key queried.
+    /// * `value` - The value to set, ignored by gets and deletes.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the outcome of the query.
+    ///
+    pub async fn query_kv(
+        &mut self,
+        kind: QueryKind,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> Result<QueryOutcome> {
+        let (query_type, value) = match kind {
+            QueryKind::Get => (QueryType::Get, None),
+            QueryKind::Set => (QueryType::Set, Some(value.unwrap_or_default().to_vec())),
+            QueryKind::Delete => (QueryType::Delete, None),
+        };
+
+        let response = self
+            .client
+            .query(QueryRequest::new(query_type, key.to_vec(), value))
+            .await?
+            .into_inner();
+
+        Ok(QueryOutcome {
+            value: response.value,
+            owner: response.from_id,
+            hops: response.hops,
+        })
+    }
+
+    /// Finds the node owning the given key, without reading or writing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key whose owner is
+    ///   requested.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the owner, its leaf set and the number of hops
+    /// the lookup was routed through.
+    ///
+    pub async fn lookup(&mut self, key: &[u8]) -> Result<Lookup> {
+        let response = self
+            .client
+            .lookup(LookupRequest::new(key))
+            .await?
+            .into_inner();
+
+        Ok(response.into())
+    }
+
+    /// Retrieves the values associated with many keys in a single batch.
+    ///
+    /// Keys are grouped by their next hop and each group is forwarded as a
+    /// single request, which is split again at every hop.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys for which the values are requested.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing, in the same order as `keys`, the value of each
+    /// key, `None` if the key does not exist, or the error its query failed
+    /// with.
+    ///
+    pub async fn get_many(&mut self, keys: &[&[u8]]) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        Ok(self
+            .client
+            .batch_query(BatchQueryRequest::get(keys))
+            .await?
+            .into_inner()
+            .into_results())
+    }
+
+    /// Sets the values of many keys in a single batch.
+    ///
+    /// Keys are grouped by their next hop and each group is forwarded as a
+    /// single request, which is split again at every hop.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The key-value pairs to be set.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing, in the same order as `entries`, the value each
+    /// key had before, `None` if a new entry was created, or the error its
+    /// query failed with.
+    ///
+    pub async fn set_many(
+        &mut self,
+        entries: &[(&[u8], &[u8])],
+    ) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        Ok(self
+            .client
+            .batch_query(BatchQueryRequest::set(entries))
+            .await?
+            .into_inner()
+            .into_results())
+    }
+
+    /// Watches a key for changes.
+    ///
+    /// The watch is routed to the owner of the key, which emits an event on
+    /// every change. When ownership of the key moves to another node the watch
+    /// is re-established automatically.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key to be watched.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a stream of the key's events. The stream ends
+    /// when it is dropped.
+    ///
+    pub async fn watch(&mut self, key: &[u8]) -> Result<impl Stream<Item = WatchEvent>> {
+        let request = WatchRequest {
+            from_id: 0,
+            matched_digits: 0,
+            hops: 0,
+            key: Sha256Hasher::hash_once(key),
+            raw_key: key.to_vec(),
+        };
+
+        let mut upstream = self.client.watch(request.clone()).await?.into_inner();
+        let mut client = self.client.clone();
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    message = upstream.message() => {
+                        if let Ok(Some(event)) = message {
+                            if tx.send(event.into()).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                    }
+                    _ = tx.closed() => return,
+                }
+
+                // watch was closed, route it again from the node the client
+                // is connected to
+                loop {
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(WATCH_RETRY_SECONDS)) => {}
+                        _ = tx.closed() => return,
+                    }
+
+                    match client.watch(request.clone()).await {
+                        Ok(response) => {
+                            upstream = response.into_inner();
+                            break;
+                        }
+                        Err(err) => warn!("Could not re-establish watch: {}", err),
+                    }
+                }
+            }
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Stores a large value read from `reader` in the Pastry network.
+    ///
+    /// The value is split into chunks stored under keys derived from `key`,
+    /// and a manifest listing the digest of every chunk is stored under `key`.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key to which the value is
+    ///   to be associated.
+    /// * `reader` - The source of the value.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the size of the stored value.
+    ///
+    pub async fn put_blob<R>(&mut self, key: &[u8], mut reader: R) -> Result<u64>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let previous = match self.get_kv(key).await? {
+            Some(value) => Manifest::decode(&value).ok(),
+            None => None,
+        };
+
+        let mut manifest = Manifest {
+            size: 0,
+            digests: Vec::new(),
+        };
+        let mut chunk = vec![0; BLOB_CHUNK_SIZE];
+
+        loop {
+            let mut len = 0;
+            while len < BLOB_CHUNK_SIZE {
+                match reader.read(&mut chunk[len..]).await? {
+                    0 => break,
+                    read => len += read,
+                }
+            }
+
+            if len == 0 {
+                break;
+            }
+
+            let index = manifest.digests.len();
+            self.set_kv(&blob::chunk_key(key, index), &chunk[..len])
+                .await?;
+            manifest.digests.push(blob::digest(&chunk[..len]));
+            manifest.size += len as u64;
+
+            if len < BLOB_CHUNK_SIZE {
+                break;
+            }
+        }
+
+        // chunks are written before the manifest so it never refers to
+        // missing chunks
+        self.set_kv(key, &manifest.encode()).await?;
+
+        if let Some(previous) = previous {
+            for index in manifest.digests.len()..previous.digests.len() {
+                self.delete_kv(&blob::chunk_key(key, index)).await?;
+            }
+        }
+
+        Ok(manifest.size)
+    }
+
+    /// Retrieves a large value stored with `put_blob` and writes it to
+    /// `writer`, verifying the integrity of every chunk.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key for which the value is
+    ///   requested.
+    /// * `writer` - The destination of the value.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` which is:
+    ///
+    /// - `Ok(Some(u64))` if the key exists, containing the size of the value.
+    /// - `Ok(None)` if the key does not exist.
+    /// - `Err(e)` if the key does not hold a large value, or a chunk is
+    ///   missing or corrupted.
+    ///
+    pub async fn get_blob<W>(&mut self, key: &[u8], mut writer: W) -> Result<Option<u64>>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let manifest = match self.get_kv(key).await? {
+            Some(value) => Manifest::decode(&value)?,
+            None => return Ok(None),
+        };
+
+        for index in 0..manifest.digests.len() {
+            let chunk = self
+                .get_kv(&blob::chunk_key(key, index))
+                .await?
+                .ok_or(Error::Value(format!("blob chunk {} is missing", index)))?;
+            blob::verify_chunk(&manifest, index, &chunk)?;
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+
+        Ok(Some(manifest.size))
+    }
+
+    /// Deletes a large value stored with `put_blob` and all of its chunks.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key whose associated value
+    ///   is to be deleted.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing whether the key existed.
+    ///
+    pub async fn delete_blob(&mut self, key: &[u8]) -> Result<bool> {
+        let manifest = match self.delete_kv(key).await? {
+            Some(value) => Manifest::decode(&value)?,
+            None => return Ok(false),
+        };
+
+        for index in 0..manifest.digests.len() {
+            self.delete_kv(&blob::chunk_key(key, index)).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Retrieves the ID and leaf set of the node the client is connected to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the node ID and the entries of its leaf set.
+    ///
+    pub async fn get_leaf_set(&mut self) -> Result<(u64, Vec<NodeInfo>)> {
+        let state = self.client.get_node_state(()).await?.into_inner();
+
+        Ok((
+            state.id,
+            state
+                .leaf_set
+                .iter()
+                .map(NodeInfo::from_node_entry)
+                .collect(),
+        ))
+    }
+
+    /// Retrieves the routing table of the node the client is connected to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing every row of the routing table, with `None` for
+    /// empty entries.
+    ///
+    pub async fn get_routing_table(&mut self) -> Result<Vec<Vec<Option<NodeInfo>>>> {
+        let mut table = Vec::with_capacity(U64_HEX_NUM_OF_DIGITS as usize);
+
+        for row in 0..U64_HEX_NUM_OF_DIGITS {
+            let mut entries = Vec::with_capacity(HEX_BASE as usize);
+            for column in 0..HEX_BASE {
+                let node = self
+                    .client
+                    .get_node_table_entry(GetNodeTableEntryRequest { row, column })
+                    .await?
+                    .into_inner()
+                    .node;
+                entries.push(node.as_ref().map(NodeInfo::from_node_entry));
+            }
+            table.push(entries);
+        }
+
+        Ok(table)
+    }
+
+    /// Retrieves the full state of the node the client is connected to: its
+    /// routing table, leaf set, state, uptime, version, store statistics and
+    /// in-flight key transfers.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the node's state.
+    ///
+    pub async fn dump_state(&mut self) -> Result<NodeDump> {
+        Ok(self.client.dump_state(()).await?.into_inner().into())
+    }
+
+    /// Lists the keys stored locally in the node the client is connected to,
+    /// in key order.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_after` - The key to list after, or `None` to start at the
+    ///   first key. Use the `next` key of the previous page to continue.
+    /// * `limit` - The maximum number of keys returned, capped by the node.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a page of keys.
+    ///
+    pub async fn list_keys(&mut self, start_after: Option<&[u8]>, limit: usize) -> Result<KeyPage> {
+        Ok(self
+            .client
+            .list_keys(ListKeysRequest {
+                start_after: start_after.map(<[u8]>::to_vec),
+                limit: limit as u32,
+            })
+            .await?
+            .into_inner()
+            .into())
+    }
 }
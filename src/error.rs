@@ -15,6 +15,8 @@ pub enum Error {
     Config(String),
     Internal(String),
     Parse(String),
+    PermissionDenied(String),
+    Unauthenticated(String),
     Value(String),
 }
 
@@ -23,9 +25,12 @@ impl std::error::Error for Error {}
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Config(s) | Error::Internal(s) | Error::Parse(s) | Error::Value(s) => {
-                write!(f, "{}", s)
-            }
+            Error::Config(s)
+            | Error::Internal(s)
+            | Error::Parse(s)
+            | Error::PermissionDenied(s)
+            | Error::Unauthenticated(s)
+            | Error::Value(s) => write!(f, "{}", s),
             Error::Abort => write!(f, "Operation aborted"),
         }
     }
@@ -93,7 +98,11 @@ impl From<tokio::sync::oneshot::error::RecvError> for Error {
 
 impl From<Error> for tonic::Status {
     fn from(err: Error) -> Self {
-        tonic::Status::internal(err.to_string())
+        match err {
+            Error::PermissionDenied(s) => tonic::Status::permission_denied(s),
+            Error::Unauthenticated(s) => tonic::Status::unauthenticated(s),
+            err => tonic::Status::internal(err.to_string()),
+        }
     }
 }
 
@@ -126,3 +135,21 @@ impl From<SystemTimeError> for Error {
         Error::Internal(err.to_string())
     }
 }
+
+impl From<prometheus::Error> for Error {
+    fn from(err: prometheus::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,120 @@
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+/// Size of each chunk a large value is split into.
+pub const BLOB_CHUNK_SIZE: usize = 256 * 1024;
+
+const MANIFEST_MAGIC: &[u8; 8] = b"PSTRYBLB";
+const MANIFEST_HEADER_SIZE: usize = MANIFEST_MAGIC.len() + 8 + 4;
+const DIGEST_SIZE: usize = 32;
+
+/// Describes a large value stored as a sequence of chunks.
+///
+/// The manifest is stored under the value's key and lists the SHA-256 digest
+/// of every chunk, which are stored under keys derived from it.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub size: u64,
+    pub digests: Vec<[u8; DIGEST_SIZE]>,
+}
+
+impl Manifest {
+    /// Encodes the manifest as: magic, total size, number of chunks and the
+    /// digest of each chunk, all integers big endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MANIFEST_HEADER_SIZE + self.digests.len() * DIGEST_SIZE);
+        bytes.extend_from_slice(MANIFEST_MAGIC);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&(self.digests.len() as u32).to_be_bytes());
+        for digest in &self.digests {
+            bytes.extend_from_slice(digest);
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MANIFEST_HEADER_SIZE || &bytes[..MANIFEST_MAGIC.len()] != MANIFEST_MAGIC {
+            return Err(Error::Parse("value is not a blob manifest".into()));
+        }
+
+        let size = u64::from_be_bytes(bytes[8..16].try_into()?);
+        let count = u32::from_be_bytes(bytes[16..20].try_into()?) as usize;
+
+        let digests = &bytes[MANIFEST_HEADER_SIZE..];
+        if digests.len() != count * DIGEST_SIZE {
+            return Err(Error::Parse("blob manifest is truncated".into()));
+        }
+
+        Ok(Manifest {
+            size,
+            digests: digests
+                .chunks(DIGEST_SIZE)
+                .map(|d| d.try_into())
+                .collect::<std::result::Result<_, _>>()?,
+        })
+    }
+}
+
+/// Gets the SHA-256 digest of a chunk.
+pub fn digest(chunk: &[u8]) -> [u8; DIGEST_SIZE] {
+    Sha256::digest(chunk).into()
+}
+
+/// Gets the key a chunk of a large value is stored under.
+pub fn chunk_key(key: &[u8], index: usize) -> Vec<u8> {
+    let mut chunk_key = key.to_vec();
+    chunk_key.extend_from_slice(b"\0chunk\0");
+    chunk_key.extend_from_slice(&(index as u32).to_be_bytes());
+    chunk_key
+}
+
+/// Checks a chunk against the digest listed in the manifest.
+pub fn verify_chunk(manifest: &Manifest, index: usize, chunk: &[u8]) -> Result<()> {
+    match manifest.digests.get(index) {
+        Some(expected) if *expected == digest(chunk) => Ok(()),
+        Some(_) => Err(Error::Value(format!("blob chunk {} is corrupted", index))),
+        None => Err(Error::Value(format!(
+            "blob chunk {} is not in manifest",
+            index
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_roundtrip() -> Result<()> {
+        let manifest = Manifest {
+            size: 3,
+            digests: vec![digest(b"a"), digest(b"bc")],
+        };
+
+        assert_eq!(Manifest::decode(&manifest.encode())?, manifest);
+        assert!(Manifest::decode(b"not a manifest").is_err());
+        assert!(Manifest::decode(&manifest.encode()[..30]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_chunk() {
+        let manifest = Manifest {
+            size: 1,
+            digests: vec![digest(b"a")],
+        };
+
+        assert!(verify_chunk(&manifest, 0, b"a").is_ok());
+        assert!(verify_chunk(&manifest, 0, b"b").is_err());
+        assert!(verify_chunk(&manifest, 1, b"a").is_err());
+    }
+
+    #[test]
+    fn test_chunk_keys_are_distinct() {
+        assert_ne!(chunk_key(b"key", 0), chunk_key(b"key", 1));
+        assert_ne!(chunk_key(b"key", 0), b"key".to_vec());
+    }
+}
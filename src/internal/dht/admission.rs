@@ -0,0 +1,141 @@
+use super::node::Node;
+use super::service::grpc::NodeCredentials;
+use crate::{error::*, internal::hring::hasher::Sha256Hasher};
+use hmac::{Hmac, Mac};
+use log::warn;
+use sha2::Sha256;
+use std::fmt::{self, Debug};
+
+/// How a node verifies that peers own the IDs they claim when joining the
+/// network or updating its leaf set and routing table. Every node in the
+/// network must use the same policy.
+///
+#[derive(Clone, PartialEq, Default)]
+pub enum Admission {
+    /// Peers are trusted to claim any ID.
+    #[default]
+    Open,
+    /// A peer's ID must be the hash of its public address.
+    Address,
+    /// A peer's ID and public address must be signed with a secret shared by
+    /// every node in the network, so any ID can be bound to a node.
+    Secret(Vec<u8>),
+}
+
+impl Debug for Admission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Admission::Open => write!(f, "Open"),
+            Admission::Address => write!(f, "Address"),
+            Admission::Secret(_) => write!(f, "Secret(..)"),
+        }
+    }
+}
+
+impl Admission {
+    fn mac(secret: &[u8], id: u64, pub_addr: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key size");
+        mac.update(&id.to_be_bytes());
+        mac.update(pub_addr.as_bytes());
+        mac
+    }
+
+    /// Signs a node's ID and public address.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The node's ID.
+    /// * `pub_addr` - The node's public address.
+    ///
+    /// # Returns
+    ///
+    /// The signature, empty unless the policy is `Secret`.
+    ///
+    pub fn sign(&self, id: u64, pub_addr: &str) -> Vec<u8> {
+        match self {
+            Admission::Secret(secret) => Admission::mac(secret, id, pub_addr)
+                .finalize()
+                .into_bytes()
+                .to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Checks that a peer owns the ID it claims.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The peer's ID.
+    /// * `pub_addr` - The peer's public address.
+    /// * `signature` - The signature sent by the peer.
+    ///
+    /// # Returns
+    ///
+    /// Whether the peer is admitted.
+    ///
+    pub fn verify(&self, id: u64, pub_addr: &str, signature: &[u8]) -> bool {
+        match self {
+            Admission::Open => true,
+            Admission::Address => Sha256Hasher::hash_once(pub_addr.as_bytes()) == id,
+            Admission::Secret(secret) => Admission::mac(secret, id, pub_addr)
+                .verify_slice(signature)
+                .is_ok(),
+        }
+    }
+}
+
+impl Node {
+    /// Gets the credentials binding this node's ID to its public address.
+    pub fn credentials(&self) -> NodeCredentials {
+        NodeCredentials {
+            id: self.id,
+            pub_addr: self.pub_addr.clone(),
+            signature: self.config.admission.sign(self.id, &self.pub_addr),
+        }
+    }
+
+    /// Rejects requests from peers that do not own the ID they claim.
+    pub fn verify_peer(&self, id: u64, pub_addr: &str, signature: &[u8]) -> Result<()> {
+        if self.config.admission.verify(id, pub_addr, signature) {
+            return Ok(());
+        }
+
+        warn!(
+            "#{:016X}: Rejected unverified node #{:016X} at {}",
+            self.id, id, pub_addr
+        );
+        Err(Error::PermissionDenied(format!(
+            "node #{:016X} at {} is not admitted",
+            id, pub_addr
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address() {
+        let pub_addr = "http://0.0.0.0:50000";
+        let id = Sha256Hasher::hash_once(pub_addr.as_bytes());
+
+        assert!(Admission::Address.verify(id, pub_addr, &[]));
+        assert!(!Admission::Address.verify(id + 1, pub_addr, &[]));
+        assert!(Admission::Open.verify(id + 1, pub_addr, &[]));
+    }
+
+    #[test]
+    fn test_secret() {
+        let admission = Admission::Secret(b"secret".to_vec());
+        let signature = admission.sign(42, "http://0.0.0.0:50000");
+
+        assert!(admission.verify(42, "http://0.0.0.0:50000", &signature));
+        assert!(!admission.verify(43, "http://0.0.0.0:50000", &signature));
+        assert!(!admission.verify(42, "http://0.0.0.0:50001", &signature));
+        assert!(!admission.verify(42, "http://0.0.0.0:50000", &[]));
+
+        let other = Admission::Secret(b"other".to_vec());
+        assert!(!other.verify(42, "http://0.0.0.0:50000", &signature));
+    }
+}
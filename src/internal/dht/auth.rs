@@ -0,0 +1,367 @@
+use serde::Deserialize;
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    Code, Request, Status,
+};
+
+use super::node::Node;
+use crate::error::*;
+
+pub(crate) const AUTHORIZATION_HEADER: &str = "authorization";
+pub(crate) const BEARER_PREFIX: &str = "Bearer ";
+
+/// What an authenticated sender is allowed to do.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Gets, sets, deletes and watches keys.
+    Client,
+    /// Another node: may also join, transfer keys and update neighbors.
+    Peer,
+    /// An operator: may also inspect the node's state and keys.
+    Admin,
+}
+
+/// The kinds of requests a node serves.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Get, Set and Delete queries, batches of them and watches.
+    Query,
+    /// Join, TransferKeys, AnnounceArrival and FixLeafSet.
+    Peer,
+    /// GetNodeState, GetNodeTableEntry, DumpState and ListKeys.
+    Inspect,
+}
+
+impl Role {
+    /// Checks whether the role is allowed to perform an operation.
+    ///
+    pub fn allows(&self, operation: Operation) -> bool {
+        match self {
+            Role::Peer => true,
+            Role::Client => operation == Operation::Query,
+            Role::Admin => operation != Operation::Peer,
+        }
+    }
+}
+
+/// The authenticated sender of a request.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+    /// The prefix every key accessed by the principal must start with, to
+    /// confine a tenant to its own keys.
+    pub key_prefix: Option<Vec<u8>>,
+}
+
+impl Principal {
+    /// Creates a principal allowed to access every key.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name the principal is logged as.
+    /// * `role` - What the principal is allowed to do.
+    ///
+    /// # Returns
+    ///
+    /// A new Principal.
+    ///
+    pub fn new(name: &str, role: Role) -> Self {
+        Principal {
+            name: name.to_owned(),
+            role,
+            key_prefix: None,
+        }
+    }
+
+    /// Confines the principal to the keys starting with a prefix.
+    ///
+    pub fn with_key_prefix(mut self, prefix: &[u8]) -> Self {
+        self.key_prefix = Some(prefix.to_vec());
+        self
+    }
+}
+
+/// Identifies the sender of a request from its metadata or its TLS
+/// certificates.
+///
+pub trait Authenticator: Debug + Send + Sync {
+    /// Authenticates a request.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the sender, `None` if the request carries no
+    /// credentials, or `Error::Unauthenticated` if its credentials are
+    /// invalid.
+    ///
+    fn authenticate(&self, request: &Request<()>) -> Result<Option<Principal>>;
+}
+
+/// Authenticates requests carrying an `authorization: Bearer <token>`
+/// header.
+///
+#[derive(Debug, Clone, Default)]
+pub struct TokenAuthenticator {
+    tokens: HashMap<String, Principal>,
+}
+
+impl TokenAuthenticator {
+    /// Creates an authenticator that accepts no token.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts a token as the credentials of a principal.
+    ///
+    pub fn with_token(mut self, token: &str, principal: Principal) -> Self {
+        self.tokens.insert(token.to_owned(), principal);
+        self
+    }
+}
+
+impl Authenticator for TokenAuthenticator {
+    fn authenticate(&self, request: &Request<()>) -> Result<Option<Principal>> {
+        let Some(value) = request.metadata().get(AUTHORIZATION_HEADER) else {
+            return Ok(None);
+        };
+
+        value
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+            .and_then(|token| self.tokens.get(token))
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| Error::Unauthenticated("invalid bearer token".into()))
+    }
+}
+
+/// Attaches a bearer token, if any, to every request sent through a
+/// client.
+///
+#[derive(Debug, Clone, Default)]
+pub struct BearerToken {
+    value: Option<MetadataValue<Ascii>>,
+}
+
+impl BearerToken {
+    /// Creates the interceptor for a token, or one that attaches nothing.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the interceptor, or an error if the token is not
+    /// a valid header value.
+    ///
+    pub fn new(token: Option<&str>) -> Result<Self> {
+        let value = match token {
+            Some(token) => Some(
+                format!("{}{}", BEARER_PREFIX, token)
+                    .parse()
+                    .map_err(|_| Error::Config("invalid bearer token".into()))?,
+            ),
+            None => None,
+        };
+
+        Ok(BearerToken { value })
+    }
+}
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        if let Some(value) = &self.value {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_HEADER, value.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Authenticates every request before it reaches its handler, storing the
+/// sender in the request's extensions.
+///
+#[derive(Debug, Clone)]
+pub struct Authenticate {
+    authenticator: Option<Arc<dyn Authenticator>>,
+}
+
+impl Authenticate {
+    pub fn new(authenticator: Option<Arc<dyn Authenticator>>) -> Self {
+        Authenticate { authenticator }
+    }
+}
+
+impl Interceptor for Authenticate {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        if let Some(authenticator) = &self.authenticator {
+            if let Some(principal) = authenticator.authenticate(&request)? {
+                request.extensions_mut().insert(principal);
+            }
+        }
+        Ok(request)
+    }
+}
+
+impl Node {
+    /// Authenticates a request received by a frontend other than gRPC, such
+    /// as the HTTP gateway, by the value of its authorization header.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the request, carrying its sender like the gRPC
+    /// requests authenticated by `Authenticate`.
+    ///
+    pub(crate) fn authenticate<T>(
+        &self,
+        authorization: Option<&str>,
+        message: T,
+    ) -> Result<Request<T>> {
+        let mut request = Request::new(());
+        if let Some(authorization) = authorization {
+            let value: MetadataValue<Ascii> = authorization
+                .parse()
+                .map_err(|_| Error::Unauthenticated("invalid authorization".into()))?;
+            request.metadata_mut().insert(AUTHORIZATION_HEADER, value);
+        }
+
+        let request = Authenticate::new(self.config.authenticator.clone())
+            .call(request)
+            .map_err(|status| match status.code() {
+                Code::PermissionDenied => Error::PermissionDenied(status.message().into()),
+                _ => Error::Unauthenticated(status.message().into()),
+            })?;
+        let (metadata, extensions, _) = request.into_parts();
+        Ok(Request::from_parts(metadata, extensions, message))
+    }
+
+    /// Wraps a message sent by the process running the node, which is
+    /// trusted like an operator without needing a token.
+    ///
+    /// # Returns
+    ///
+    /// A request carrying the local principal.
+    ///
+    pub(crate) fn local_request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .extensions_mut()
+            .insert(Principal::new("local", Role::Admin));
+        request
+    }
+
+    /// Checks that the sender of a request is allowed to perform an
+    /// operation on some keys. Every request is allowed unless an
+    /// authenticator is configured.
+    pub fn authorize<'a, T>(
+        &self,
+        request: &Request<T>,
+        operation: Operation,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<()> {
+        if self.config.authenticator.is_none() {
+            return Ok(());
+        }
+
+        let Some(principal) = request.extensions().get::<Principal>() else {
+            return Err(Error::Unauthenticated(format!(
+                "{:?} requests require authentication",
+                operation
+            )));
+        };
+
+        if !principal.role.allows(operation) {
+            return Err(Error::PermissionDenied(format!(
+                "{} may not send {:?} requests",
+                principal.name, operation
+            )));
+        }
+
+        if let Some(prefix) = &principal.key_prefix {
+            if let Some(key) = keys.into_iter().find(|key| !key.starts_with(prefix)) {
+                return Err(Error::PermissionDenied(format!(
+                    "{} may not access key {:?}",
+                    principal.name,
+                    String::from_utf8_lossy(key)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::pastry::shared::Config;
+
+    #[test]
+    fn test_roles() {
+        assert!(Role::Client.allows(Operation::Query));
+        assert!(!Role::Client.allows(Operation::Peer));
+        assert!(!Role::Client.allows(Operation::Inspect));
+        assert!(Role::Admin.allows(Operation::Inspect));
+        assert!(!Role::Admin.allows(Operation::Peer));
+        assert!(Role::Peer.allows(Operation::Peer));
+    }
+
+    #[test]
+    fn test_token_authenticator() -> Result<()> {
+        let alice = Principal::new("alice", Role::Client).with_key_prefix(b"alice/");
+        let authenticator = TokenAuthenticator::new().with_token("secret", alice.clone());
+
+        let request = |token| {
+            let mut interceptor = BearerToken::new(token)?;
+            Ok::<_, Error>(interceptor.call(Request::new(()))?)
+        };
+
+        assert_eq!(
+            authenticator.authenticate(&request(Some("secret"))?)?,
+            Some(alice)
+        );
+        assert_eq!(authenticator.authenticate(&request(None)?)?, None);
+        assert!(matches!(
+            authenticator.authenticate(&request(Some("guess"))?),
+            Err(Error::Unauthenticated(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_authenticate() -> Result<()> {
+        let alice = Principal::new("alice", Role::Client);
+        let authenticator = TokenAuthenticator::new().with_token("secret", alice.clone());
+        let addr = "0.0.0.0:50000".parse()?;
+        let node = Node::new(
+            Config::new(4).with_authenticator(Arc::new(authenticator)),
+            addr,
+            addr,
+        )?;
+
+        let request = node.authenticate(Some("Bearer secret"), 7)?;
+        assert_eq!(request.extensions().get::<Principal>(), Some(&alice));
+        assert_eq!(request.into_inner(), 7);
+
+        let request = node.authenticate(None, ())?;
+        assert_eq!(request.extensions().get::<Principal>(), None);
+
+        assert!(matches!(
+            node.authenticate(Some("Bearer guess"), ()),
+            Err(Error::Unauthenticated(_))
+        ));
+        assert!(matches!(
+            node.authenticate(Some("Bearer \n"), ()),
+            Err(Error::Unauthenticated(_))
+        ));
+
+        Ok(())
+    }
+}
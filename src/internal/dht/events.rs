@@ -0,0 +1,31 @@
+use tokio::sync::broadcast;
+
+use super::node::{NodeInfo, NodeState};
+
+/// A change in the node's membership, topology or keys.
+#[derive(Debug, Clone)]
+pub enum NodeEvent {
+    /// A node joining the network announced its arrival to this node.
+    NeighborJoined(NodeInfo),
+    /// A leaf set neighbor failed and was removed from the leaf set.
+    NeighborFailed(NodeInfo),
+    /// A routing table entry was added, replaced or removed.
+    RoutingTableChanged {
+        added: Option<NodeInfo>,
+        removed: Option<NodeInfo>,
+    },
+    /// Keys were received from a neighbor while joining next to it.
+    KeysReceived { from: u64, keys: Vec<Vec<u8>> },
+    /// Keys were handed off to a neighbor joining next to this node.
+    KeysHandedOff { to: u64, keys: Vec<Vec<u8>> },
+    /// The node changed its state.
+    StateChanged(NodeState),
+}
+
+/// Events are dropped for subscribers lagging behind by more events.
+pub const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Creates the sender of a node's events.
+pub fn channel() -> broadcast::Sender<NodeEvent> {
+    broadcast::channel(EVENT_CHANNEL_CAPACITY).0
+}
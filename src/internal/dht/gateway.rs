@@ -0,0 +1,438 @@
+use hyper::{
+    body::HttpBody,
+    header::{ALLOW, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Request as HttpRequest, Response, Server, StatusCode,
+};
+use log::info;
+use prost::Message;
+use serde::{Serialize, Serializer};
+use std::{convert::Infallible, future::Future, net::SocketAddr};
+use tonic::{Code, Request, Status};
+
+use super::{
+    node::{Node, MAX_MESSAGE_SIZE},
+    service::grpc::{
+        NodeService, QueryError, QueryRequest, QueryResponse, QueryType, SetCondition, SetOptions,
+        Version,
+    },
+};
+use crate::{admin::NodeDump, error::*, NodeInfo};
+
+const KV_PREFIX: &str = "/kv/";
+const JSON: &str = "application/json";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// An error answered to an HTTP request, as a JSON object with an `error`
+/// message.
+#[derive(Debug)]
+struct HttpError {
+    status: StatusCode,
+    message: String,
+    allow: Option<&'static str>,
+}
+
+impl HttpError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        HttpError {
+            status,
+            message: message.to_string(),
+            allow: None,
+        }
+    }
+
+    fn with_allow(mut self, allowed: &'static str) -> Self {
+        self.allow = Some(allowed);
+        self
+    }
+
+    fn into_response(self) -> Response<Body> {
+        let body = serde_json::json!({ "error": self.message }).to_string();
+        let mut response = Response::builder()
+            .status(self.status)
+            .header(CONTENT_TYPE, JSON);
+        if self.status == StatusCode::UNAUTHORIZED {
+            response = response.header(WWW_AUTHENTICATE, "Bearer");
+        }
+        if let Some(allowed) = self.allow {
+            response = response.header(ALLOW, allowed);
+        }
+        response.body(Body::from(body)).unwrap()
+    }
+}
+
+impl From<Status> for HttpError {
+    fn from(status: Status) -> Self {
+        let code = match status.code() {
+            Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        HttpError::new(code, status.message())
+    }
+}
+
+impl From<Error> for HttpError {
+    fn from(err: Error) -> Self {
+        Status::from(err).into()
+    }
+}
+
+/// Maps the error of a query answered without a value.
+fn query_error(error: Option<i32>) -> HttpError {
+    match error.and_then(|error| QueryError::try_from(error).ok()) {
+        Some(QueryError::ValueNotProvided) => {
+            HttpError::new(StatusCode::BAD_REQUEST, "value not provided")
+        }
+        Some(QueryError::ConditionNotMet) => {
+            HttpError::new(StatusCode::PRECONDITION_FAILED, "condition not met")
+        }
+        Some(QueryError::KeyNotFound) | None => {
+            HttpError::new(StatusCode::NOT_FOUND, "key not found")
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatusBody {
+    #[serde(serialize_with = "serialize_id")]
+    id: u64,
+    pub_addr: String,
+    state: String,
+    uptime_secs: u64,
+    version: String,
+    k: usize,
+    keys: u64,
+    bytes: u64,
+}
+
+#[derive(Serialize)]
+struct LeafSetBody {
+    #[serde(serialize_with = "serialize_id")]
+    id: u64,
+    leaf_set: Vec<NodeBody>,
+}
+
+#[derive(Serialize)]
+struct NodeBody {
+    #[serde(serialize_with = "serialize_id")]
+    id: u64,
+    pub_addr: String,
+}
+
+/// IDs are exported as hexadecimal strings, since JSON consumers may not
+/// handle 64-bit integers.
+fn serialize_id<S: Serializer>(id: &u64, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016X}", id))
+}
+
+/// Binds the HTTP gateway of a node, serving key-value operations and the
+/// node's state as REST endpoints:
+///
+/// - `GET`, `PUT` and `DELETE /kv/{key}`, with the key percent-encoded and
+///   values as raw bytes.
+/// - `GET /status` and `GET /leaf-set`, as JSON.
+///
+/// Requests go through the same handlers as gRPC requests, authenticated by
+/// their `Authorization` header.
+///
+/// # Returns
+///
+/// A Result containing the future serving the gateway.
+///
+pub fn bind_http_gateway(addr: SocketAddr, node: Node) -> Result<impl Future<Output = Result<()>>> {
+    let make_service = make_service_fn(move |_| {
+        let node = node.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let node = node.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        route(&node, req)
+                            .await
+                            .unwrap_or_else(HttpError::into_response),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+
+    Ok(async move { server.await.map_err(Error::from) })
+}
+
+async fn route(
+    node: &Node,
+    req: HttpRequest<Body>,
+) -> std::result::Result<Response<Body>, HttpError> {
+    info!(
+        "#{:016X}: Got HTTP request {} {}",
+        node.id,
+        req.method(),
+        req.uri().path()
+    );
+
+    let path = req.uri().path().to_owned();
+    match (req.method(), path.as_str()) {
+        (&Method::GET, "/status") => {
+            let dump = dump_state(node, req.headers()).await?;
+            json(&StatusBody {
+                id: dump.id,
+                pub_addr: dump.pub_addr,
+                state: format!("{:?}", dump.state),
+                uptime_secs: dump.uptime.as_secs(),
+                version: dump.version,
+                k: dump.k,
+                keys: dump.store.keys,
+                bytes: dump.store.bytes,
+            })
+        }
+        (&Method::GET, "/leaf-set") => {
+            let dump = dump_state(node, req.headers()).await?;
+            json(&LeafSetBody {
+                id: dump.id,
+                leaf_set: dump
+                    .leaf_set
+                    .into_iter()
+                    .map(|entry: NodeInfo| NodeBody {
+                        id: entry.id,
+                        pub_addr: entry.pub_addr,
+                    })
+                    .collect(),
+            })
+        }
+        (_, "/status" | "/leaf-set") => Err(method_not_allowed("GET")),
+        (method, path) if path.starts_with(KV_PREFIX) => {
+            let key = percent_decode(&path[KV_PREFIX.len()..])?;
+            if key.is_empty() {
+                return Err(HttpError::new(StatusCode::BAD_REQUEST, "empty key"));
+            }
+
+            match *method {
+                Method::GET => get(node, req, key).await,
+                Method::PUT => put(node, req, key).await,
+                Method::DELETE => delete(node, req, key).await,
+                _ => Err(method_not_allowed("GET, PUT, DELETE")),
+            }
+        }
+        _ => Err(HttpError::new(
+            StatusCode::NOT_FOUND,
+            format!("no endpoint at {}", path),
+        )),
+    }
+}
+
+async fn get(
+    node: &Node,
+    req: HttpRequest<Body>,
+    key: Vec<u8>,
+) -> std::result::Result<Response<Body>, HttpError> {
+    let request = authenticate(
+        node,
+        req.headers(),
+        QueryRequest::new(QueryType::Get, key, None),
+    )?;
+    let response = node.query(request).await?.into_inner();
+
+    match response.value {
+        Some(value) => Ok(Response::builder()
+            .header(CONTENT_TYPE, OCTET_STREAM)
+            .body(Body::from(value))
+            .unwrap()),
+        None => Err(query_error(response.error)),
+    }
+}
+
+async fn put(
+    node: &Node,
+    req: HttpRequest<Body>,
+    key: Vec<u8>,
+) -> std::result::Result<Response<Body>, HttpError> {
+    let headers = req.headers().clone();
+    let value = read_value(req.into_body(), max_value_size(&key)).await?;
+
+    let query = QueryRequest::new(QueryType::Set, key, Some(value));
+    let response = node
+        .query(authenticate(node, &headers, query)?)
+        .await?
+        .into_inner();
+
+    if response.error.is_some() {
+        return Err(query_error(response.error));
+    }
+    let status = match response.value {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::CREATED,
+    };
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap())
+}
+
+async fn delete(
+    node: &Node,
+    req: HttpRequest<Body>,
+    key: Vec<u8>,
+) -> std::result::Result<Response<Body>, HttpError> {
+    let request = authenticate(
+        node,
+        req.headers(),
+        QueryRequest::new(QueryType::Delete, key, None),
+    )?;
+    let response = node.query(request).await?.into_inner();
+
+    match response.value {
+        Some(_) => Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap()),
+        None => Err(query_error(response.error)),
+    }
+}
+
+async fn dump_state(node: &Node, headers: &HeaderMap) -> std::result::Result<NodeDump, HttpError> {
+    let request = authenticate(node, headers, ())?;
+    Ok(node.dump_state(request).await?.into_inner().into())
+}
+
+/// Authenticates an HTTP request by its `Authorization` header, as the
+/// node's interceptor authenticates gRPC requests.
+fn authenticate<T>(
+    node: &Node,
+    headers: &HeaderMap,
+    message: T,
+) -> std::result::Result<Request<T>, HttpError> {
+    let authorization = headers
+        .get(AUTHORIZATION)
+        .map(|value| value.to_str())
+        .transpose()
+        .map_err(|_| HttpError::new(StatusCode::BAD_REQUEST, "invalid authorization"))?;
+
+    Ok(node.authenticate(authorization, message)?)
+}
+
+fn json(body: &impl Serialize) -> std::result::Result<Response<Body>, HttpError> {
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, JSON)
+        .body(Body::from(
+            serde_json::to_string(body).map_err(Error::from)?,
+        ))
+        .unwrap())
+}
+
+fn method_not_allowed(allowed: &'static str) -> HttpError {
+    HttpError::new(StatusCode::METHOD_NOT_ALLOWED, "method not allowed").with_allow(allowed)
+}
+
+/// Gets the largest value stored under a key whose Set query, and the Get
+/// response returning it, fit in a gRPC message at any hop. Values are
+/// rejected before being routed if they are larger.
+pub(crate) fn max_value_size(key: &[u8]) -> usize {
+    // every other field at its largest encoding, and the value's length
+    // prefix grown from one byte to the five of the largest value
+    let query = QueryRequest {
+        from_id: u64::MAX,
+        matched_digits: u32::MAX,
+        hops: u32::MAX,
+        query_type: QueryType::Set.into(),
+        key: u64::MAX,
+        value: Some(Vec::new()),
+        raw_key: key.to_vec(),
+        options: Some(SetOptions {
+            condition: SetCondition::IfVersion.into(),
+            ttl_ms: Some(u64::MAX),
+            expected_counter: u64::MAX,
+            flags: u32::MAX,
+            expected_node_id: Some(u64::MAX),
+        }),
+    };
+    let response = QueryResponse {
+        from_id: u64::MAX,
+        hops: u32::MAX,
+        key: u64::MAX,
+        value: Some(Vec::new()),
+        error: Some(QueryError::ConditionNotMet.into()),
+        version: Some(Version {
+            counter: u64::MAX,
+            node_id: u64::MAX,
+        }),
+        raw_key: key.to_vec(),
+        flags: u32::MAX,
+    };
+    let envelope = query.encoded_len().max(response.encoded_len()) + 4;
+    MAX_MESSAGE_SIZE.saturating_sub(envelope)
+}
+
+/// Reads a request body, failing as soon as it exceeds `limit` bytes instead
+/// of buffering the rest of it.
+async fn read_value(mut body: Body, limit: usize) -> std::result::Result<Vec<u8>, HttpError> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(value_too_large(limit));
+    }
+
+    let mut value = Vec::with_capacity(body.size_hint().lower() as usize);
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| HttpError::new(StatusCode::BAD_REQUEST, err))?;
+        if value.len() + chunk.len() > limit {
+            return Err(value_too_large(limit));
+        }
+        value.extend_from_slice(&chunk);
+    }
+    Ok(value)
+}
+
+fn value_too_large(limit: usize) -> HttpError {
+    HttpError::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("values under this key are limited to {} bytes", limit),
+    )
+}
+
+/// Decodes a percent-encoded path segment into the raw bytes of a key.
+fn percent_decode(s: &str) -> std::result::Result<Vec<u8>, HttpError> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = s
+                .get(i + 1..i + 3)
+                // from_str_radix would also accept a sign, as in "%+f"
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| {
+                    HttpError::new(StatusCode::BAD_REQUEST, format!("invalid key: {}", s))
+                })?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("plain").unwrap(), b"plain");
+        assert_eq!(percent_decode("a%2Fb%20c").unwrap(), b"a/b c");
+        assert_eq!(percent_decode("%00%ff").unwrap(), vec![0, 255]);
+        assert!(percent_decode("bad%2").is_err());
+        assert!(percent_decode("bad%zz").is_err());
+        assert!(percent_decode("bad%+f").is_err());
+        assert!(percent_decode("bad%-0").is_err());
+    }
+}
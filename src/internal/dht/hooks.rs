@@ -0,0 +1,50 @@
+use std::{fmt, sync::Arc};
+
+use super::store::Entry;
+
+/// A callback receiving the entries of keys that moved in or out of a node.
+pub type KeysCallback = Arc<dyn Fn(&[Entry]) + Send + Sync>;
+
+/// Keeps the callbacks registered by applications embedding the node.
+#[derive(Default)]
+pub struct KeyHooks {
+    received: Vec<KeysCallback>,
+    handed_off: Vec<KeysCallback>,
+}
+
+impl KeyHooks {
+    /// Registers a callback for keys received from a neighbor.
+    pub fn on_received(&mut self, callback: KeysCallback) {
+        self.received.push(callback);
+    }
+
+    /// Registers a callback for keys handed off to a joining neighbor.
+    pub fn on_handed_off(&mut self, callback: KeysCallback) {
+        self.handed_off.push(callback);
+    }
+
+    /// Calls the callbacks for received keys, unless there are none.
+    pub fn received(&self, entries: &[Entry]) {
+        if !entries.is_empty() {
+            self.received.iter().for_each(|callback| callback(entries));
+        }
+    }
+
+    /// Calls the callbacks for handed off keys, unless there are none.
+    pub fn handed_off(&self, entries: &[Entry]) {
+        if !entries.is_empty() {
+            self.handed_off
+                .iter()
+                .for_each(|callback| callback(entries));
+        }
+    }
+}
+
+impl fmt::Debug for KeyHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyHooks")
+            .field("received", &self.received.len())
+            .field("handed_off", &self.handed_off.len())
+            .finish()
+    }
+}
@@ -0,0 +1,402 @@
+use log::warn;
+use std::{future::Future, net::SocketAddr, time::SystemTime};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tonic::{Code, Status};
+
+use super::{
+    node::Node,
+    resp::read_line,
+    service::grpc::{
+        BatchQueryRequest, NodeService, QueryError, QueryRequest, QueryResponse, QueryType,
+        SetCondition, SetOptions,
+    },
+};
+use crate::{error::*, internal::util};
+
+const MAX_KEY_SIZE: usize = 250;
+/// Values are rejected before being routed if they would not fit in a gRPC
+/// message.
+const MAX_VALUE_SIZE: usize = 4 * 1024 * 1024;
+/// Expiration times above this many seconds are Unix timestamps, as in
+/// memcached.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// An error answered to a command, as a whole reply line.
+#[derive(Debug)]
+struct MemcacheError(String);
+
+impl MemcacheError {
+    fn client(message: impl ToString) -> Self {
+        MemcacheError(format!("CLIENT_ERROR {}", message.to_string()))
+    }
+}
+
+impl From<Status> for MemcacheError {
+    fn from(status: Status) -> Self {
+        match status.code() {
+            Code::Unauthenticated | Code::PermissionDenied => {
+                MemcacheError::client(status.message())
+            }
+            _ => MemcacheError(format!("SERVER_ERROR {}", status.message())),
+        }
+    }
+}
+
+impl From<Error> for MemcacheError {
+    fn from(err: Error) -> Self {
+        Status::from(err).into()
+    }
+}
+
+/// Binds a listener for a node speaking the memcached text protocol,
+/// translating `get`, `gets`, `set`, `add`, `replace`, `cas`, `delete` and
+/// `touch` commands into queries. Flags and expiration times are stored with
+/// each entry, and the counter of an entry's version is its CAS value.
+///
+/// The protocol carries no credentials, so commands are only served if no
+/// authenticator is configured.
+///
+/// # Returns
+///
+/// A Result containing the future serving the listener.
+///
+pub fn bind_memcache_server(
+    addr: SocketAddr,
+    node: Node,
+) -> Result<impl Future<Output = Result<()>>> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+
+    Ok(async move {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let node = node.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve_connection(&node, stream).await {
+                    warn!(
+                        "#{:016X}: Memcached connection from {}: {}",
+                        node.id, peer, err
+                    );
+                }
+            });
+        }
+    })
+}
+
+async fn serve_connection(node: &Node, stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    while let Some(line) = read_line(&mut reader).await? {
+        let line = String::from_utf8_lossy(&line).to_string();
+        let args: Vec<&str> = line.split_ascii_whitespace().collect();
+        let Some(&command) = args.first() else {
+            continue;
+        };
+
+        let reply = match command {
+            "quit" => return Ok(()),
+            "set" | "add" | "replace" | "cas" => {
+                let store = match Store::parse(command, &args[1..]) {
+                    Ok(store) => store,
+                    Err(err) => {
+                        // the data block is swallowed as memcached does, so
+                        // that it is not run as commands. Without its length
+                        // there is no telling where it ends.
+                        let bytes = args.get(4).and_then(|bytes| bytes.parse::<u64>().ok());
+                        if let Some(bytes) = bytes {
+                            let mut data = (&mut reader).take(bytes + 2);
+                            tokio::io::copy(&mut data, &mut tokio::io::sink()).await?;
+                        }
+                        writer
+                            .write_all(format!("{}\r\n", err.0).as_bytes())
+                            .await?;
+                        match bytes {
+                            Some(_) => continue,
+                            None => return Err(Error::Parse(err.0)),
+                        }
+                    }
+                };
+
+                let mut data = vec![0; store.bytes + 2];
+                reader.read_exact(&mut data).await?;
+                if !data.ends_with(b"\r\n") {
+                    let err = MemcacheError::client("bad data chunk");
+                    writer
+                        .write_all(format!("{}\r\n", err.0).as_bytes())
+                        .await?;
+                    return Err(Error::Parse("bad data chunk".into()));
+                }
+                data.truncate(store.bytes);
+
+                let noreply = store.noreply;
+                let reply = store.execute(node, data).await;
+                if noreply {
+                    continue;
+                }
+                reply
+            }
+            "get" | "gets" => get(node, &args[1..], command == "gets").await,
+            "delete" => match args.len() {
+                2 | 3 => delete(node, &args[1..]).await,
+                _ => Err(MemcacheError("ERROR".into())),
+            },
+            "touch" => match args.len() {
+                3 | 4 => touch(node, &args[1..]).await,
+                _ => Err(MemcacheError("ERROR".into())),
+            },
+            "version" => Ok(format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes()),
+            _ => Err(MemcacheError("ERROR".into())),
+        };
+
+        let noreply = args.last() == Some(&"noreply")
+            && matches!(command, "delete" | "touch")
+            && reply.is_ok();
+        if noreply {
+            continue;
+        }
+
+        let out = reply.unwrap_or_else(|err| format!("{}\r\n", err.0).into_bytes());
+        writer.write_all(&out).await?;
+    }
+
+    Ok(())
+}
+
+/// A storage command, whose data block follows its line.
+struct Store {
+    command: String,
+    key: Vec<u8>,
+    flags: u32,
+    exptime: i64,
+    bytes: usize,
+    cas: Option<u64>,
+    noreply: bool,
+}
+
+impl Store {
+    /// Parses the arguments of a storage command:
+    /// `<key> <flags> <exptime> <bytes> [<cas unique>] [noreply]`.
+    fn parse(command: &str, args: &[&str]) -> std::result::Result<Self, MemcacheError> {
+        let is_cas = command == "cas";
+        let required = if is_cas { 5 } else { 4 };
+        if args.len() < required || args.len() > required + 1 {
+            return Err(MemcacheError("ERROR".into()));
+        }
+        if args.len() == required + 1 && args[required] != "noreply" {
+            return Err(MemcacheError::client("bad command line format"));
+        }
+
+        let format = || MemcacheError::client("bad command line format");
+        let bytes: usize = args[3].parse().map_err(|_| format())?;
+        if bytes > MAX_VALUE_SIZE {
+            return Err(MemcacheError(
+                "SERVER_ERROR object too large for cache".into(),
+            ));
+        }
+
+        Ok(Store {
+            command: command.to_owned(),
+            key: parse_key(args[0])?,
+            flags: args[1].parse().map_err(|_| format())?,
+            exptime: args[2].parse().map_err(|_| format())?,
+            bytes,
+            cas: match is_cas {
+                true => Some(args[4].parse().map_err(|_| format())?),
+                false => None,
+            },
+            noreply: args.len() == required + 1,
+        })
+    }
+
+    async fn execute(
+        self,
+        node: &Node,
+        data: Vec<u8>,
+    ) -> std::result::Result<Vec<u8>, MemcacheError> {
+        let condition = match self.command.as_str() {
+            "add" => SetCondition::IfAbsent,
+            "replace" => SetCondition::IfPresent,
+            "cas" => SetCondition::IfVersion,
+            _ => SetCondition::Always,
+        };
+
+        let mut query = QueryRequest::new(QueryType::Set, self.key, Some(data));
+        query.options = Some(SetOptions {
+            condition: condition.into(),
+            ttl_ms: ttl_ms(self.exptime)?,
+            expected_counter: self.cas.unwrap_or_default(),
+            flags: self.flags,
+            ..Default::default()
+        });
+        let response = query_one(node, query).await?;
+
+        let reply = match response.error.map(QueryError::try_from) {
+            None => "STORED",
+            Some(Ok(QueryError::ConditionNotMet)) => match (condition, response.value) {
+                (SetCondition::IfVersion, Some(_)) => "EXISTS",
+                (SetCondition::IfVersion, None) => "NOT_FOUND",
+                _ => "NOT_STORED",
+            },
+            Some(_) => return Err(MemcacheError("SERVER_ERROR value not stored".into())),
+        };
+        Ok(format!("{}\r\n", reply).into_bytes())
+    }
+}
+
+async fn get(
+    node: &Node,
+    keys: &[&str],
+    with_cas: bool,
+) -> std::result::Result<Vec<u8>, MemcacheError> {
+    if keys.is_empty() {
+        return Err(MemcacheError("ERROR".into()));
+    }
+
+    let queries = keys
+        .iter()
+        .map(|key| Ok(QueryRequest::new(QueryType::Get, parse_key(key)?, None)))
+        .collect::<std::result::Result<_, MemcacheError>>()?;
+    let request = node.authenticate(
+        None,
+        BatchQueryRequest {
+            from_id: 0,
+            queries,
+        },
+    )?;
+    let responses = node.batch_query(request).await?.into_inner().responses;
+
+    let mut out = Vec::new();
+    for (key, response) in keys.iter().zip(responses) {
+        let Some(value) = response.value else {
+            continue;
+        };
+
+        out.extend_from_slice(
+            format!("VALUE {} {} {}", key, response.flags, value.len()).as_bytes(),
+        );
+        if with_cas {
+            // the unique has no room for the writer's ID, so cas compares
+            // version counters only
+            let counter = response.version.unwrap_or_default().counter;
+            out.extend_from_slice(format!(" {}", counter).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&value);
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"END\r\n");
+
+    Ok(out)
+}
+
+async fn delete(node: &Node, args: &[&str]) -> std::result::Result<Vec<u8>, MemcacheError> {
+    if args.len() == 2 && args[1] != "noreply" {
+        return Err(MemcacheError::client("bad command line format"));
+    }
+
+    let query = QueryRequest::new(QueryType::Delete, parse_key(args[0])?, None);
+    let reply = match query_one(node, query).await?.value {
+        Some(_) => "DELETED\r\n",
+        None => "NOT_FOUND\r\n",
+    };
+    Ok(reply.into())
+}
+
+async fn touch(node: &Node, args: &[&str]) -> std::result::Result<Vec<u8>, MemcacheError> {
+    if args.len() == 3 && args[2] != "noreply" {
+        return Err(MemcacheError::client("bad command line format"));
+    }
+    let exptime: i64 = args[1]
+        .parse()
+        .map_err(|_| MemcacheError::client("invalid exptime argument"))?;
+
+    let mut query = QueryRequest::new(QueryType::Touch, parse_key(args[0])?, None);
+    query.options = Some(SetOptions {
+        ttl_ms: ttl_ms(exptime)?,
+        ..Default::default()
+    });
+    let reply = match query_one(node, query).await?.value {
+        Some(_) => "TOUCHED\r\n",
+        None => "NOT_FOUND\r\n",
+    };
+    Ok(reply.into())
+}
+
+async fn query_one(
+    node: &Node,
+    query: QueryRequest,
+) -> std::result::Result<QueryResponse, MemcacheError> {
+    let request = node.authenticate(None, query)?;
+    Ok(node.query(request).await?.into_inner())
+}
+
+fn parse_key(key: &str) -> std::result::Result<Vec<u8>, MemcacheError> {
+    if key.len() > MAX_KEY_SIZE || key.bytes().any(|b| b.is_ascii_control()) {
+        return Err(MemcacheError::client("bad command line format"));
+    }
+    Ok(key.as_bytes().to_vec())
+}
+
+/// Converts a memcached expiration time into a TTL: `0` never expires,
+/// negative times expire immediately and times above 30 days are Unix
+/// timestamps. Times too large for a TTL in milliseconds are rejected.
+fn ttl_ms(exptime: i64) -> std::result::Result<Option<u64>, MemcacheError> {
+    let millis = |exptime: i64| {
+        (exptime as u64)
+            .checked_mul(1000)
+            .ok_or_else(|| MemcacheError::client("invalid exptime argument"))
+    };
+
+    match exptime {
+        0 => Ok(None),
+        exptime if exptime < 0 => Ok(Some(0)),
+        exptime if exptime <= MAX_RELATIVE_EXPTIME => Ok(Some(millis(exptime)?)),
+        exptime => {
+            let now = util::get_unix_millis(SystemTime::now());
+            Ok(Some(millis(exptime)?.saturating_sub(now)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl() {
+        assert_eq!(ttl_ms(0).ok(), Some(None));
+        assert_eq!(ttl_ms(-1).ok(), Some(Some(0)));
+        assert_eq!(ttl_ms(60).ok(), Some(Some(60_000)));
+
+        let in_an_hour = util::get_unix_millis(SystemTime::now()) / 1000 + 3600;
+        let ttl = ttl_ms(in_an_hour as i64).unwrap().unwrap();
+        assert!(ttl > 3_590_000 && ttl <= 3_600_000);
+        assert_eq!(ttl_ms(MAX_RELATIVE_EXPTIME + 1).ok(), Some(Some(0)));
+        assert!(ttl_ms(9223372036854775807).is_err());
+    }
+
+    #[test]
+    fn test_parse_store() {
+        let store = Store::parse("cas", &["key", "5", "60", "3", "42", "noreply"]).unwrap();
+        assert_eq!(
+            (
+                store.flags,
+                store.exptime,
+                store.bytes,
+                store.cas,
+                store.noreply
+            ),
+            (5, 60, 3, Some(42), true)
+        );
+
+        assert!(Store::parse("set", &["key", "0", "0"]).is_err());
+        assert!(Store::parse("set", &["key", "x", "0", "3"]).is_err());
+        assert!(Store::parse("set", &["key", "0", "0", "3", "later"]).is_err());
+        assert!(Store::parse("set", &[&"k".repeat(251), "0", "0", "3"]).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use prometheus::{
+    core::Collector, exponential_buckets, histogram_opts, labels, opts, Encoder, Histogram,
+    HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge, Registry, TextEncoder,
+};
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc};
+
+use super::node::State;
+use crate::error::*;
+
+/// Prometheus metrics of a node.
+///
+/// Every node keeps its own registry, labelled with the node ID, so that many
+/// nodes can run in the same process.
+///
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+
+    pub queries_served: IntCounter,
+    pub queries_forwarded: IntCounter,
+    pub query_hops: Histogram,
+    pub rpc_duration: HistogramVec,
+    pub leaf_set_repairs: IntCounter,
+    pub routing_table_repairs: IntCounter,
+    pub state_transitions: IntCounterVec,
+    pub store_keys: IntGauge,
+    pub store_bytes: IntGauge,
+    pub transferred_keys: IntCounterVec,
+    pub transferred_bytes: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(id: u64) -> Result<Self> {
+        let registry = Registry::new_custom(
+            Some("pastry".into()),
+            Some(labels! { "node_id".to_owned() => format!("{:016X}", id) }),
+        )?;
+
+        let metrics = Metrics {
+            queries_served: IntCounter::with_opts(opts!(
+                "queries_served_total",
+                "Queries executed on this node's store."
+            ))?,
+            queries_forwarded: IntCounter::with_opts(opts!(
+                "queries_forwarded_total",
+                "Queries forwarded to another node."
+            ))?,
+            query_hops: Histogram::with_opts(histogram_opts!(
+                "query_hops",
+                "Hops taken by queries served by this node.",
+                vec![0.0, 1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0]
+            ))?,
+            rpc_duration: HistogramVec::new(
+                histogram_opts!(
+                    "rpc_duration_seconds",
+                    "Time taken to handle each RPC.",
+                    exponential_buckets(0.0005, 2.0, 14)?
+                ),
+                &["rpc"],
+            )?,
+            leaf_set_repairs: IntCounter::with_opts(opts!(
+                "leaf_set_repairs_total",
+                "Leaf set entries repaired after a node failure."
+            ))?,
+            routing_table_repairs: IntCounter::with_opts(opts!(
+                "routing_table_repairs_total",
+                "Routing table entries repaired after a node failure."
+            ))?,
+            state_transitions: IntCounterVec::new(
+                opts!("state_transitions_total", "Node state transitions."),
+                &["state"],
+            )?,
+            store_keys: IntGauge::with_opts(opts!("store_keys", "Keys in the local store."))?,
+            store_bytes: IntGauge::with_opts(opts!(
+                "store_bytes",
+                "Bytes of keys and values in the local store."
+            ))?,
+            transferred_keys: IntCounterVec::new(
+                opts!("transferred_keys_total", "Keys transferred between nodes."),
+                &["direction"],
+            )?,
+            transferred_bytes: IntCounterVec::new(
+                opts!(
+                    "transferred_bytes_total",
+                    "Bytes transferred between nodes."
+                ),
+                &["direction"],
+            )?,
+            registry,
+        };
+
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(metrics.queries_served.clone()),
+            Box::new(metrics.queries_forwarded.clone()),
+            Box::new(metrics.query_hops.clone()),
+            Box::new(metrics.rpc_duration.clone()),
+            Box::new(metrics.leaf_set_repairs.clone()),
+            Box::new(metrics.routing_table_repairs.clone()),
+            Box::new(metrics.state_transitions.clone()),
+            Box::new(metrics.store_keys.clone()),
+            Box::new(metrics.store_bytes.clone()),
+            Box::new(metrics.transferred_keys.clone()),
+            Box::new(metrics.transferred_bytes.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+
+        Ok(metrics)
+    }
+
+    /// Starts timing an RPC, observing its duration when the timer is dropped.
+    pub fn time_rpc(&self, rpc: &str) -> HistogramTimer {
+        self.rpc_duration.with_label_values(&[rpc]).start_timer()
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Binds the HTTP metrics listener of a node.
+///
+/// # Returns
+///
+/// A Result containing the future serving the metrics on `/metrics`.
+///
+pub fn bind_metrics_server(
+    addr: SocketAddr,
+    state: Arc<State>,
+) -> Result<impl Future<Output = Result<()>>> {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(metrics_response(req.uri().path(), &state).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+
+    Ok(async move { server.await.map_err(Error::from) })
+}
+
+async fn metrics_response(path: &str, state: &State) -> Response<Body> {
+    if path != "/metrics" {
+        return Response::builder().status(404).body(Body::empty()).unwrap();
+    }
+
+    let stats = state.store.read().await.stats();
+    state.metrics.store_keys.set(stats.keys as i64);
+    state.metrics.store_bytes.set(stats.bytes as i64);
+
+    match state.metrics.render() {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(body))
+            .unwrap(),
+        Err(err) => Response::builder()
+            .status(500)
+            .body(Body::from(err.to_string()))
+            .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let metrics = Metrics::new(0xFEDCBA9876543210)?;
+        metrics.queries_served.inc();
+        metrics.query_hops.observe(2.0);
+        metrics
+            .state_transitions
+            .with_label_values(&["RoutingRequests"])
+            .inc();
+
+        let body = metrics.render()?;
+        assert!(body.contains("pastry_queries_served_total{node_id=\"FEDCBA9876543210\"} 1"));
+        assert!(body.contains("pastry_query_hops_count{node_id=\"FEDCBA9876543210\"} 1"));
+        assert!(body.contains(
+            "pastry_state_transitions_total{state=\"RoutingRequests\",node_id=\"FEDCBA9876543210\"} 1"
+        ));
+
+        Ok(())
+    }
+}
@@ -1,4 +1,15 @@
+pub mod admission;
+pub mod auth;
+pub mod events;
+pub mod gateway;
+pub mod hooks;
+pub mod memcache;
+pub mod metrics;
 pub mod node;
+pub mod resp;
 pub mod service;
-mod store;
+pub mod store;
 mod tests;
+pub mod trace;
+pub mod transport;
+pub mod watch;
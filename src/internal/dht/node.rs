@@ -1,22 +1,40 @@
 use log::{debug, info, warn};
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Instant};
 use tokio::{
-    sync::{Notify, RwLock, RwLockWriteGuard},
+    sync::{broadcast, Notify, RwLock, RwLockWriteGuard},
     task::JoinHandle,
 };
-use tonic::transport::{Channel, Server};
+use tonic::{
+    codegen::InterceptedService,
+    transport::{Channel, Endpoint, Server},
+};
 
+use super::auth::{Authenticate, BearerToken};
+use super::events::{self, NodeEvent};
+use super::gateway::bind_http_gateway;
+use super::hooks::KeyHooks;
+use super::memcache::bind_memcache_server;
+use super::metrics::{bind_metrics_server, Metrics};
+use super::resp::bind_resp_server;
 use super::service::grpc::*;
-use super::store::Store;
+use super::store::{Entry, Store, PURGE_INTERVAL};
+use super::trace::traced;
+use super::watch::Watchers;
 
 use crate::{
+    admin::Transfer,
     error::*,
     internal::{
         hring::hasher::Sha256Hasher,
         pastry::{leaf::LeafSet, shared::Config, table::RoutingTable},
+        util,
     },
 };
 
+/// The largest gRPC message nodes and clients decode, tonic's default made
+/// explicit so that frontends can size what they route to fit in it.
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct NodeInfo {
     pub id: u64,
@@ -43,7 +61,31 @@ impl NodeInfo {
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The owner of a key, as found by a lookup.
+#[derive(Debug, Clone)]
+pub struct Lookup {
+    pub owner: NodeInfo,
+    /// The owner's leaf set, without the owner.
+    pub leaf_set: Vec<NodeInfo>,
+    /// Number of nodes the lookup was forwarded through.
+    pub hops: u32,
+}
+
+impl From<LookupResponse> for Lookup {
+    fn from(response: LookupResponse) -> Self {
+        Lookup {
+            owner: NodeInfo::from_node_entry(&response.owner.unwrap_or_default()),
+            leaf_set: response
+                .leaf_set
+                .iter()
+                .map(NodeInfo::from_node_entry)
+                .collect(),
+            hops: response.hops,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum NodeState {
     Uninitialized,
     Initializing,
@@ -57,6 +99,19 @@ pub struct State {
     pub notify: Notify,
     pub data: RwLock<StateData>,
     pub store: RwLock<Store>,
+    pub watchers: RwLock<Watchers>,
+    pub hooks: RwLock<KeyHooks>,
+    pub events: broadcast::Sender<NodeEvent>,
+    pub metrics: Metrics,
+    pub transfers: RwLock<HashMap<u64, Transfer>>,
+    pub started: Instant,
+}
+
+impl State {
+    /// Sends an event to the node's subscribers, if any.
+    pub fn emit(&self, event: NodeEvent) {
+        let _ = self.events.send(event);
+    }
 }
 
 #[derive(Debug)]
@@ -65,11 +120,34 @@ pub struct StateData {
     pub table: RoutingTable<NodeInfo>,
 }
 
+impl StateData {
+    /// Gets the node a request for the key should be forwarded to by the node
+    /// with the given ID, or None if that node owns the key.
+    pub fn next_hop(&self, id: u64, key: u64, min_matched_digits: usize) -> Option<NodeInfo> {
+        if let Some(node) = self.leaf.get(key) {
+            return (node.id != id).then(|| node.clone());
+        }
+
+        if let Ok(Some((node, _))) = self.table.route(key, min_matched_digits) {
+            if node.id != id {
+                return Some(node.clone());
+            }
+        }
+
+        let (node, _) = self.leaf.get_closest(key).ok()?;
+        (node.id != id).then(|| node.clone())
+    }
+}
+
+/// A client to another node, sending the node's peer token.
+pub type NodeClient = NodeServiceClient<InterceptedService<Channel, BearerToken>>;
+
 #[derive(Debug, Clone)]
 pub struct Node {
     pub id: u64,
     pub addr: SocketAddr,
     pub pub_addr: String,
+    pub config: Config,
 
     pub state: Arc<State>,
 }
@@ -98,7 +176,7 @@ impl Node {
     /// A Result containing the newly registered node.
     ///
     pub fn new(config: Config, addr: SocketAddr, pub_addr: SocketAddr) -> Result<Self> {
-        let pub_addr = format!("http://{}:{}", pub_addr.ip(), pub_addr.port());
+        let pub_addr = Node::format_pub_addr(&config, pub_addr);
         let id = Sha256Hasher::hash_once(pub_addr.as_bytes());
 
         info!("#{:016X}: Registered node", id);
@@ -109,6 +187,7 @@ impl Node {
             id,
             addr,
             pub_addr: pub_addr.clone(),
+            config: config.clone(),
 
             state: Arc::new(State {
                 name: RwLock::new(NodeState::Uninitialized),
@@ -117,7 +196,13 @@ impl Node {
                     leaf: LeafSet::new(config.k, id, info.clone())?,
                     table: RoutingTable::new(id, info),
                 }),
-                store: RwLock::new(Store::new()),
+                store: RwLock::new(Store::new(id)),
+                watchers: RwLock::new(Watchers::default()),
+                hooks: RwLock::new(KeyHooks::default()),
+                events: events::channel(),
+                metrics: Metrics::new(id)?,
+                transfers: RwLock::new(HashMap::new()),
+                started: Instant::now(),
             }),
         })
     }
@@ -141,7 +226,7 @@ impl Node {
         pub_addr: SocketAddr,
         id: u64,
     ) -> Result<Self> {
-        let pub_addr = format!("http://{}:{}", pub_addr.ip(), pub_addr.port());
+        let pub_addr = Node::format_pub_addr(&config, pub_addr);
 
         info!("#{:016X}: Registered node", id);
 
@@ -151,6 +236,7 @@ impl Node {
             id,
             addr,
             pub_addr: pub_addr.clone(),
+            config: config.clone(),
 
             state: Arc::new(State {
                 name: RwLock::new(NodeState::Uninitialized),
@@ -159,7 +245,13 @@ impl Node {
                     leaf: LeafSet::new(config.k, id, info.clone())?,
                     table: RoutingTable::new(id, info),
                 }),
-                store: RwLock::new(Store::new()),
+                store: RwLock::new(Store::new(id)),
+                watchers: RwLock::new(Watchers::default()),
+                hooks: RwLock::new(KeyHooks::default()),
+                events: events::channel(),
+                metrics: Metrics::new(id)?,
+                transfers: RwLock::new(HashMap::new()),
+                started: Instant::now(),
             }),
         })
     }
@@ -184,7 +276,8 @@ impl Node {
         let server_handle = self.initialize_server().await?;
 
         if let Some(bootstrap_addr) = bootstrap_addr {
-            self.connect_to_network(bootstrap_addr).await?;
+            self.in_span("join", None, self.connect_to_network(bootstrap_addr))
+                .await?;
         } else {
             info!("#{:016X}: Initializing network", self.id);
         }
@@ -237,50 +330,180 @@ impl Node {
         Ok(())
     }
 
-    /// Initializes gRPC server
+    /// Initializes gRPC server and, if configured, the metrics listener, the
+    /// HTTP gateway, the RESP listener and the memcached listener
     async fn initialize_server(&self) -> Result<JoinHandle<Result<()>>> {
-        let incoming = tonic::transport::server::TcpIncoming::new(self.addr, true, None)?;
+        let mut server = match &self.config.tls {
+            Some(tls) => Server::builder().tls_config(tls.server_config()?)?,
+            None => Server::builder(),
+        };
+
+        let metrics_server = match self.config.metrics_addr {
+            Some(addr) => {
+                info!("#{:016X}: Serving metrics on {}", self.id, addr);
+                Some(bind_metrics_server(addr, self.state.clone())?)
+            }
+            None => None,
+        };
+
+        let http_gateway = match self.config.http_addr {
+            Some(addr) => {
+                info!("#{:016X}: Serving HTTP gateway on {}", self.id, addr);
+                Some(bind_http_gateway(addr, self.clone())?)
+            }
+            None => None,
+        };
+
+        let resp_server = match self.config.resp_addr {
+            Some(addr) => {
+                info!("#{:016X}: Serving RESP on {}", self.id, addr);
+                Some(bind_resp_server(addr, self.clone())?)
+            }
+            None => None,
+        };
+
+        let memcache_server = match self.config.memcache_addr {
+            Some(addr) => {
+                info!("#{:016X}: Serving memcached on {}", self.id, addr);
+                Some(bind_memcache_server(addr, self.clone())?)
+            }
+            None => None,
+        };
+
+        let authenticate = Authenticate::new(self.config.authenticator.clone());
+        let service =
+            NodeServiceServer::new(self.clone()).max_decoding_message_size(MAX_MESSAGE_SIZE);
+        let router = server.add_service(InterceptedService::new(service, authenticate));
+        let grpc_server = self
+            .config
+            .transport
+            .bind(self.addr, &self.pub_addr, router)?;
 
         let node = self.clone();
         Ok(tokio::spawn(async move {
-            Server::builder()
-                .add_service(NodeServiceServer::new(node))
-                .serve_with_incoming(incoming)
-                .await
-                .map_err(|e| Error::from(e))
+            let metrics_server = async {
+                match metrics_server {
+                    Some(metrics_server) => metrics_server.await,
+                    None => std::future::pending().await,
+                }
+            };
+            let http_gateway = async {
+                match http_gateway {
+                    Some(http_gateway) => http_gateway.await,
+                    None => std::future::pending().await,
+                }
+            };
+            let resp_server = async {
+                match resp_server {
+                    Some(resp_server) => resp_server.await,
+                    None => std::future::pending().await,
+                }
+            };
+            let memcache_server = async {
+                match memcache_server {
+                    Some(memcache_server) => memcache_server.await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                res = grpc_server => res,
+                res = node.expire_keys() => res,
+                res = metrics_server => res,
+                res = http_gateway => res,
+                res = resp_server => res,
+                res = memcache_server => res,
+            }
         }))
     }
 
+    /// Purges the expired keys every `PURGE_INTERVAL`, notifying their
+    /// watchers.
+    async fn expire_keys(&self) -> Result<()> {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let expired = self.state.store.write().await.purge_expired();
+            self.notify_expired(expired).await;
+        }
+    }
+
     /// Connects to bootstrap node.
     async fn connect_to_network(&self, bootstrap_addr: &str) -> Result<()> {
         info!("#{:016X}: Connecting to network", self.id);
 
         let mut data = self.state.data.write().await;
 
-        let mut client = Node::connect_with_retry(bootstrap_addr).await?;
+        let mut client = Node::connect_with_retry(bootstrap_addr, &self.config).await?;
         let join_response = client
-            .join(JoinRequest {
+            .join(traced(JoinRequest {
                 id: self.id,
                 pub_addr: self.pub_addr.clone(),
                 hops: 0,
                 matched_digits: 0,
                 routing_table: Vec::new(),
-            })
+                signature: self.config.admission.sign(self.id, &self.pub_addr),
+            }))
             .await?
             .into_inner();
 
-        {
-            let mut client = Node::connect_with_retry(&join_response.pub_addr).await?;
+        self.in_span("transfer", None, async {
+            let mut client =
+                Node::connect_with_retry(&join_response.pub_addr, &self.config).await?;
             let mut stream = client
-                .transfer_keys(TransferKeysRequest { id: self.id })
+                .transfer_keys(traced(TransferKeysRequest { id: self.id }))
                 .await?
                 .into_inner();
             let mut store = self.state.store.write().await;
+            let mut received = Vec::new();
+            let peer_id = join_response.id;
+            self.state.transfers.write().await.insert(
+                peer_id,
+                Transfer {
+                    peer_id,
+                    outgoing: false,
+                    keys: 0,
+                    bytes: 0,
+                },
+            );
 
             while let Some(entry) = stream.message().await? {
-                store.set(&entry.key, &entry.value);
+                let size = (entry.raw_key.len() + entry.value.len()) as u64;
+                let metrics = &self.state.metrics;
+                metrics.transferred_keys.with_label_values(&["in"]).inc();
+                metrics
+                    .transferred_bytes
+                    .with_label_values(&["in"])
+                    .inc_by(size);
+
+                if let Some(transfer) = self.state.transfers.write().await.get_mut(&peer_id) {
+                    transfer.keys += 1;
+                    transfer.bytes += size;
+                }
+
+                let version = entry.version.unwrap_or_default().into();
+                let expires_at = entry.expires_at_ms.map(util::from_unix_millis);
+                let merged = Entry::new(entry.raw_key, entry.value, version)
+                    .with_expiry(expires_at)
+                    .with_flags(entry.flags);
+                if store.merge(&entry.key, merged.clone()) {
+                    received.push(merged);
+                }
             }
-        }
+            self.state.transfers.write().await.remove(&peer_id);
+            drop(store);
+
+            self.state.hooks.read().await.received(&received);
+            if !received.is_empty() {
+                self.state.emit(NodeEvent::KeysReceived {
+                    from: peer_id,
+                    keys: received.into_iter().map(|e| e.key).collect(),
+                });
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await?;
 
         self.update_routing_table(&mut data, &join_response.routing_table)
             .await?;
@@ -297,6 +520,13 @@ impl Node {
         self.state.data.read().await.leaf.get(key).cloned()
     }
 
+    /// Checks whether the node owns a key according to its leaf set.
+    pub async fn is_owner(&self, key: u64) -> bool {
+        self.route_with_leaf_set(key)
+            .await
+            .is_some_and(|node| node.id == self.id)
+    }
+
     pub async fn get_closest_from_leaf_set(&self, key: u64) -> (NodeInfo, usize) {
         self.state
             .data
@@ -323,6 +553,16 @@ impl Node {
             .unwrap()
     }
 
+    /// Gets the node a request for the key should be forwarded to, or None
+    /// if this node owns the key.
+    pub async fn get_next_hop(&self, key: u64, min_matched_digits: usize) -> Option<NodeInfo> {
+        self.state
+            .data
+            .read()
+            .await
+            .next_hop(self.id, key, min_matched_digits)
+    }
+
     pub async fn update_leaf_set<'a, T>(
         &self,
         state_data: &mut RwLockWriteGuard<'_, StateData>,
@@ -352,9 +592,15 @@ impl Node {
     {
         info!("#{:016X}: Updating routing table", self.id);
         for entry in entries.into_iter() {
-            state_data
-                .table
-                .insert(entry.id, NodeInfo::from_node_entry(entry))?;
+            // the table never stores the node itself, skip it so that no
+            // change is reported for it
+            if entry.id == self.id {
+                continue;
+            }
+
+            let added = NodeInfo::from_node_entry(entry);
+            let removed = state_data.table.insert(entry.id, added.clone())?;
+            self.routing_table_changed(Some(added), removed);
         }
         debug!(
             "#{:016X}: Updated routing table: \n{}",
@@ -363,42 +609,104 @@ impl Node {
         Ok(())
     }
 
+    /// Emits an event for a routing table entry change, unless the entry was
+    /// replaced by the same node.
+    pub fn routing_table_changed(&self, added: Option<NodeInfo>, removed: Option<NodeInfo>) {
+        let id = |node: &Option<NodeInfo>| node.as_ref().map(|n| n.id);
+        if id(&added) != id(&removed) {
+            self.state
+                .emit(NodeEvent::RoutingTableChanged { added, removed });
+        }
+    }
+
     /// Changes the Node state and notifies waiters
     pub async fn change_state(&self, next_state: NodeState) {
+        self.state
+            .metrics
+            .state_transitions
+            .with_label_values(&[&format!("{:?}", next_state)])
+            .inc();
+
         let mut state = self.state.name.write().await;
+        if *state != next_state {
+            self.state.emit(NodeEvent::StateChanged(next_state));
+        }
         *state = next_state;
         self.state.notify.notify_waiters();
     }
 
     /// Blocks thread and yields back execution until state is RoutingRequests
     pub async fn block_until_routing_requests(&self) -> () {
-        while *self.state.name.read().await != NodeState::RoutingRequests {
-            self.state.notify.notified().await;
+        loop {
+            // created before checking the state, so a notification sent in
+            // between is not missed
+            let notified = self.state.notify.notified();
+            if *self.state.name.read().await == NodeState::RoutingRequests {
+                return;
+            }
+            notified.await;
         }
     }
 
-    const MAX_CONNECT_RETRIES: usize = 10;
-    const CONNECT_TIMEOUT_SECONDS: u64 = 1;
+    /// Formats the public address of a node, with the `https` scheme if it
+    /// serves over TLS.
+    fn format_pub_addr(config: &Config, pub_addr: SocketAddr) -> String {
+        let scheme = if config.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        format!("{}://{}:{}", scheme, pub_addr.ip(), pub_addr.port())
+    }
+
+    /// Builds the endpoint of a node, with the connection timeout and TLS
+    /// settings of the config.
+    fn endpoint(addr: &str, config: &Config) -> Result<Endpoint> {
+        let endpoint =
+            Endpoint::from_shared(addr.to_owned())?.connect_timeout(config.timeouts.connect);
+
+        Ok(match &config.tls {
+            Some(tls) => endpoint.tls_config(tls.client_config())?,
+            None => endpoint,
+        })
+    }
+
+    /// Connects to another node once and returns a Result containing the
+    /// client
+    pub async fn connect(&self, addr: &str) -> Result<NodeClient> {
+        let endpoint = Node::endpoint(addr, &self.config)?;
+        let channel = self.config.transport.connect(endpoint).await?;
+        Node::client(channel, &self.config)
+    }
+
+    /// Wraps a channel in a client sending the config's peer token.
+    fn client(channel: Channel, config: &Config) -> Result<NodeClient> {
+        let token = BearerToken::new(config.peer_token.as_deref())?;
+        Ok(NodeServiceClient::with_interceptor(channel, token)
+            .max_decoding_message_size(MAX_MESSAGE_SIZE))
+    }
 
     /// Attempts to repeatedly connect to a node and returns a Result containing the client
-    pub async fn connect_with_retry(addr: &str) -> Result<NodeServiceClient<Channel>> {
+    pub async fn connect_with_retry(addr: &str, config: &Config) -> Result<NodeClient> {
+        let timeouts = &config.timeouts;
+        let endpoint = Node::endpoint(addr, config)?;
         let mut retries = 0;
 
         loop {
-            match NodeServiceClient::connect(addr.to_owned()).await {
-                Ok(client) => return Ok(client),
+            match config.transport.connect(endpoint.clone()).await {
+                Ok(channel) => return Node::client(channel, config),
                 Err(err) => {
                     retries += 1;
 
-                    if retries >= Self::MAX_CONNECT_RETRIES {
-                        return Err(err.into());
+                    if retries >= timeouts.max_retries {
+                        return Err(err);
                     }
 
                     warn!(
-                        "Connection failed. Retrying in {} seconds...",
-                        Self::CONNECT_TIMEOUT_SECONDS
+                        "Connection failed. Retrying in {:?}...",
+                        timeouts.retry_interval
                     );
-                    tokio::time::sleep(Duration::from_secs(Self::CONNECT_TIMEOUT_SECONDS)).await;
+                    tokio::time::sleep(timeouts.retry_interval).await;
                 }
             }
         }
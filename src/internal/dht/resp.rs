@@ -0,0 +1,431 @@
+use log::warn;
+use std::{future::Future, net::SocketAddr};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tonic::{Code, Status};
+
+use super::{
+    auth::BEARER_PREFIX,
+    node::Node,
+    service::grpc::{
+        BatchQueryRequest, NodeService, QueryError, QueryRequest, QueryType, SetCondition,
+        SetOptions,
+    },
+};
+use crate::error::*;
+
+/// Values are rejected before being routed if they would not fit in a gRPC
+/// message.
+const MAX_BULK_SIZE: usize = 4 * 1024 * 1024;
+const MAX_ARGS: usize = 64 * 1024;
+const MAX_LINE_SIZE: u64 = 64 * 1024;
+
+/// A reply to a RESP command.
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(s) => out.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            Reply::Error(s) => out.extend_from_slice(format!("-{}\r\n", s).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(value)) => {
+                out.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+                out.extend_from_slice(value);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+        }
+    }
+}
+
+/// An error answered to a command, prefixed by its Redis error code.
+#[derive(Debug)]
+struct RespError(String);
+
+impl RespError {
+    fn new(message: impl ToString) -> Self {
+        RespError(format!("ERR {}", message.to_string()))
+    }
+
+    fn wrong_arity(command: &str) -> Self {
+        RespError::new(format!(
+            "wrong number of arguments for '{}' command",
+            command.to_ascii_lowercase()
+        ))
+    }
+}
+
+impl From<Status> for RespError {
+    fn from(status: Status) -> Self {
+        match status.code() {
+            Code::Unauthenticated => RespError(format!("NOAUTH {}", status.message())),
+            Code::PermissionDenied => RespError(format!("NOPERM {}", status.message())),
+            _ => RespError::new(status.message()),
+        }
+    }
+}
+
+impl From<Error> for RespError {
+    fn from(err: Error) -> Self {
+        Status::from(err).into()
+    }
+}
+
+/// Binds a Redis-compatible listener for a node, translating `GET`, `SET`
+/// (with `EX`, `PX`, `NX` and `XX`), `DEL`, `EXISTS`, `MGET`, `MSET`,
+/// `PING`, `AUTH` and `QUIT` commands into queries.
+///
+/// Commands go through the same handlers as gRPC requests, authenticated by
+/// the token set with `AUTH`.
+///
+/// # Returns
+///
+/// A Result containing the future serving the listener.
+///
+pub fn bind_resp_server(addr: SocketAddr, node: Node) -> Result<impl Future<Output = Result<()>>> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+
+    Ok(async move {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let node = node.clone();
+            tokio::spawn(async move {
+                if let Err(err) = serve_connection(node.clone(), stream).await {
+                    warn!("#{:016X}: RESP connection from {}: {}", node.id, peer, err);
+                }
+            });
+        }
+    })
+}
+
+async fn serve_connection(node: Node, stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut connection = Connection {
+        node,
+        authorization: None,
+    };
+
+    loop {
+        let args = match read_command(&mut reader).await {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                let reply = Reply::Error(format!("ERR Protocol error: {}", err));
+                let mut out = Vec::new();
+                reply.encode(&mut out);
+                writer.write_all(&out).await?;
+                return Err(err);
+            }
+        };
+        if args.is_empty() {
+            continue;
+        }
+
+        let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+        let reply = match quit {
+            true => Reply::Simple("OK"),
+            false => connection.execute(&args).await,
+        };
+
+        let mut out = Vec::new();
+        reply.encode(&mut out);
+        writer.write_all(&out).await?;
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads a command, either as an array of bulk strings or inline as words
+/// separated by spaces.
+///
+/// # Returns
+///
+/// A Result containing the command's arguments, or `None` once the
+/// connection is closed.
+///
+async fn read_command<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+
+    let Some(count) = line.strip_prefix(b"*") else {
+        return Ok(Some(
+            line.split(|b| b.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect(),
+        ));
+    };
+
+    let count = parse_length(count, MAX_ARGS)?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let line = read_line(reader)
+            .await?
+            .ok_or_else(|| Error::Parse("unexpected end of stream".into()))?;
+        let len = line
+            .strip_prefix(b"$")
+            .ok_or_else(|| Error::Parse("expected '$'".into()))?;
+        let len = parse_length(len, MAX_BULK_SIZE)?;
+
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await?;
+        if !arg.ends_with(b"\r\n") {
+            return Err(Error::Parse("expected CRLF after bulk string".into()));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+
+    Ok(Some(args))
+}
+
+/// Reads a line terminated by CRLF, without the terminator.
+pub(super) async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_SIZE)
+        .read_until(b'\n', &mut line)
+        .await?;
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        return Err(Error::Parse("line too long or unterminated".into()));
+    }
+
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_length(s: &[u8], max: usize) -> Result<usize> {
+    std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&len| len <= max)
+        .ok_or_else(|| Error::Parse(format!("invalid length {:?}", String::from_utf8_lossy(s))))
+}
+
+struct Connection {
+    node: Node,
+    authorization: Option<String>,
+}
+
+impl Connection {
+    async fn execute(&mut self, args: &[Vec<u8>]) -> Reply {
+        let command = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let keys = &args[1..];
+
+        let res = match (command.as_str(), keys.len()) {
+            ("PING", 0) => Ok(Reply::Simple("PONG")),
+            ("PING", 1) => Ok(Reply::Bulk(Some(keys[0].clone()))),
+            ("AUTH", 1 | 2) => self.auth(&keys[keys.len() - 1]),
+            ("GET", 1) => self.get(&keys[0]).await,
+            ("SET", n) if n >= 2 => self.set(keys).await,
+            ("DEL", n) if n >= 1 => self.count(QueryType::Delete, keys).await,
+            ("EXISTS", n) if n >= 1 => self.count(QueryType::Get, keys).await,
+            ("MGET", n) if n >= 1 => self.mget(keys).await,
+            ("MSET", n) if n >= 2 && n % 2 == 0 => self.mset(keys).await,
+            ("PING" | "AUTH" | "GET" | "SET" | "DEL" | "EXISTS" | "MGET" | "MSET", _) => {
+                Err(RespError::wrong_arity(&command))
+            }
+            _ => Err(RespError::new(format!(
+                "unknown command '{}'",
+                String::from_utf8_lossy(&args[0])
+            ))),
+        };
+
+        res.unwrap_or_else(|err| Reply::Error(err.0))
+    }
+
+    /// Authenticates the following commands with a token.
+    fn auth(&mut self, token: &[u8]) -> std::result::Result<Reply, RespError> {
+        let authorization = format!("{}{}", BEARER_PREFIX, String::from_utf8_lossy(token));
+        if let Err(err) = self.node.authenticate(Some(&authorization), ()) {
+            return Err(RespError(format!("WRONGPASS {}", err)));
+        }
+
+        self.authorization = Some(authorization);
+        Ok(Reply::Simple("OK"))
+    }
+
+    async fn query(&self, query: QueryRequest) -> std::result::Result<Option<Vec<u8>>, RespError> {
+        let request = self
+            .node
+            .authenticate(self.authorization.as_deref(), query)?;
+        Ok(self.node.query(request).await?.into_inner().value)
+    }
+
+    async fn get(&self, key: &[u8]) -> std::result::Result<Reply, RespError> {
+        let value = self
+            .query(QueryRequest::new(QueryType::Get, key.to_vec(), None))
+            .await?;
+        Ok(Reply::Bulk(value))
+    }
+
+    async fn set(&self, args: &[Vec<u8>]) -> std::result::Result<Reply, RespError> {
+        let mut options = SetOptions::default();
+        let mut rest = args[2..].iter();
+        while let Some(arg) = rest.next() {
+            let unconditional = options.condition == SetCondition::Always as i32;
+            match String::from_utf8_lossy(arg).to_ascii_uppercase().as_str() {
+                "NX" if unconditional => options.condition = SetCondition::IfAbsent.into(),
+                "XX" if unconditional => options.condition = SetCondition::IfPresent.into(),
+                unit @ ("EX" | "PX") if options.ttl_ms.is_none() => {
+                    let ttl = rest.next().ok_or_else(|| RespError::new("syntax error"))?;
+                    let ttl = std::str::from_utf8(ttl)
+                        .ok()
+                        .and_then(|ttl| ttl.parse::<u64>().ok())
+                        .filter(|&ttl| ttl > 0)
+                        .and_then(|ttl| ttl.checked_mul(if unit == "EX" { 1000 } else { 1 }))
+                        .ok_or_else(|| RespError::new("invalid expire time in 'set' command"))?;
+                    options.ttl_ms = Some(ttl);
+                }
+                _ => return Err(RespError::new("syntax error")),
+            }
+        }
+
+        let mut query = QueryRequest::new(QueryType::Set, args[0].clone(), Some(args[1].clone()));
+        let conditional = options.condition != SetCondition::Always as i32;
+        query.options = Some(options);
+
+        let request = self
+            .node
+            .authenticate(self.authorization.as_deref(), query)?;
+        let response = self.node.query(request).await?.into_inner();
+        match response.error {
+            Some(error) if conditional && error == QueryError::ConditionNotMet as i32 => {
+                Ok(Reply::Bulk(None))
+            }
+            Some(_) => Err(RespError::new("value not set")),
+            None => Ok(Reply::Simple("OK")),
+        }
+    }
+
+    /// Counts the keys found by a query of each key, as `DEL` and `EXISTS`
+    /// reply.
+    async fn count(
+        &self,
+        query_type: QueryType,
+        keys: &[Vec<u8>],
+    ) -> std::result::Result<Reply, RespError> {
+        let mut count = 0;
+        for key in keys {
+            let value = self
+                .query(QueryRequest::new(query_type, key.clone(), None))
+                .await?;
+            if value.is_some() {
+                count += 1;
+            }
+        }
+        Ok(Reply::Integer(count))
+    }
+
+    async fn mget(&self, keys: &[Vec<u8>]) -> std::result::Result<Reply, RespError> {
+        let queries = keys
+            .iter()
+            .map(|key| QueryRequest::new(QueryType::Get, key.clone(), None))
+            .collect();
+        let responses = self.batch_query(queries).await?;
+
+        Ok(Reply::Array(
+            responses.into_iter().map(Reply::Bulk).collect(),
+        ))
+    }
+
+    async fn mset(&self, args: &[Vec<u8>]) -> std::result::Result<Reply, RespError> {
+        let queries = args
+            .chunks(2)
+            .map(|pair| QueryRequest::new(QueryType::Set, pair[0].clone(), Some(pair[1].clone())))
+            .collect();
+        self.batch_query(queries).await?;
+
+        Ok(Reply::Simple("OK"))
+    }
+
+    async fn batch_query(
+        &self,
+        queries: Vec<QueryRequest>,
+    ) -> std::result::Result<Vec<Option<Vec<u8>>>, RespError> {
+        let request = self.node.authenticate(
+            self.authorization.as_deref(),
+            BatchQueryRequest {
+                from_id: 0,
+                queries,
+            },
+        )?;
+        let response = self.node.batch_query(request).await?.into_inner();
+
+        Ok(response.responses.into_iter().map(|r| r.value).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(input: &[u8]) -> Result<Option<Vec<Vec<u8>>>> {
+        read_command(&mut BufReader::new(input)).await
+    }
+
+    #[tokio::test]
+    async fn test_read_command() {
+        assert_eq!(
+            parse(b"*2\r\n$3\r\nGET\r\n$4\r\nk\r\ny\r\n").await,
+            Ok(Some(vec![b"GET".to_vec(), b"k\r\ny".to_vec()]))
+        );
+        assert_eq!(
+            parse(b"SET  key value\r\n").await,
+            Ok(Some(vec![
+                b"SET".to_vec(),
+                b"key".to_vec(),
+                b"value".to_vec()
+            ]))
+        );
+        assert_eq!(parse(b"").await, Ok(None));
+        assert!(parse(b"*1\r\n$2\r\nGET\r\n").await.is_err());
+        assert!(parse(b"*1\r\n$-1\r\n").await.is_err());
+        assert!(parse(b"*1\r\n").await.is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut out = Vec::new();
+        Reply::Array(vec![
+            Reply::Bulk(Some(b"v".to_vec())),
+            Reply::Bulk(None),
+            Reply::Integer(2),
+            Reply::Simple("OK"),
+            Reply::Error("ERR syntax error".into()),
+        ])
+        .encode(&mut out);
+        assert_eq!(
+            out,
+            b"*5\r\n$1\r\nv\r\n$-1\r\n:2\r\n+OK\r\n-ERR syntax error\r\n"
+        );
+    }
+}
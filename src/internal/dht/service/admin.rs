@@ -0,0 +1,107 @@
+use tonic::{Response, Status};
+
+use super::super::node::Node;
+use super::grpc::*;
+
+use crate::internal::util::U64_HEX_NUM_OF_DIGITS;
+
+const DEFAULT_LIST_KEYS_LIMIT: usize = 100;
+const MAX_LIST_KEYS_LIMIT: usize = 1000;
+
+impl Node {
+    pub async fn dump_state_service(
+        &self,
+    ) -> std::result::Result<Response<DumpStateResponse>, Status> {
+        let status = NodeStatus::from(*self.state.name.read().await);
+
+        let (leaf_set, routing_table) = {
+            let data = self.state.data.read().await;
+
+            let leaf_set = data
+                .leaf
+                .get_set()
+                .iter()
+                .map(|&e| e.clone().to_node_entry())
+                .collect();
+
+            let mut routing_table = Vec::new();
+            for row in 0..U64_HEX_NUM_OF_DIGITS {
+                let Some(entries) = data.table.get_row(row as usize) else {
+                    break;
+                };
+
+                for (column, node) in entries.into_iter().enumerate() {
+                    if let Some(node) = node {
+                        routing_table.push(RoutingTableEntry {
+                            row,
+                            column: column as u32,
+                            node: Some(node.clone().to_node_entry()),
+                        });
+                    }
+                }
+            }
+
+            (leaf_set, routing_table)
+        };
+
+        let transfers = self
+            .state
+            .transfers
+            .read()
+            .await
+            .values()
+            .cloned()
+            .map(Transfer::from)
+            .collect();
+
+        let store = self.state.store.read().await.stats();
+
+        Ok(Response::new(DumpStateResponse {
+            id: self.id,
+            pub_addr: self.pub_addr.clone(),
+            status: status.into(),
+            uptime_ms: self.state.started.elapsed().as_millis() as u64,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            leaf_set,
+            routing_table,
+            store: Some(store.into()),
+            transfers,
+            k: self.config.k as u32,
+        }))
+    }
+
+    pub async fn list_keys_service(
+        &self,
+        req: &ListKeysRequest,
+    ) -> std::result::Result<Response<ListKeysResponse>, Status> {
+        let limit = match req.limit as usize {
+            0 => DEFAULT_LIST_KEYS_LIMIT,
+            limit => limit.min(MAX_LIST_KEYS_LIMIT),
+        };
+
+        let store = self.state.store.read().await;
+
+        // fetch one more entry than requested to tell whether there is a
+        // next page
+        let mut entries = store.scan(req.start_after.as_deref(), limit + 1);
+        let next_start_after = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|(_, e)| e.key.clone())
+        } else {
+            None
+        };
+
+        Ok(Response::new(ListKeysResponse {
+            keys: entries
+                .into_iter()
+                .map(|(id, e)| StoredKey {
+                    key: id,
+                    raw_key: e.key.clone(),
+                    version: Some(e.version.into()),
+                    value_size: e.value.len() as u64,
+                })
+                .collect(),
+            next_start_after,
+        }))
+    }
+}
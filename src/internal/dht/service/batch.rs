@@ -0,0 +1,138 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use tokio::task::JoinSet;
+use tonic::{Response, Status};
+
+use super::super::{
+    node::Node,
+    trace::{traced, TraceContext},
+};
+use super::grpc::*;
+
+use crate::{
+    error::*,
+    internal::{dht::node::NodeInfo, util},
+};
+
+impl Node {
+    pub async fn batch_query_service(
+        &self,
+        req: &BatchQueryRequest,
+    ) -> std::result::Result<Response<BatchQueryResponse>, Status> {
+        let mut responses: Vec<Option<QueryResponse>> = vec![None; req.queries.len()];
+        let mut groups: HashMap<u64, (NodeInfo, Vec<usize>, Vec<QueryRequest>)> = HashMap::new();
+
+        for (idx, query) in req.queries.iter().enumerate() {
+            let mut request = query.clone();
+            request.from_id = self.id;
+            request.matched_digits = util::get_num_matched_digits(self.id, query.key)?;
+            request.hops += 1;
+
+            match self
+                .get_next_hop(request.key, request.matched_digits as usize)
+                .await
+            {
+                None => responses[idx] = Some(self.query_service(query).await?.into_inner()),
+                Some(node) => {
+                    let group = groups
+                        .entry(node.id)
+                        .or_insert_with(|| (node, Vec::new(), Vec::new()));
+                    group.1.push(idx);
+                    group.2.push(request);
+                }
+            }
+        }
+
+        let mut tasks = JoinSet::new();
+        let parent = TraceContext::current();
+        for (_, (node, indexes, queries)) in groups {
+            let curr_node = self.clone();
+            tasks.spawn(async move {
+                let responses = curr_node
+                    .in_span(
+                        "forward_batch",
+                        parent,
+                        curr_node.forward_batch(&node, queries),
+                    )
+                    .await;
+                (indexes, responses)
+            });
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            let (indexes, group_responses) = result.map_err(Error::from)?;
+            let group_responses = group_responses?;
+            if group_responses.len() != indexes.len() {
+                return Err(Error::Internal(format!(
+                    "sent {} queries but got {} responses",
+                    indexes.len(),
+                    group_responses.len()
+                ))
+                .into());
+            }
+            for (idx, response) in indexes.into_iter().zip(group_responses) {
+                responses[idx] = Some(response);
+            }
+        }
+
+        let responses = responses
+            .into_iter()
+            .enumerate()
+            .map(|(idx, response)| {
+                response.ok_or_else(|| Error::Internal(format!("query {} got no response", idx)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Response::new(BatchQueryResponse { responses }))
+    }
+
+    /// Forwards a group of queries to the next hop as a single batch. If the
+    /// next hop is unreachable each query is routed individually, which
+    /// repairs the failed entry on the way.
+    async fn forward_batch(
+        &self,
+        node: &NodeInfo,
+        queries: Vec<QueryRequest>,
+    ) -> std::result::Result<Vec<QueryResponse>, Status> {
+        info!(
+            "#{:016X}: Forwarding batch of {} queries to #{:016X}",
+            self.id,
+            queries.len(),
+            node.id
+        );
+
+        self.state
+            .metrics
+            .queries_forwarded
+            .inc_by(queries.len() as u64);
+
+        let request = BatchQueryRequest {
+            from_id: self.id,
+            queries: queries.clone(),
+        };
+
+        match self.connect_and_batch_query(node, request).await {
+            Ok(response) => Ok(response.into_inner().responses),
+            Err(err) => {
+                warn!(
+                    "#{:016X}: Batch to #{:016X} failed, routing queries individually: {}",
+                    self.id, node.id, err
+                );
+
+                let mut responses = Vec::with_capacity(queries.len());
+                for query in &queries {
+                    responses.push(self.query_service(query).await?.into_inner());
+                }
+                Ok(responses)
+            }
+        }
+    }
+
+    async fn connect_and_batch_query(
+        &self,
+        node: &NodeInfo,
+        request: BatchQueryRequest,
+    ) -> Result<Response<BatchQueryResponse>> {
+        let mut client = self.connect(&node.pub_addr).await?;
+        Ok(client.batch_query(traced(request)).await?)
+    }
+}
@@ -5,7 +5,11 @@ use super::grpc::*;
 use crate::{
     error::*,
     internal::{
-        dht::node::{Node, NodeInfo, NodeState},
+        dht::{
+            events::NodeEvent,
+            node::{Node, NodeInfo, NodeState},
+            trace::{traced, TraceContext},
+        },
         util::{self, U64_HEX_NUM_OF_DIGITS},
     },
 };
@@ -14,151 +18,196 @@ impl Node {
     /// Attempts to fix a leaf set entry
     pub async fn fix_leaf_entry(&self, node: &NodeInfo) -> Result<()> {
         info!("#{:016X}: Fixing leaf set", self.id);
+        self.state.metrics.leaf_set_repairs.inc();
         self.change_state(NodeState::UpdatingConnections).await;
 
-        let mut data = self.state.data.write().await;
+        // neighbors are contacted without holding the lock and while routing
+        // requests, since they may be fixing their own leaf sets and
+        // contacting this node
+        let nodes_on_the_same_side = {
+            let mut data = self.state.data.write().await;
+
+            if !data.leaf.get_entries().iter().any(|e| e.id == node.id) {
+                // already fixed by a concurrent repair
+                drop(data);
+                self.change_state(NodeState::RoutingRequests).await;
+                return Ok(());
+            }
+
+            if !data.leaf.is_full() {
+                // remove failed entry
+                data.leaf.remove(node.id)?;
 
-        if !data.leaf.is_full() {
-            // remove failed entry
-            data.leaf.remove(node.id).unwrap();
+                // there are not enough nodes to replace entry
+                drop(data);
+                self.state.emit(NodeEvent::NeighborFailed(node.clone()));
+                self.change_state(NodeState::RoutingRequests).await;
+                return Ok(());
+            }
 
-            // there are not enough nodes to replace entry
+            match data.leaf.is_clockwise_neighbor(node.id) {
+                Err(_) => None,
+                Ok(is_clockwise_neighbor) => {
+                    // iterator without failed node
+                    let forward_iterator =
+                        data.leaf.clone().into_iter().filter(|e| e.id != node.id);
+
+                    // remove failed leaf entry
+                    data.leaf.remove(node.id)?;
+
+                    // yield only the ones on the same side as the failed node
+                    Some(if !is_clockwise_neighbor {
+                        forward_iterator.take_while(|e| e.id != self.id).collect()
+                    } else {
+                        forward_iterator
+                            .rev()
+                            .take_while(|e| e.id != self.id)
+                            .collect::<Vec<NodeInfo>>()
+                    })
+                }
+            }
+        };
+        self.change_state(NodeState::RoutingRequests).await;
 
-            self.change_state(NodeState::RoutingRequests).await;
+        // the failed node was not removed
+        let Some(nodes_on_the_same_side) = nodes_on_the_same_side else {
             return Ok(());
-        }
+        };
+        self.state.emit(NodeEvent::NeighborFailed(node.clone()));
+
+        for neighbor in &nodes_on_the_same_side {
+            // check if node is alive
+            let mut client = match self.connect(&neighbor.pub_addr).await {
+                Ok(client) => client,
+                Err(err) => {
+                    warn!(
+                        "#{:016X}: Connection to #{:016X} failed: {}",
+                        self.id, neighbor.id, err
+                    );
+                    continue;
+                }
+            };
+            let state = client.get_node_state(traced(())).await?.into_inner();
 
-        match data.leaf.is_clockwise_neighbor(node.id) {
-            Err(_) => {}
-            Ok(is_clockwise_neighbor) => {
-                // iterator without failed node
-                let forward_iterator = data.leaf.clone().into_iter().filter(|e| e.id != node.id);
-
-                // remove failed leaf entry
-                data.leaf.remove(node.id).unwrap();
-
-                // yield only the ones on the same side as the failed node
-                let nodes_on_the_same_side: Vec<NodeInfo> = if !is_clockwise_neighbor {
-                    forward_iterator.take_while(|e| e.id != self.id).collect()
-                } else {
-                    forward_iterator
-                        .rev()
-                        .take_while(|e| e.id != self.id)
-                        .collect()
-                };
-
-                for neighbor in &nodes_on_the_same_side {
-                    // check if node is alive
-                    let mut client =
-                        match NodeServiceClient::connect(neighbor.pub_addr.to_owned()).await {
-                            Ok(client) => client,
-                            Err(err) => {
-                                warn!(
-                                    "#{:016X}: Connection to #{:016X} failed: {}",
-                                    self.id, neighbor.id, err
-                                );
-                                continue;
-                            }
-                        };
-                    let state = client.get_node_state(()).await?.into_inner();
-
-                    // replace entry
-                    for entry in state.leaf_set {
-                        if entry.id == neighbor.id || entry.id == node.id {
-                            continue;
-                        }
-
-                        // check if entry is alive
-                        if let Err(err) =
-                            NodeServiceClient::connect(entry.pub_addr.to_owned()).await
-                        {
-                            warn!(
-                                "#{:016X}: Connection to #{:016X} failed: {}",
-                                self.id, entry.id, err
-                            );
-                            continue;
-                        }
-
-                        data.leaf
-                            .insert(entry.id, NodeInfo::from_node_entry(&entry))?;
-                    }
-
-                    // break if already fixed leaf set
-                    if data.leaf.is_full() {
-                        break;
-                    }
+            // replace entry
+            for entry in state.leaf_set {
+                if entry.id == neighbor.id || entry.id == node.id {
+                    continue;
                 }
 
-                if !data.leaf.is_full() {
-                    // unable to fix leaf set
-                    panic!(
-                        "#{:016X}: Could not fix leaf set. Too many failed nodes.",
-                        self.id,
+                // check if entry is alive
+                if let Err(err) = self.connect(&entry.pub_addr).await {
+                    warn!(
+                        "#{:016X}: Connection to #{:016X} failed: {}",
+                        self.id, entry.id, err
                     );
+                    continue;
                 }
 
-                debug!("#{:016X}: Fixed leaf set: \n{}", self.id, data.leaf);
+                self.state
+                    .data
+                    .write()
+                    .await
+                    .leaf
+                    .insert(entry.id, NodeInfo::from_node_entry(&entry))?;
+            }
+
+            // break if already fixed leaf set
+            if self.state.data.read().await.leaf.is_full() {
+                break;
             }
         }
 
-        self.change_state(NodeState::RoutingRequests).await;
+        if !nodes_on_the_same_side.is_empty() {
+            let data = self.state.data.read().await;
+            if !data.leaf.is_full() {
+                // unable to fix leaf set
+                return Err(Error::Internal(format!(
+                    "#{:016X}: Could not fix leaf set. Too many failed nodes.",
+                    self.id
+                )));
+            }
+
+            debug!("#{:016X}: Fixed leaf set: \n{}", self.id, data.leaf);
+        }
+
         Ok(())
     }
 
     pub async fn fix_table_entry(&self, node: &NodeInfo) -> Result<()> {
         info!("#{:016X}: Fixing routing table", self.id);
+        self.state.metrics.routing_table_repairs.inc();
         self.change_state(NodeState::UpdatingConnections).await;
 
-        let mut data = self.state.data.write().await;
-
-        // remove node from table
-        data.table.remove(node.id)?;
-
         let matched_digits = util::get_num_matched_digits(self.id, node.id)?;
         let row_index = matched_digits;
         let column_index = util::get_nth_digit_in_u64_hex(node.id, matched_digits as usize + 1)?;
 
-        let mut matched = matched_digits;
-        while let Some(row) = data.table.get_row(matched as usize) {
-            for entry in row.iter().filter_map(|&opt| opt) {
-                let mut client = match NodeServiceClient::connect(entry.pub_addr.to_owned()).await {
-                    Ok(client) => client,
-                    Err(err) => {
-                        warn!(
-                            "#{:016X}: Connection to #{:016X} failed: {}",
-                            self.id, entry.id, err
-                        );
-                        continue;
-                    }
-                };
-
-                let table_entry = client
-                    .get_node_table_entry(GetNodeTableEntryRequest {
-                        row: row_index,
-                        column: column_index,
-                    })
-                    .await?
-                    .into_inner()
-                    .node;
-
-                if let Some(replacement) = table_entry {
-                    if let Ok(_) = NodeServiceClient::connect(replacement.pub_addr.to_owned()).await
-                    {
-                        data.table
-                            .insert(replacement.id, NodeInfo::from_node_entry(&replacement))?;
-                        debug!("#{:016X}: Fixed routing table: \n{}", self.id, data.table);
-                        break;
-                    }
-                }
+        // other nodes are asked for a replacement without holding the lock
+        // and while routing requests, since they may be fixing their own
+        // tables and asking this node
+        let rows: Vec<Vec<NodeInfo>> = {
+            let mut data = self.state.data.write().await;
+
+            if !data
+                .table
+                .get_entries()
+                .iter()
+                .flatten()
+                .any(|e| e.id == node.id)
+            {
+                // already fixed by a concurrent repair
+                drop(data);
+                self.change_state(NodeState::RoutingRequests).await;
+                return Ok(());
             }
 
-            matched += 1;
-            if matched == U64_HEX_NUM_OF_DIGITS {
-                break;
+            // remove node from table
+            let removed = data.table.remove(node.id)?;
+            self.routing_table_changed(None, removed);
+
+            (matched_digits as usize..U64_HEX_NUM_OF_DIGITS as usize)
+                .map_while(|i| data.table.get_row(i))
+                .map(|row| row.into_iter().flatten().cloned().collect())
+                .collect()
+        };
+        self.change_state(NodeState::RoutingRequests).await;
+
+        // the node's own entry fills a column of every row, and asking it
+        // would only return the entry just removed
+        for entry in rows.iter().flatten().filter(|e| e.id != self.id) {
+            let mut client = match self.connect(&entry.pub_addr).await {
+                Ok(client) => client,
+                Err(err) => {
+                    warn!(
+                        "#{:016X}: Connection to #{:016X} failed: {}",
+                        self.id, entry.id, err
+                    );
+                    continue;
+                }
+            };
+
+            let table_entry = client
+                .get_node_table_entry(traced(GetNodeTableEntryRequest {
+                    row: row_index,
+                    column: column_index,
+                }))
+                .await?
+                .into_inner()
+                .node;
+
+            if let Some(replacement) = table_entry {
+                if self.connect(&replacement.pub_addr).await.is_ok() {
+                    let mut data = self.state.data.write().await;
+                    let added = NodeInfo::from_node_entry(&replacement);
+                    let removed = data.table.insert(replacement.id, added.clone())?;
+                    self.routing_table_changed(Some(added), removed);
+                    debug!("#{:016X}: Fixed routing table: \n{}", self.id, data.table);
+                    break;
+                }
             }
         }
 
-        self.change_state(NodeState::RoutingRequests).await;
         Ok(())
     }
 
@@ -167,12 +216,27 @@ impl Node {
             "#{:016X}: Connection to #{:016X} failed: {}",
             self.id, node.id, err
         );
-        let _ = self.fix_leaf_entry(&node).await;
+        if let Err(err) = self
+            .in_span("repair_leaf_set", None, self.fix_leaf_entry(node))
+            .await
+        {
+            warn!("#{:016X}: Leaf set repair failed: {}", self.id, err);
+        }
 
-        // notify neighbors of failed leaf entry
-        for leaf_entry in self.state.data.read().await.leaf.get_entries() {
-            let mut client = match NodeServiceClient::connect(leaf_entry.pub_addr.to_owned()).await
-            {
+        // notify neighbors of failed leaf entry, without holding the lock
+        // while they fix their leaf sets and contact this node
+        let leaf_entries: Vec<NodeInfo> = self
+            .state
+            .data
+            .read()
+            .await
+            .leaf
+            .get_entries()
+            .into_iter()
+            .cloned()
+            .collect();
+        for leaf_entry in &leaf_entries {
+            let mut client = match self.connect(&leaf_entry.pub_addr).await {
                 Ok(client) => client,
                 Err(err) => {
                     warn!(
@@ -184,10 +248,11 @@ impl Node {
             };
 
             let _ = client
-                .fix_leaf_set(FixLeafSetRequest {
+                .fix_leaf_set(traced(FixLeafSetRequest {
                     id: node.id,
                     pub_addr: node.pub_addr.clone(),
-                })
+                    sender: Some(self.credentials()),
+                }))
                 .await;
         }
     }
@@ -200,6 +265,15 @@ impl Node {
 
         let curr_node = self.clone();
         let failed_node = node.clone();
-        tokio::spawn(async move { curr_node.fix_table_entry(&failed_node).await });
+        let parent = TraceContext::current();
+        tokio::spawn(async move {
+            curr_node
+                .in_span(
+                    "repair_routing_table",
+                    parent,
+                    curr_node.fix_table_entry(&failed_node),
+                )
+                .await
+        });
     }
 }
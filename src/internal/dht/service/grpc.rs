@@ -6,6 +6,108 @@ pub use proto::node_service_client::*;
 pub use proto::node_service_server::*;
 pub use proto::*;
 
+use crate::{error::*, internal::hring::hasher::Sha256Hasher};
+
+impl QueryRequest {
+    /// Creates a query for a key, to be routed from this node.
+    ///
+    /// # Arguments
+    ///
+    /// * `query_type` - The type of the query.
+    /// * `key` - The original key, hashed into its ring ID.
+    /// * `value` - The value to set, for Set queries.
+    ///
+    pub fn new(query_type: QueryType, key: Vec<u8>, value: Option<Vec<u8>>) -> Self {
+        QueryRequest {
+            from_id: 0,
+            matched_digits: 0,
+            hops: 0,
+            query_type: query_type.into(),
+            key: Sha256Hasher::hash_once(&key),
+            value,
+            raw_key: key,
+            options: None,
+        }
+    }
+}
+
+impl BatchQueryRequest {
+    /// Creates a batch of gets of many keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The original keys.
+    ///
+    pub fn get(keys: &[&[u8]]) -> Self {
+        BatchQueryRequest {
+            from_id: 0,
+            queries: keys
+                .iter()
+                .map(|key| QueryRequest::new(QueryType::Get, key.to_vec(), None))
+                .collect(),
+        }
+    }
+
+    /// Creates a batch of sets of many keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The original keys and their values.
+    ///
+    pub fn set(entries: &[(&[u8], &[u8])]) -> Self {
+        BatchQueryRequest {
+            from_id: 0,
+            queries: entries
+                .iter()
+                .map(|(key, value)| {
+                    QueryRequest::new(QueryType::Set, key.to_vec(), Some(value.to_vec()))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl BatchQueryResponse {
+    /// Converts the responses into the result of each query, in order.
+    ///
+    /// # Returns
+    ///
+    /// For each query, the value it returned, `None` if its key was not
+    /// found, or the error it failed with.
+    ///
+    pub fn into_results(self) -> Vec<Result<Option<Vec<u8>>>> {
+        self.responses
+            .into_iter()
+            .map(|response| match response.error.map(QueryError::try_from) {
+                None | Some(Ok(QueryError::KeyNotFound)) => Ok(response.value),
+                Some(Ok(error)) => Err(Error::Value(format!(
+                    "Query failed: {}",
+                    error.as_str_name()
+                ))),
+                Some(Err(err)) => Err(Error::Internal(err.to_string())),
+            })
+            .collect()
+    }
+}
+
+impl LookupRequest {
+    /// Creates a lookup of the owner of a key, to be routed from this node.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The original key, hashed into its ring ID.
+    ///
+    pub fn new(key: &[u8]) -> Self {
+        LookupRequest {
+            from_id: 0,
+            matched_digits: 0,
+            hops: 0,
+            key: Sha256Hasher::hash_once(key),
+            raw_key: key.to_vec(),
+        }
+    }
+}
+
 pub struct NodeEntryIterator<'a> {
     node_entry: &'a NodeEntry,
     index: usize,
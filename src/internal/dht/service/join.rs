@@ -3,10 +3,11 @@ use tokio::sync::{mpsc, RwLockWriteGuard};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tonic::{Response, Status};
 
-use super::super::node::Node;
+use super::super::{events::NodeEvent, node::Node, store::Entry, trace::traced};
 use super::grpc::*;
 
 use crate::{
+    admin::Transfer,
     error::*,
     internal::{
         dht::node::{NodeInfo, NodeState, StateData},
@@ -20,6 +21,9 @@ impl Node {
         &self,
         req: &JoinRequest,
     ) -> std::result::Result<Response<JoinResponse>, Status> {
+        self.verify_peer(req.id, &req.pub_addr, &req.signature)?;
+        self.forget_rejoining_node(req.id).await?;
+
         let mut routing_table = req.routing_table.clone();
 
         // Append routing table entries from this node
@@ -92,16 +96,49 @@ impl Node {
         self.join_with_closest_from_leaf_set(&request).await
     }
 
+    /// Removes the entries of a node rejoining under its previous ID if its
+    /// join request would be routed back to it. Nodes that would route the
+    /// request elsewhere keep their entries until the node announces its
+    /// arrival.
+    async fn forget_rejoining_node(&self, id: u64) -> Result<()> {
+        let matched_digits = util::get_num_matched_digits(self.id, id)? as usize;
+        let routes_back = |data: &StateData| {
+            data.next_hop(self.id, id, matched_digits)
+                .is_some_and(|node| node.id == id)
+        };
+        if !routes_back(&*self.state.data.read().await) {
+            return Ok(());
+        }
+
+        // the entries may have changed since the read lock was released
+        let mut data = self.state.data.write().await;
+        if !routes_back(&data) {
+            return Ok(());
+        }
+        if data.leaf.get_entries().iter().any(|e| e.id == id) {
+            data.leaf.remove(id)?;
+        }
+        if data
+            .table
+            .get_entries()
+            .iter()
+            .flatten()
+            .any(|e| e.id == id)
+        {
+            let removed = data.table.remove(id)?;
+            self.routing_table_changed(None, removed);
+        }
+        Ok(())
+    }
+
     // JOIN
     async fn connect_and_join(
         &self,
         node: &NodeInfo,
         request: JoinRequest,
     ) -> Result<Response<JoinResponse>> {
-        match NodeServiceClient::connect(node.pub_addr.to_owned()).await {
-            Ok(mut client) => Ok(client.join(request.clone()).await?),
-            Err(err) => Err(err.into()),
-        }
+        let mut client = self.connect(&node.pub_addr).await?;
+        Ok(client.join(traced(request)).await?)
     }
 
     async fn join_with_leaf_set(
@@ -167,6 +204,8 @@ impl Node {
         &self,
         req: &AnnounceArrivalRequest,
     ) -> std::result::Result<Response<()>, Status> {
+        self.verify_peer(req.id, &req.pub_addr, &req.signature)?;
+
         self.change_state(NodeState::UpdatingConnections).await;
 
         let mut data = self.state.data.write().await;
@@ -184,6 +223,10 @@ impl Node {
 
         self.update_routing_table(&mut data, &node_entry).await?;
 
+        self.state
+            .emit(NodeEvent::NeighborJoined(NodeInfo::from_node_entry(
+                &node_entry,
+            )));
         self.change_state(NodeState::RoutingRequests).await;
 
         Ok(Response::new(()))
@@ -197,6 +240,7 @@ impl Node {
         let announce_arrival_request = AnnounceArrivalRequest {
             id: self.id,
             pub_addr: self.pub_addr.clone(),
+            signature: self.config.admission.sign(self.id, &self.pub_addr),
         };
 
         for entry in state_data.leaf.get_entries() {
@@ -204,9 +248,9 @@ impl Node {
                 continue;
             }
 
-            let mut client = Node::connect_with_retry(&entry.pub_addr).await?;
+            let mut client = Node::connect_with_retry(&entry.pub_addr, &self.config).await?;
             client
-                .announce_arrival(announce_arrival_request.clone())
+                .announce_arrival(traced(announce_arrival_request.clone()))
                 .await?;
         }
 
@@ -216,9 +260,9 @@ impl Node {
                     continue;
                 }
 
-                let mut client = Node::connect_with_retry(&entry.pub_addr).await?;
+                let mut client = Node::connect_with_retry(&entry.pub_addr, &self.config).await?;
                 client
-                    .announce_arrival(announce_arrival_request.clone())
+                    .announce_arrival(traced(announce_arrival_request.clone()))
                     .await?;
             }
         }
@@ -244,19 +288,55 @@ impl Node {
                 .get_entries(|key| !Ring64::is_in_range(prev_id, node_id, key))
                 .await
                 .iter()
-                .map(|e| (e.0.clone(), e.1.clone()))
-                .collect::<Vec<(u64, Vec<u8>)>>();
+                .map(|e| (e.0, e.1.clone()))
+                .collect::<Vec<(u64, Entry)>>();
 
             info!("#{:016X}: Transferring keys to #{:016X}", prev_id, node_id);
 
-            for (key, value) in &entries {
+            // close watches on keys that are no longer owned by this node
+            state
+                .watchers
+                .write()
+                .await
+                .close(|key| !Ring64::is_in_range(prev_id, node_id, key));
+
+            state.transfers.write().await.insert(
+                node_id,
+                Transfer {
+                    peer_id: node_id,
+                    outgoing: true,
+                    keys: 0,
+                    bytes: 0,
+                },
+            );
+
+            let mut handed_off = Vec::new();
+            for (key, entry) in entries {
                 // TODO: implement retry logic
                 match tx.send(Ok(KeyValueEntry {
-                    key: key.clone(),
-                    value: value.clone(),
+                    key,
+                    value: entry.value.clone(),
+                    version: Some(entry.version.into()),
+                    raw_key: entry.key.clone(),
+                    expires_at_ms: entry.expires_at.map(util::get_unix_millis),
+                    flags: entry.flags,
                 })) {
                     Ok(_) => {
-                        store.delete(key);
+                        store.remove(&key, &entry.key);
+
+                        let size = (entry.key.len() + entry.value.len()) as u64;
+                        let metrics = &state.metrics;
+                        metrics.transferred_keys.with_label_values(&["out"]).inc();
+                        metrics
+                            .transferred_bytes
+                            .with_label_values(&["out"])
+                            .inc_by(size);
+
+                        if let Some(transfer) = state.transfers.write().await.get_mut(&node_id) {
+                            transfer.keys += 1;
+                            transfer.bytes += size;
+                        }
+                        handed_off.push(entry);
                     }
                     Err(err) => {
                         warn!(
@@ -266,6 +346,17 @@ impl Node {
                     }
                 };
             }
+
+            state.transfers.write().await.remove(&node_id);
+            drop(store);
+
+            state.hooks.read().await.handed_off(&handed_off);
+            if !handed_off.is_empty() {
+                state.emit(NodeEvent::KeysHandedOff {
+                    to: node_id,
+                    keys: handed_off.into_iter().map(|e| e.key).collect(),
+                });
+            }
         });
 
         Ok(Response::new(UnboundedReceiverStream::new(rx)))
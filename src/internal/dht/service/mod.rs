@@ -1,14 +1,19 @@
+mod admin;
+mod batch;
 mod fail;
 pub mod grpc;
 mod join;
 mod query;
 mod state;
+mod watch;
 
 use log::info;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tonic::{Request, Response, Status};
 
+use super::auth::Operation;
 use super::node::Node;
+use super::trace::TraceContext;
 use grpc::*;
 
 #[tonic::async_trait]
@@ -16,9 +21,11 @@ impl NodeService for Node {
     // INFO
     async fn get_node_state(
         &self,
-        _request: Request<()>,
+        request: Request<()>,
     ) -> std::result::Result<Response<GetNodeStateResponse>, Status> {
         info!("#{:016X}: Got request for get_node_state", self.id);
+        let _timer = self.state.metrics.time_rpc("get_node_state");
+        self.authorize(&request, Operation::Inspect, [])?;
         self.block_until_routing_requests().await;
         self.get_node_state_service().await
     }
@@ -28,18 +35,45 @@ impl NodeService for Node {
         request: Request<GetNodeTableEntryRequest>,
     ) -> std::result::Result<Response<GetNodeTableEntryResponse>, Status> {
         info!("#{:016X}: Got request for get_node_table_entry", self.id);
+        let _timer = self.state.metrics.time_rpc("get_node_table_entry");
+        self.authorize(&request, Operation::Inspect, [])?;
         self.block_until_routing_requests().await;
         self.get_node_table_entry_service(request.get_ref()).await
     }
 
+    // ADMIN
+    async fn dump_state(
+        &self,
+        request: Request<()>,
+    ) -> std::result::Result<Response<DumpStateResponse>, Status> {
+        info!("#{:016X}: Got request for dump_state", self.id);
+        let _timer = self.state.metrics.time_rpc("dump_state");
+        self.authorize(&request, Operation::Inspect, [])?;
+        self.dump_state_service().await
+    }
+
+    async fn list_keys(
+        &self,
+        request: Request<ListKeysRequest>,
+    ) -> std::result::Result<Response<ListKeysResponse>, Status> {
+        info!("#{:016X}: Got request for list_keys", self.id);
+        let _timer = self.state.metrics.time_rpc("list_keys");
+        self.authorize(&request, Operation::Inspect, [])?;
+        self.list_keys_service(request.get_ref()).await
+    }
+
     // MAIN
     async fn join(
         &self,
         request: Request<JoinRequest>,
     ) -> std::result::Result<Response<JoinResponse>, Status> {
         info!("#{:016X}: Got request for join", self.id);
+        let _timer = self.state.metrics.time_rpc("join");
+        self.authorize(&request, Operation::Peer, [])?;
         self.block_until_routing_requests().await;
-        self.join_service(request.get_ref()).await
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span("join", parent, self.join_service(request.get_ref()))
+            .await
     }
 
     async fn query(
@@ -47,8 +81,77 @@ impl NodeService for Node {
         request: Request<QueryRequest>,
     ) -> std::result::Result<Response<QueryResponse>, Status> {
         info!("#{:016X}: Got request for query", self.id);
+        let _timer = self.state.metrics.time_rpc("query");
+        self.authorize(
+            &request,
+            Operation::Query,
+            [request.get_ref().raw_key.as_slice()],
+        )?;
+        self.block_until_routing_requests().await;
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span("query", parent, self.query_service(request.get_ref()))
+            .await
+    }
+
+    async fn batch_query(
+        &self,
+        request: Request<BatchQueryRequest>,
+    ) -> std::result::Result<Response<BatchQueryResponse>, Status> {
+        info!("#{:016X}: Got request for batch_query", self.id);
+        let _timer = self.state.metrics.time_rpc("batch_query");
+        self.authorize(
+            &request,
+            Operation::Query,
+            request
+                .get_ref()
+                .queries
+                .iter()
+                .map(|q| q.raw_key.as_slice()),
+        )?;
+        self.block_until_routing_requests().await;
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span(
+            "batch_query",
+            parent,
+            self.batch_query_service(request.get_ref()),
+        )
+        .await
+    }
+
+    async fn lookup(
+        &self,
+        request: Request<LookupRequest>,
+    ) -> std::result::Result<Response<LookupResponse>, Status> {
+        info!("#{:016X}: Got request for lookup", self.id);
+        let _timer = self.state.metrics.time_rpc("lookup");
+        self.authorize(
+            &request,
+            Operation::Query,
+            [request.get_ref().raw_key.as_slice()],
+        )?;
+        self.block_until_routing_requests().await;
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span("lookup", parent, self.lookup_service(request.get_ref()))
+            .await
+    }
+
+    type WatchStream = UnboundedReceiverStream<std::result::Result<WatchResponse, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> std::result::Result<Response<Self::WatchStream>, Status> {
+        info!("#{:016X}: Got request for watch", self.id);
+        let _timer = self.state.metrics.time_rpc("watch");
+        self.authorize(
+            &request,
+            Operation::Query,
+            [request.get_ref().raw_key.as_slice()],
+        )?;
         self.block_until_routing_requests().await;
-        self.query_service(request.get_ref()).await
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span("watch", parent, self.watch_service(request.get_ref()))
+            .await
     }
 
     type TransferKeysStream = UnboundedReceiverStream<std::result::Result<KeyValueEntry, Status>>;
@@ -58,8 +161,16 @@ impl NodeService for Node {
         request: Request<TransferKeysRequest>,
     ) -> std::result::Result<Response<Self::TransferKeysStream>, Status> {
         info!("#{:016X}: Got request for transfer_keys", self.id);
+        let _timer = self.state.metrics.time_rpc("transfer_keys");
+        self.authorize(&request, Operation::Peer, [])?;
         self.block_until_routing_requests().await;
-        self.transfer_keys_service(request.get_ref()).await
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span(
+            "transfer",
+            parent,
+            self.transfer_keys_service(request.get_ref()),
+        )
+        .await
     }
 
     // UPDATE
@@ -68,8 +179,16 @@ impl NodeService for Node {
         request: Request<AnnounceArrivalRequest>,
     ) -> std::result::Result<Response<()>, Status> {
         info!("#{:016X}: Got request for announce_arrival", self.id);
+        let _timer = self.state.metrics.time_rpc("announce_arrival");
+        self.authorize(&request, Operation::Peer, [])?;
         self.block_until_routing_requests().await;
-        self.announce_arrival_service(request.get_ref()).await
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span(
+            "announce_arrival",
+            parent,
+            self.announce_arrival_service(request.get_ref()),
+        )
+        .await
     }
 
     async fn fix_leaf_set(
@@ -77,7 +196,15 @@ impl NodeService for Node {
         request: Request<FixLeafSetRequest>,
     ) -> std::result::Result<Response<()>, Status> {
         info!("#{:016X}: Got request for fix_leaf_set", self.id);
+        let _timer = self.state.metrics.time_rpc("fix_leaf_set");
+        self.authorize(&request, Operation::Peer, [])?;
         self.block_until_routing_requests().await;
-        self.fix_leaf_set_service(request.get_ref()).await
+        let parent = TraceContext::from_metadata(request.metadata());
+        self.in_span(
+            "repair_leaf_set",
+            parent,
+            self.fix_leaf_set_service(request.get_ref()),
+        )
+        .await
     }
 }
@@ -1,7 +1,15 @@
 use log::{info, warn};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime},
+};
 use tonic::{Response, Status};
 
-use super::super::node::Node;
+use super::super::{
+    node::Node,
+    store::{Entry, Version},
+    trace::traced,
+};
 use super::grpc::*;
 
 use crate::{
@@ -17,32 +25,41 @@ impl Node {
         &self,
         req: &QueryRequest,
     ) -> std::result::Result<Response<QueryResponse>, Status> {
+        // unknown conditions are rejected before routing, rather than
+        // executed as unconditional sets
+        let condition = match &req.options {
+            Some(options) => SetCondition::try_from(options.condition).map_err(|_| {
+                Status::invalid_argument(format!("unknown set condition {}", options.condition))
+            })?,
+            None => SetCondition::Always,
+        };
+
         if let Some(node) = self.route_with_leaf_set(req.key).await {
             if node.id == self.id {
                 // Node is the owner of key
-                return match self.execute_query(req).await {
-                    Ok(value) => Ok(Response::new(QueryResponse {
+                return match self.execute_query(req, condition).await {
+                    Ok((value, version, flags)) => Ok(Response::new(QueryResponse {
                         from_id: self.id,
                         hops: req.hops,
                         key: req.key,
                         value,
                         error: None,
+                        version: Some(version.into()),
+                        raw_key: req.raw_key.clone(),
+                        flags,
                     })),
-                    Err(err) => {
-                        warn!("#{:016X}: Query error: {}", self.id, err);
+                    Err((error, value)) => {
+                        warn!("#{:016X}: Query error: {:?}", self.id, error);
 
                         Ok(Response::new(QueryResponse {
                             from_id: self.id,
                             hops: req.hops,
                             key: req.key,
-                            value: None,
-                            error: Some(
-                                match QueryType::from_i32(req.query_type).unwrap() {
-                                    QueryType::Set => QueryError::ValueNotProvided,
-                                    QueryType::Get | QueryType::Delete => QueryError::KeyNotFound,
-                                }
-                                .into(),
-                            ),
+                            value,
+                            error: Some(error.into()),
+                            version: None,
+                            raw_key: req.raw_key.clone(),
+                            flags: 0,
                         }))
                     }
                 };
@@ -54,67 +71,105 @@ impl Node {
         request.matched_digits = util::get_num_matched_digits(self.id, req.key)?;
         request.hops += 1;
 
-        if let Some(res) = self.query_with_leaf_set(&request).await? {
-            return Ok(res);
-        }
+        self.state.metrics.queries_forwarded.inc();
+
+        self.forward(request.key, request.matched_digits, |node| {
+            let request = request.clone();
+            async move { self.connect_and_query(&node, request).await }
+        })
+        .await
+    }
+
+    pub async fn lookup_service(
+        &self,
+        req: &LookupRequest,
+    ) -> std::result::Result<Response<LookupResponse>, Status> {
+        if self.is_owner(req.key).await {
+            let leaf_set = self
+                .state
+                .data
+                .read()
+                .await
+                .leaf
+                .get_entries()
+                .into_iter()
+                .map(|e| e.clone().to_node_entry())
+                .collect();
 
-        if let Some(res) = self.query_with_routing_table(&request).await? {
-            return Ok(res);
+            return Ok(Response::new(LookupResponse {
+                owner: Some(self.get_info().to_node_entry()),
+                leaf_set,
+                hops: req.hops,
+            }));
         }
 
-        self.query_with_closest_from_leaf_set(&request).await
+        let mut request = req.clone();
+        request.from_id = self.id;
+        request.matched_digits = util::get_num_matched_digits(self.id, req.key)?;
+        request.hops += 1;
+
+        self.forward(request.key, request.matched_digits, |node| {
+            let request = request.clone();
+            async move {
+                let mut client = self.connect(&node.pub_addr).await?;
+                Ok(client.lookup(traced(request)).await?)
+            }
+        })
+        .await
     }
 
-    // QUERY
-    async fn query_with_leaf_set(
+    /// Forwards a request for a key to the next node on its route: the key's
+    /// owner in the leaf set, else a node from the routing table sharing a
+    /// longer prefix with the key, else the closest node in the leaf set.
+    /// Failed nodes are removed from the leaf set or routing table and the
+    /// request is sent to the next candidate.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key being routed.
+    /// * `matched_digits` - The number of digits the key shares with this node.
+    /// * `send` - Sends the request to a node.
+    ///
+    /// # Returns
+    ///
+    /// The response of the first node the request was sent to.
+    ///
+    async fn forward<T, F, Fut>(
         &self,
-        request: &QueryRequest,
-    ) -> std::result::Result<Option<Response<QueryResponse>>, Status> {
-        loop {
-            let node = match self.route_with_leaf_set(request.key).await {
-                Some(node) => node,
-                None => return Ok(None),
-            };
-
-            match self.connect_and_query(&node, request.clone()).await {
-                Ok(r) => break Ok(Some(r)),
+        key: u64,
+        matched_digits: u32,
+        send: F,
+    ) -> std::result::Result<Response<T>, Status>
+    where
+        F: Fn(NodeInfo) -> Fut,
+        Fut: Future<Output = Result<Response<T>>>,
+    {
+        // leaf set
+        while let Some(node) = self.route_with_leaf_set(key).await {
+            match send(node.clone()).await {
+                Ok(r) => return Ok(r),
                 Err(err) => self.warn_and_fix_leaf_entry(&node, &err.to_string()).await,
             }
         }
-    }
 
-    async fn query_with_routing_table(
-        &self,
-        request: &QueryRequest,
-    ) -> std::result::Result<Option<Response<QueryResponse>>, Status> {
-        let (node, _) = match self
-            .route_with_routing_table(request.key, request.matched_digits as usize)
+        // routing table
+        if let Some((node, _)) = self
+            .route_with_routing_table(key, matched_digits as usize)
             .await
         {
-            Some(res) => res,
-            None => return Ok(None),
-        };
-
-        if node.id == self.id {
-            return Ok(None);
-        }
-
-        match self.connect_and_query(&node, request.clone()).await {
-            Ok(r) => return Ok(Some(r)),
-            Err(err) => self.warn_and_fix_table_entry(&node, &err.to_string()).await,
+            if node.id != self.id {
+                match send(node.clone()).await {
+                    Ok(r) => return Ok(r),
+                    Err(err) => self.warn_and_fix_table_entry(&node, &err.to_string()).await,
+                }
+            }
         }
 
-        Ok(None)
-    }
-
-    async fn query_with_closest_from_leaf_set(
-        &self,
-        request: &QueryRequest,
-    ) -> std::result::Result<Response<QueryResponse>, Status> {
+        // closest from leaf set
         loop {
-            let (node, _) = self.get_closest_from_leaf_set(request.key).await;
+            let (node, _) = self.get_closest_from_leaf_set(key).await;
 
-            match self.connect_and_query(&node, request.clone()).await {
+            match send(node.clone()).await {
                 Ok(r) => break Ok(r),
                 Err(err) => self.warn_and_fix_leaf_entry(&node, &err.to_string()).await,
             }
@@ -126,35 +181,153 @@ impl Node {
         node: &NodeInfo,
         request: QueryRequest,
     ) -> Result<Response<QueryResponse>> {
-        match NodeServiceClient::connect(node.pub_addr.to_owned()).await {
-            Ok(mut client) => Ok(client.query(request.clone()).await?),
-            Err(err) => Err(err.into()),
-        }
+        let mut client = self.connect(&node.pub_addr).await?;
+        Ok(client.query(traced(request)).await?)
     }
 
-    pub async fn execute_query(&self, query: &QueryRequest) -> Result<Option<Vec<u8>>> {
-        let key = &query.key;
+    /// Executes a query against the local store.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to execute.
+    /// * `condition` - The condition of a Set query, parsed from its options.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the value read, replaced, deleted or touched by
+    /// the query and the version and flags of the affected entry. For Set
+    /// queries these are the version and flags of the newly written value.
+    /// A failed query returns its error and, for Set queries whose condition
+    /// was not met, the current value.
+    ///
+    pub async fn execute_query(
+        &self,
+        query: &QueryRequest,
+        condition: SetCondition,
+    ) -> std::result::Result<(Option<Vec<u8>>, Version, u32), (QueryError, Option<Vec<u8>>)> {
+        let id = &query.key;
+        let key = &query.raw_key;
         let value = &query.value;
         let query_type = query.query_type;
+        let options = query.options.clone().unwrap_or_default();
+        let expires_at = options
+            .ttl_ms
+            .map(|ttl| SystemTime::now() + Duration::from_millis(ttl));
 
         info!(
             "#{:016X}: Executing query for key {:016X}",
             self.id, query.key
         );
 
+        self.state.metrics.queries_served.inc();
+        self.state.metrics.query_hops.observe(query.hops as f64);
+
         match QueryType::try_from(query_type).unwrap() {
             QueryType::Set => match value {
-                None => Err(Error::Value("Value not provided".into())),
-                Some(value) => Ok(self.state.store.write().await.set(key, value)),
-            },
-            QueryType::Get => match self.state.store.read().await.get(key) {
-                None => Err(Error::Value("Key not present in database".into())),
-                Some(value) => Ok(Some(value.clone())),
+                None => Err((QueryError::ValueNotProvided, None)),
+                Some(value) => {
+                    let mut store = self.state.store.write().await;
+                    let current = store.get(id, key).map(|e| (e.value.clone(), e.version));
+                    let met = match condition {
+                        SetCondition::IfAbsent => current.is_none(),
+                        SetCondition::IfPresent => current.is_some(),
+                        SetCondition::IfVersion => current.as_ref().is_some_and(|(_, version)| {
+                            version.counter == options.expected_counter
+                                && options
+                                    .expected_node_id
+                                    .is_none_or(|node_id| version.node_id == node_id)
+                        }),
+                        SetCondition::Always => true,
+                    };
+                    if !met {
+                        let current = current.map(|(value, _)| value);
+                        return Err((QueryError::ConditionNotMet, current));
+                    }
+
+                    let entry = Entry::new(key.clone(), value.clone(), Version::default())
+                        .with_expiry(expires_at)
+                        .with_flags(options.flags);
+                    let (previous, version) = store.set_entry(id, entry);
+                    let expired = store.take_expired();
+                    drop(store);
+                    self.notify_expired(expired).await;
+                    self.notify_watchers(query, WatchEventType::Updated, Some(value), version)
+                        .await;
+                    Ok((previous.map(|e| e.value), version, options.flags))
+                }
             },
-            QueryType::Delete => match self.state.store.write().await.delete(key) {
-                None => Err(Error::Value("Key not present in database.".into())),
-                Some(value) => Ok(Some(value)),
+            QueryType::Get => {
+                if let Some(entry) = self.state.store.read().await.get(id, key) {
+                    return Ok((Some(entry.value.clone()), entry.version, entry.flags));
+                }
+
+                // the key may have expired since the last purge
+                let mut store = self.state.store.write().await;
+                if store.expire(id, key) {
+                    let expired = store.take_expired();
+                    drop(store);
+                    self.notify_expired(expired).await;
+                }
+                Err((QueryError::KeyNotFound, None))
+            }
+            QueryType::Touch => match self.state.store.write().await.touch(id, key, expires_at) {
+                None => Err((QueryError::KeyNotFound, None)),
+                Some(entry) => Ok((Some(entry.value.clone()), entry.version, entry.flags)),
             },
+            QueryType::Delete => {
+                let mut store = self.state.store.write().await;
+                let deleted = store.delete(id, key);
+                let expired = store.take_expired();
+                drop(store);
+                self.notify_expired(expired).await;
+
+                match deleted {
+                    None => Err((QueryError::KeyNotFound, None)),
+                    Some(entry) => {
+                        self.notify_watchers(query, WatchEventType::Deleted, None, entry.version)
+                            .await;
+                        Ok((Some(entry.value), entry.version, entry.flags))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Notifies the watchers of expired keys, given by their ring ID, key
+    /// and version.
+    pub async fn notify_expired(&self, expired: Vec<(u64, Vec<u8>, Version)>) {
+        if expired.is_empty() {
+            return;
         }
+
+        let mut watchers = self.state.watchers.write().await;
+        for (id, key, version) in expired {
+            watchers.notify(WatchResponse {
+                from_id: self.id,
+                event_type: WatchEventType::Expired.into(),
+                key: id,
+                raw_key: key,
+                value: None,
+                version: Some(version.into()),
+            });
+        }
+    }
+
+    /// Notifies the watchers of the queried key of a change.
+    async fn notify_watchers(
+        &self,
+        query: &QueryRequest,
+        event_type: WatchEventType,
+        value: Option<&Vec<u8>>,
+        version: Version,
+    ) {
+        self.state.watchers.write().await.notify(WatchResponse {
+            from_id: self.id,
+            event_type: event_type.into(),
+            key: query.key,
+            raw_key: query.raw_key.clone(),
+            value: value.cloned(),
+            version: Some(version.into()),
+        });
     }
 }
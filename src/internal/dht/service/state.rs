@@ -40,6 +40,9 @@ impl Node {
         &self,
         req: &FixLeafSetRequest,
     ) -> std::result::Result<Response<()>, Status> {
+        let sender = req.sender.clone().unwrap_or_default();
+        self.verify_peer(sender.id, &sender.pub_addr, &sender.signature)?;
+
         if let None = self.state.data.read().await.leaf.get(req.id) {
             return Ok(Response::new(()));
         }
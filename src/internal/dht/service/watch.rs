@@ -0,0 +1,136 @@
+use log::{info, warn};
+use std::time::Duration;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::{Response, Status};
+
+use super::super::{node::Node, trace::traced};
+use super::grpc::*;
+
+use crate::internal::{dht::node::NodeInfo, util};
+
+const WATCH_RETRY_SECONDS: u64 = 1;
+
+/// The source of the events of a watched key.
+enum Upstream {
+    /// The node owns the key.
+    Local(broadcast::Receiver<WatchResponse>),
+    /// The watch was forwarded to the next node on the key's route.
+    Remote(Box<tonic::Streaming<WatchResponse>>),
+}
+
+impl Upstream {
+    /// Receives the next event, or `None` once the upstream is closed.
+    async fn next(&mut self) -> Option<WatchResponse> {
+        match self {
+            Upstream::Local(events) => loop {
+                match events.recv().await {
+                    Ok(event) => return Some(event),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+            Upstream::Remote(upstream) => upstream.message().await.ok().flatten(),
+        }
+    }
+}
+
+impl Node {
+    pub async fn watch_service(
+        &self,
+        req: &WatchRequest,
+    ) -> std::result::Result<Response<<Node as NodeService>::WatchStream>, Status> {
+        let mut upstream = self.subscribe(req).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let node = self.clone();
+        let req = req.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    event = upstream.next() => match event {
+                        Some(event) => {
+                            if tx.send(Ok(event)).is_err() {
+                                return;
+                            }
+                        }
+                        // the key moved or the next hop closed the watch,
+                        // route it again from this node
+                        None => {
+                            tokio::select! {
+                                _ = tokio::time::sleep(Duration::from_secs(WATCH_RETRY_SECONDS)) => {}
+                                _ = tx.closed() => return,
+                            }
+
+                            match node.subscribe(&req).await {
+                                Ok(next) => upstream = next,
+                                Err(err) => {
+                                    warn!(
+                                        "#{:016X}: Could not re-route watch of key {:016X}: {}",
+                                        node.id, req.key, err
+                                    );
+                                    return;
+                                }
+                            }
+                        }
+                    },
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    /// Subscribes to the events of a key, locally if this node owns the key
+    /// or else through the next node on the key's route.
+    async fn subscribe(&self, req: &WatchRequest) -> std::result::Result<Upstream, Status> {
+        let mut request = req.clone();
+        request.from_id = self.id;
+        request.matched_digits = util::get_num_matched_digits(self.id, req.key)?;
+        request.hops += 1;
+
+        match self
+            .get_next_hop(request.key, request.matched_digits as usize)
+            .await
+        {
+            None => {
+                info!(
+                    "#{:016X}: Watching key {:016X} for #{:016X}",
+                    self.id, req.key, req.from_id
+                );
+
+                Ok(Upstream::Local(
+                    self.state
+                        .watchers
+                        .write()
+                        .await
+                        .subscribe(req.key, &req.raw_key),
+                ))
+            }
+            Some(node) => Ok(Upstream::Remote(Box::new(
+                self.connect_and_watch(&node, request).await?,
+            ))),
+        }
+    }
+
+    async fn connect_and_watch(
+        &self,
+        node: &NodeInfo,
+        request: WatchRequest,
+    ) -> std::result::Result<tonic::Streaming<WatchResponse>, Status> {
+        match self.connect(&node.pub_addr).await {
+            Ok(mut client) => Ok(client.watch(traced(request)).await?.into_inner()),
+            Err(err) => {
+                warn!(
+                    "#{:016X}: Connection to #{:016X} failed: {}",
+                    self.id, node.id, err
+                );
+                Err(Status::unavailable(err.to_string()))
+            }
+        }
+    }
+}
@@ -1,8 +1,21 @@
+use log::warn;
 use std::{
     collections::HashMap,
     hash::{Hash, Hasher},
+    time::{Duration, SystemTime},
 };
 
+use super::service::grpc;
+use crate::admin::StoreStats;
+
+/// How often expired entries are removed from the store. Until then they
+/// are hidden from reads.
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long deleted keys are remembered, so that older copies of them merged
+/// from other nodes are discarded instead of bringing them back.
+const TOMBSTONE_TTL: Duration = Duration::from_secs(3600);
+
 #[derive(Debug, PartialEq, Eq)]
 struct PreHashedKey(u64);
 
@@ -18,42 +31,552 @@ impl From<u64> for PreHashedKey {
     }
 }
 
+/// The version of a stored value.
+///
+/// Versions are Lamport timestamps: `counter` is advanced on every write and
+/// ties are broken by the ID of the node that performed the write, so any two
+/// versions are totally ordered and the greatest one is the newest.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub counter: u64,
+    pub node_id: u64,
+}
+
+impl Version {
+    pub fn new(counter: u64, node_id: u64) -> Self {
+        Version { counter, node_id }
+    }
+}
+
+impl From<grpc::Version> for Version {
+    fn from(version: grpc::Version) -> Self {
+        Version::new(version.counter, version.node_id)
+    }
+}
+
+impl From<Version> for grpc::Version {
+    fn from(version: Version) -> Self {
+        grpc::Version {
+            counter: version.counter,
+            node_id: version.node_id,
+        }
+    }
+}
+
+/// A value stored in the node together with its original key and version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub version: Version,
+    /// When the entry expires, if it was set with a TTL.
+    pub expires_at: Option<SystemTime>,
+    /// Opaque flags stored with the value by clients.
+    pub flags: u32,
+}
+
+impl Entry {
+    pub fn new(key: Vec<u8>, value: Vec<u8>, version: Version) -> Self {
+        Entry {
+            key,
+            value,
+            version,
+            expires_at: None,
+            flags: 0,
+        }
+    }
+
+    pub fn with_expiry(mut self, expires_at: Option<SystemTime>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// The version a key was deleted with and when it is forgotten.
+#[derive(Debug)]
+struct Tombstone {
+    version: Version,
+    expires_at: SystemTime,
+}
+
+/// The node's local key-value store.
+///
+/// Entries are indexed by their ring ID and kept apart by their original key,
+/// so keys whose hashes collide do not overwrite each other. Deleted keys
+/// leave a tombstone for `TOMBSTONE_TTL`, which is not transferred with the
+/// keys when a neighbor joins.
+///
 #[derive(Debug)]
 pub struct Store {
-    store: HashMap<PreHashedKey, Vec<u8>>,
+    id: u64,
+    clock: u64,
+    store: HashMap<PreHashedKey, Vec<Entry>>,
+    tombstones: HashMap<(u64, Vec<u8>), Tombstone>,
+    next_purge: SystemTime,
+    /// The ring ID, key and version of the entries removed because they
+    /// expired, until they are returned by `purge_expired`.
+    expired: Vec<(u64, Vec<u8>, Version)>,
 }
 
 impl Store {
-    pub fn new() -> Self {
+    pub fn new(id: u64) -> Self {
         Store {
+            id,
+            clock: 0,
             store: HashMap::new(),
+            tombstones: HashMap::new(),
+            next_purge: SystemTime::UNIX_EPOCH,
+            expired: Vec::new(),
         }
     }
 
-    pub fn get(&self, key: &u64) -> Option<&Vec<u8>> {
-        self.store.get(&key.clone().into())
+    /// Gets the entry for a key, unless it expired.
+    pub fn get(&self, id: &u64, key: &[u8]) -> Option<&Entry> {
+        let now = SystemTime::now();
+        self.store
+            .get(&(*id).into())
+            .and_then(|bucket| bucket.iter().find(|e| e.key == key))
+            .filter(|e| !e.is_expired(now))
     }
 
-    pub fn set(&mut self, key: &u64, value: &[u8]) -> Option<Vec<u8>> {
-        self.store.insert(key.clone().into(), value.to_vec())
+    /// Sets the value for a key, stamping it with a version newer than both
+    /// the node's clock and the version it replaces.
+    ///
+    /// # Returns
+    ///
+    /// The previous entry, if any, and the version of the new one.
+    ///
+    #[cfg(test)]
+    pub fn set(&mut self, id: &u64, key: &[u8], value: &[u8]) -> (Option<Entry>, Version) {
+        let entry = Entry::new(key.to_vec(), value.to_vec(), Version::default());
+        self.set_entry(id, entry)
     }
 
-    pub fn delete(&mut self, key: &u64) -> Option<Vec<u8>> {
-        self.store.remove(&key.clone().into())
+    /// Stores an entry, keeping its expiry and flags and stamping it with a
+    /// version newer than both the node's clock and the version it replaces.
+    ///
+    /// # Returns
+    ///
+    /// The previous entry, if any and not expired, and the version of the
+    /// new one.
+    ///
+    pub fn set_entry(&mut self, id: &u64, mut entry: Entry) -> (Option<Entry>, Version) {
+        let now = SystemTime::now();
+        self.purge_if_due(now);
+
+        let previous = self.get(id, &entry.key).map(|e| e.version.counter);
+        let deleted = self
+            .tombstones
+            .remove(&(*id, entry.key.clone()))
+            .map(|t| t.version.counter);
+        self.clock = self.clock.max(previous.max(deleted).unwrap_or(0)) + 1;
+
+        entry.version = Version::new(self.clock, self.id);
+        let version = entry.version;
+        let previous = self.insert(*id, entry);
+
+        (self.unless_expired(*id, previous, now), version)
     }
 
-    pub fn list(&self) -> Vec<(u64, &Vec<u8>)> {
-        self.store.iter().map(|e| (e.0 .0, e.1)).collect()
+    /// Changes when the entry for a key expires, keeping its value and
+    /// version.
+    ///
+    /// # Returns
+    ///
+    /// The touched entry, unless there was none or it expired.
+    ///
+    pub fn touch(
+        &mut self,
+        id: &u64,
+        key: &[u8],
+        expires_at: Option<SystemTime>,
+    ) -> Option<&Entry> {
+        let now = SystemTime::now();
+        let entry = self
+            .store
+            .get_mut(&(*id).into())?
+            .iter_mut()
+            .find(|e| e.key == key && !e.is_expired(now))?;
+
+        entry.expires_at = expires_at;
+        Some(entry)
     }
 
-    pub async fn get_entries<F>(&self, f: F) -> Vec<(u64, &Vec<u8>)>
-    where
-        F: Fn(u64) -> bool,
-    {
+    /// Deletes the entry for a key, leaving a tombstone newer than the
+    /// deleted version.
+    ///
+    /// # Returns
+    ///
+    /// The deleted entry, unless there was none or it expired.
+    ///
+    pub fn delete(&mut self, id: &u64, key: &[u8]) -> Option<Entry> {
+        let entry = self.remove(id, key)?;
+
+        self.clock = self.clock.max(entry.version.counter) + 1;
+        self.tombstones.insert(
+            (*id, key.to_vec()),
+            Tombstone {
+                version: Version::new(self.clock, self.id),
+                expires_at: SystemTime::now() + TOMBSTONE_TTL,
+            },
+        );
+        Some(entry)
+    }
+
+    /// Removes the entry for a key without leaving a tombstone, as when the
+    /// key is handed off to another node.
+    ///
+    /// # Returns
+    ///
+    /// The removed entry, unless there was none or it expired.
+    ///
+    pub fn remove(&mut self, id: &u64, key: &[u8]) -> Option<Entry> {
+        let now = SystemTime::now();
+        self.purge_if_due(now);
+
+        let bucket = self.store.get_mut(&(*id).into())?;
+        let position = bucket.iter().position(|e| e.key == key)?;
+        let entry = bucket.swap_remove(position);
+
+        if bucket.is_empty() {
+            self.store.remove(&(*id).into());
+        }
+
+        self.unless_expired(*id, Some(entry), now)
+    }
+
+    /// Merges an entry received from another node, keeping whichever of the
+    /// local and incoming entries has the newest version. Entries older than
+    /// the key's tombstone are discarded.
+    ///
+    /// # Returns
+    ///
+    /// True if the incoming entry was stored.
+    ///
+    pub fn merge(&mut self, id: &u64, entry: Entry) -> bool {
+        self.clock = self.clock.max(entry.version.counter);
+        if entry.is_expired(SystemTime::now()) {
+            return false;
+        }
+
+        let tombstone = (*id, entry.key.clone());
+        match self.tombstones.get(&tombstone) {
+            Some(t) if t.version >= entry.version => return false,
+            Some(_) => {
+                self.tombstones.remove(&tombstone);
+            }
+            None => {}
+        }
+
+        match self.get(id, &entry.key) {
+            Some(current) if current.version >= entry.version => false,
+            _ => {
+                let previous = self.insert(*id, entry);
+                self.unless_expired(*id, previous, SystemTime::now());
+                true
+            }
+        }
+    }
+
+    /// Lists the entries that did not expire.
+    pub fn list(&self) -> Vec<(u64, &Entry)> {
+        let now = SystemTime::now();
         self.store
             .iter()
-            .filter(|(&ref key, _)| f(key.0))
-            .map(|e| (e.0 .0, e.1))
+            .flat_map(|(id, bucket)| bucket.iter().map(|e| (id.0, e)))
+            .filter(|(_, e)| !e.is_expired(now))
             .collect()
     }
+
+    /// Counts the entries and the bytes of their keys and values.
+    pub fn stats(&self) -> StoreStats {
+        self.list()
+            .iter()
+            .fold(StoreStats::default(), |stats, (_, e)| StoreStats {
+                keys: stats.keys + 1,
+                bytes: stats.bytes + (e.key.len() + e.value.len()) as u64,
+            })
+    }
+
+    /// Scans entries in key order, starting after `from` (or at the first key)
+    /// and returning at most `limit` entries.
+    ///
+    pub fn scan(&self, from: Option<&[u8]>, limit: usize) -> Vec<(u64, &Entry)> {
+        let mut entries: Vec<(u64, &Entry)> = self
+            .list()
+            .into_iter()
+            .filter(|(_, e)| from.is_none_or(|from| e.key.as_slice() > from))
+            .collect();
+        entries.sort_by(|a, b| a.1.key.cmp(&b.1.key));
+        entries.truncate(limit);
+        entries
+    }
+
+    pub async fn get_entries<F>(&self, f: F) -> Vec<(u64, &Entry)>
+    where
+        F: Fn(u64) -> bool,
+    {
+        self.list().into_iter().filter(|(id, _)| f(*id)).collect()
+    }
+
+    /// Removes the expired entries and tombstones.
+    ///
+    /// # Returns
+    ///
+    /// The ring ID, key and version of every entry removed because it
+    /// expired, including those removed by writes since the last purge.
+    ///
+    pub fn purge_expired(&mut self) -> Vec<(u64, Vec<u8>, Version)> {
+        let now = SystemTime::now();
+        self.next_purge = now + PURGE_INTERVAL;
+
+        self.tombstones.retain(|_, t| t.expires_at > now);
+        let expired = &mut self.expired;
+        self.store.retain(|id, bucket| {
+            bucket.retain(|e| {
+                if e.is_expired(now) {
+                    expired.push((id.0, e.key.clone(), e.version));
+                }
+                !e.is_expired(now)
+            });
+            !bucket.is_empty()
+        });
+
+        self.take_expired()
+    }
+
+    /// Returns the entries removed by writes because they expired since the
+    /// last purge, without purging the store.
+    pub fn take_expired(&mut self) -> Vec<(u64, Vec<u8>, Version)> {
+        std::mem::take(&mut self.expired)
+    }
+
+    /// Removes the entry for a key if it expired, as when a read finds it
+    /// expired, keeping it for `take_expired`.
+    ///
+    /// # Returns
+    ///
+    /// True if the entry expired and was removed.
+    ///
+    pub fn expire(&mut self, id: &u64, key: &[u8]) -> bool {
+        let now = SystemTime::now();
+        let expired = self
+            .store
+            .get(&(*id).into())
+            .is_some_and(|bucket| bucket.iter().any(|e| e.key == key && e.is_expired(now)));
+
+        expired && self.remove(id, key).is_none()
+    }
+
+    /// Removes the expired entries and tombstones, at most once every
+    /// `PURGE_INTERVAL`, keeping the expired entries for `purge_expired`.
+    fn purge_if_due(&mut self, now: SystemTime) {
+        if now >= self.next_purge {
+            let expired = self.purge_expired();
+            self.expired = expired;
+        }
+    }
+
+    /// Returns an entry taken out of the store, unless it expired, in which
+    /// case it is kept for `purge_expired`.
+    fn unless_expired(&mut self, id: u64, entry: Option<Entry>, now: SystemTime) -> Option<Entry> {
+        match entry {
+            Some(e) if e.is_expired(now) => {
+                self.expired.push((id, e.key, e.version));
+                None
+            }
+            entry => entry,
+        }
+    }
+
+    /// Inserts an entry into its ring ID bucket, replacing the entry with the
+    /// same key.
+    fn insert(&mut self, id: u64, entry: Entry) -> Option<Entry> {
+        let bucket = self.store.entry(id.into()).or_default();
+
+        match bucket.iter_mut().find(|e| e.key == entry.key) {
+            Some(current) => Some(std::mem::replace(current, entry)),
+            None => {
+                if !bucket.is_empty() {
+                    warn!("Hash collision on ring id {:016X}", id);
+                }
+                bucket.push(entry);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_advances_version() {
+        let mut store = Store::new(1);
+
+        let (previous, first) = store.set(&10, b"k", b"a");
+        assert_eq!(previous, None);
+        assert_eq!(first, Version::new(1, 1));
+
+        let (previous, second) = store.set(&10, b"k", b"b");
+        assert_eq!(
+            previous,
+            Some(Entry::new(b"k".to_vec(), b"a".to_vec(), first))
+        );
+        assert!(second > first);
+
+        let (_, other) = store.set(&20, b"l", b"c");
+        assert!(other > second);
+    }
+
+    #[test]
+    fn test_merge_keeps_newest() {
+        let mut store = Store::new(1);
+        let (_, local) = store.set(&10, b"k", b"local");
+
+        // older version is discarded
+        let old = Entry::new(b"k".to_vec(), b"old".to_vec(), Version::new(0, 2));
+        assert!(!store.merge(&10, old));
+        assert_eq!(store.get(&10, b"k").unwrap().value, b"local".to_vec());
+
+        // same counter from a node with higher id wins the tie
+        let tie = Version::new(local.counter, 2);
+        assert!(store.merge(&10, Entry::new(b"k".to_vec(), b"tie".to_vec(), tie)));
+        assert_eq!(store.get(&10, b"k").unwrap().version, tie);
+
+        // clock catches up with merged versions
+        let new = Entry::new(b"l".to_vec(), b"new".to_vec(), Version::new(41, 3));
+        assert!(store.merge(&20, new));
+        let (_, version) = store.set(&30, b"m", b"after");
+        assert_eq!(version, Version::new(42, 1));
+    }
+
+    #[test]
+    fn test_delete_leaves_tombstone() {
+        let mut store = Store::new(1);
+        let (_, version) = store.set(&10, b"k", b"a");
+        let stale = Entry::new(b"k".to_vec(), b"a".to_vec(), version);
+
+        // an older copy does not bring a deleted key back
+        store.delete(&10, b"k");
+        assert!(!store.merge(&10, stale.clone()));
+        assert_eq!(store.get(&10, b"k"), None);
+
+        // but a newer one does, as does setting it again
+        let newer = Entry::new(b"k".to_vec(), b"b".to_vec(), Version::new(99, 2));
+        assert!(store.merge(&10, newer));
+        store.delete(&10, b"k");
+        let (_, version) = store.set(&10, b"k", b"c");
+        assert!(version > Version::new(99, 2));
+
+        // handed off keys leave no tombstone
+        store.remove(&10, b"k");
+        assert!(store.merge(&10, stale));
+    }
+
+    #[test]
+    fn test_collisions_are_kept_apart() {
+        let mut store = Store::new(1);
+        store.set(&10, b"a", b"1");
+        store.set(&10, b"b", b"2");
+
+        assert_eq!(store.get(&10, b"a").unwrap().value, b"1".to_vec());
+        assert_eq!(store.get(&10, b"b").unwrap().value, b"2".to_vec());
+
+        assert_eq!(store.delete(&10, b"a").unwrap().value, b"1".to_vec());
+        assert_eq!(store.get(&10, b"a"), None);
+        assert_eq!(store.get(&10, b"b").unwrap().value, b"2".to_vec());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut store = Store::new(1);
+        let past = SystemTime::now() - Duration::from_secs(1);
+        let future = SystemTime::now() + Duration::from_secs(60);
+
+        let entry = |key: &[u8]| Entry::new(key.to_vec(), b"v".to_vec(), Version::default());
+        store.set_entry(&10, entry(b"expired").with_expiry(Some(past)));
+        store.set_entry(&20, entry(b"live").with_expiry(Some(future)));
+        assert_eq!(store.get(&10, b"expired"), None);
+        assert_eq!(store.get(&20, b"live").unwrap().expires_at, Some(future));
+        assert_eq!(store.stats().keys, 1);
+
+        // expired entries are replaced as if absent
+        let (previous, _) = store.set(&10, b"expired", b"c");
+        assert_eq!(previous, None);
+        assert_eq!(store.get(&10, b"expired").unwrap().expires_at, None);
+
+        let stale = Entry::new(b"m".to_vec(), b"d".to_vec(), Version::new(99, 2));
+        assert!(!store.merge(&30, stale.with_expiry(Some(past))));
+        assert_eq!(store.get(&30, b"m"), None);
+
+        // touching changes the expiry but not the version
+        let version = store.get(&20, b"live").unwrap().version;
+        let touched = store.touch(&20, b"live", None).unwrap();
+        assert_eq!((touched.expires_at, touched.version), (None, version));
+        store.touch(&20, b"live", Some(past));
+        assert_eq!(store.get(&20, b"live"), None);
+        assert_eq!(store.touch(&20, b"live", None), None);
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let mut store = Store::new(1);
+        let past = SystemTime::now() - Duration::from_secs(1);
+
+        let entry = |key: &[u8]| Entry::new(key.to_vec(), b"v".to_vec(), Version::default());
+        let (_, replaced) = store.set_entry(&10, entry(b"replaced").with_expiry(Some(past)));
+        let (_, purged) = store.set_entry(&20, entry(b"purged").with_expiry(Some(past)));
+        let (_, read) = store.set_entry(&30, entry(b"read").with_expiry(Some(past)));
+        store.set(&40, b"live", b"v");
+
+        // entries overwritten or found expired on read are reported as well
+        store.set(&10, b"replaced", b"v");
+        assert_eq!(
+            store.take_expired(),
+            vec![(10, b"replaced".to_vec(), replaced)]
+        );
+        assert!(store.expire(&30, b"read"));
+        assert!(!store.expire(&40, b"live"));
+        assert_eq!(store.take_expired(), vec![(30, b"read".to_vec(), read)]);
+
+        assert_eq!(
+            store.purge_expired(),
+            vec![(20, b"purged".to_vec(), purged)]
+        );
+        assert!(store.purge_expired().is_empty());
+        assert_eq!(store.stats().keys, 2);
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut store = Store::new(1);
+        for (id, key) in [(3, "c"), (1, "a"), (2, "b"), (4, "d")] {
+            store.set(&id, key.as_bytes(), b"");
+        }
+
+        let keys = |entries: Vec<(u64, &Entry)>| -> Vec<Vec<u8>> {
+            entries.into_iter().map(|(_, e)| e.key.clone()).collect()
+        };
+
+        assert_eq!(
+            keys(store.scan(None, 2)),
+            vec![b"a".to_vec(), b"b".to_vec()]
+        );
+        assert_eq!(
+            keys(store.scan(Some(b"b"), 10)),
+            vec![b"c".to_vec(), b"d".to_vec()]
+        );
+    }
 }
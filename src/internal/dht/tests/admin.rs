@@ -0,0 +1,64 @@
+use super::setup::*;
+use crate::{admin::NodeState, client::PastryClient, error::*, internal::pastry::shared::Config};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_admin() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 16,
+    })
+    .init()
+    .await?;
+
+    let num_keys = 50;
+    let mut client = PastryClient::connect(&network.nodes[0].info.pub_addr).await?;
+    for i in 0..num_keys {
+        client
+            .set_kv(format!("key_{:02}", i).as_bytes(), b"value")
+            .await?;
+    }
+
+    let mut total_keys = 0;
+    for node in &network.nodes {
+        let mut client = PastryClient::connect(&node.info.pub_addr).await?;
+
+        let dump = client.dump_state().await?;
+        assert_eq!(dump.id, node.info.id);
+        assert_eq!(dump.state, NodeState::RoutingRequests);
+        assert_eq!(dump.version, env!("CARGO_PKG_VERSION"));
+        assert!(dump.leaf_set.iter().any(|e| e.id == node.info.id));
+        assert!(dump.transfers.is_empty());
+
+        // page through the node's keys
+        let mut keys = Vec::new();
+        let mut start_after = None;
+        loop {
+            let page = client.list_keys(start_after.as_deref(), 7).await?;
+            assert!(page.keys.len() <= 7);
+            keys.extend(page.keys.into_iter().map(|k| k.key));
+
+            match page.next {
+                Some(next) => start_after = Some(next),
+                None => break,
+            }
+        }
+
+        let mut sorted = keys.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(keys, sorted);
+        assert_eq!(keys.len() as u64, dump.store.keys);
+        assert_eq!(
+            dump.store.bytes,
+            dump.store.keys * "key_00value".len() as u64
+        );
+
+        total_keys += keys.len();
+    }
+    assert_eq!(total_keys, num_keys);
+
+    network.shutdown();
+
+    Ok(())
+}
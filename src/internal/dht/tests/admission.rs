@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+use tonic::{Code, Request};
+
+use super::{
+    super::{admission::Admission, node::Node, service::grpc::*},
+    setup::*,
+};
+use crate::{error::*, internal::pastry::shared::Config};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_secret_admission() -> Result<()> {
+    let admission = Admission::Secret(b"secret".to_vec());
+    let mut network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4).with_admission(admission.clone()),
+        num_nodes: 8,
+    })
+    .init_by_join()
+    .await?;
+
+    // the secret binds any ID
+    network.add_node_with_id(0x0123456789ABCDEF).await?;
+
+    // nodes signing with another secret cannot join
+    let addr: SocketAddr = format!("0.0.0.0:{}", network.available_port + 100).parse()?;
+    let rogue = Node::from_id(
+        Config::new(4).with_admission(Admission::Secret(b"guess".to_vec())),
+        addr,
+        addr,
+        network.nodes[0].info.id + 1,
+    )?;
+    let res = rogue
+        .bootstrap_and_serve(Some(&network.nodes[0].info.pub_addr))
+        .await;
+    assert!(matches!(res, Err(Error::Internal(err)) if err.contains("not admitted")));
+
+    // neighbor updates from unverified nodes are rejected
+    let (_, mut client) = network.get_random_node_connection().await?;
+    let pub_addr = "http://0.0.0.0:1".to_owned();
+    let status = client
+        .announce_arrival(Request::new(AnnounceArrivalRequest {
+            id: 1,
+            pub_addr: pub_addr.clone(),
+            signature: Admission::Secret(b"guess".to_vec()).sign(1, &pub_addr),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let victim = &network.nodes[1].info;
+    let status = client
+        .fix_leaf_set(Request::new(FixLeafSetRequest {
+            id: victim.id,
+            pub_addr: victim.pub_addr.clone(),
+            sender: None,
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    // signed announcements are accepted
+    client
+        .announce_arrival(Request::new(AnnounceArrivalRequest {
+            id: 1,
+            pub_addr: pub_addr.clone(),
+            signature: admission.sign(1, &pub_addr),
+        }))
+        .await?;
+
+    network.shutdown();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_address_admission() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4).with_admission(Admission::Address),
+        num_nodes: 8,
+    })
+    .init_by_join()
+    .await?;
+
+    // IDs not derived from the public address are rejected
+    let addr: SocketAddr = format!("0.0.0.0:{}", network.available_port + 100).parse()?;
+    let rogue = Node::from_id(
+        Config::new(4).with_admission(Admission::Address),
+        addr,
+        addr,
+        network.nodes[0].info.id + 1,
+    )?;
+    let res = rogue
+        .bootstrap_and_serve(Some(&network.nodes[0].info.pub_addr))
+        .await;
+    assert!(matches!(res, Err(Error::Internal(err)) if err.contains("not admitted")));
+
+    network.shutdown();
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tonic::{Code, Request};
+
+use super::{
+    super::{
+        auth::{Principal, Role, TokenAuthenticator},
+        node::Node,
+        service::grpc::*,
+    },
+    setup::*,
+};
+use crate::{
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::pastry::shared::Config,
+    node::PastryNode,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_auth() -> Result<()> {
+    let authenticator = TokenAuthenticator::new()
+        .with_token("peer", Principal::new("peer", Role::Peer))
+        .with_token("admin", Principal::new("admin", Role::Admin))
+        .with_token(
+            "alice",
+            Principal::new("alice", Role::Client).with_key_prefix(b"alice/"),
+        );
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4)
+            .with_authenticator(Arc::new(authenticator))
+            .with_peer_token("peer"),
+        num_nodes: 16,
+    })
+    .init_by_join()
+    .await?;
+
+    let addr = &network.nodes[0].info.pub_addr;
+    let connect = |token: Option<&str>| {
+        let mut config = ClientConfig::new();
+        if let Some(token) = token {
+            config = config.with_token(token);
+        }
+        async move { PastryClient::connect_with_config(addr, &config).await }
+    };
+
+    // tenants access their own keys, routed between nodes with the peer token
+    let mut alice = connect(Some("alice")).await?;
+    for i in 0..20 {
+        let key = format!("alice/{:02}", i);
+        alice.set_kv(key.as_bytes(), b"value").await?;
+        assert_eq!(alice.get_kv(key.as_bytes()).await?, Some(b"value".to_vec()));
+    }
+
+    let err = alice.set_kv(b"bob/00", b"value").await.unwrap_err();
+    assert!(err.to_string().contains("alice may not access key"));
+    let err = alice.dump_state().await.unwrap_err();
+    assert!(err.to_string().contains("PermissionDenied"));
+
+    let mut admin = connect(Some("admin")).await?;
+    assert_eq!(admin.dump_state().await?.id, network.nodes[0].info.id);
+
+    // unknown tokens and unauthenticated requests are rejected
+    let err = connect(Some("guess")).await?.get_kv(b"alice/00").await;
+    assert!(err.unwrap_err().to_string().contains("Unauthenticated"));
+    let err = connect(None).await?.get_kv(b"alice/00").await;
+    assert!(err.unwrap_err().to_string().contains("Unauthenticated"));
+
+    let mut client = Node::connect_with_retry(addr, &Config::new(4)).await?;
+    let status = client
+        .transfer_keys(Request::new(TransferKeysRequest { id: 0 }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    network.shutdown();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_auth_local_requests() -> Result<()> {
+    let authenticator =
+        TokenAuthenticator::new().with_token("alice", Principal::new("alice", Role::Client));
+    let addr: SocketAddr = "0.0.0.0:29600".parse()?;
+    let node = PastryNode::from_id(
+        Config::new(4).with_authenticator(Arc::new(authenticator)),
+        addr,
+        addr,
+        0,
+    )?;
+    let handle = tokio::spawn(node.clone().bootstrap_and_serve(None));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // the process running the node needs no token
+    assert_eq!(node.set_kv(b"key", b"value").await?, None);
+    assert_eq!(node.get_kv(b"key").await?, Some(b"value".to_vec()));
+    assert!(node.get_kv_versioned(b"key").await?.is_some());
+    let results = node.set_many(&[(b"other", b"value")]).await?;
+    assert!(matches!(results[..], [Ok(None)]));
+    let results = node.get_many(&[b"key", b"other"]).await?;
+    assert_eq!(results.len(), 2);
+    assert_eq!(node.delete_kv(b"key").await?, Some(b"value".to_vec()));
+    assert_eq!(node.lookup(b"key").await?.owner.id, 0);
+
+    // remote requests still need one
+    let err = PastryClient::connect(&format!("http://{}", addr))
+        .await?
+        .get_kv(b"other")
+        .await;
+    assert!(err.unwrap_err().to_string().contains("Unauthenticated"));
+
+    handle.abort();
+    Ok(())
+}
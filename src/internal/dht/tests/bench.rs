@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use crate::{
+    bench::{self, KeyDistribution, Target, Workload},
+    client::ClientConfig,
+    error::*,
+    internal::pastry::shared::Config,
+    testkit::TestNetwork,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_bench() -> Result<()> {
+    let mut network = TestNetwork::builder(Config::new(4))
+        .nodes(16)
+        .in_memory()
+        .build()
+        .await?;
+
+    let workload = Workload {
+        keys: 256,
+        distribution: KeyDistribution::Zipfian { exponent: 0.99 },
+        concurrency: 4,
+        duration: Duration::from_secs(60),
+        max_ops: Some(500),
+        preload: true,
+        ..Workload::default()
+    };
+    let report = bench::run(&workload, Target::Network(&mut network)).await?;
+
+    assert_eq!(report.operations, 500);
+    assert_eq!(report.errors, 0);
+    assert_eq!((report.nodes, report.k), (Some(16), Some(4)));
+    assert_eq!(report.hops.histogram.values().sum::<u64>(), 500);
+    assert!(report.latency.p50_us <= report.latency.p99_us);
+    assert_eq!(report.queries["set"].misses, 0);
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()?).unwrap();
+    assert_eq!(json["workload"]["distribution"]["kind"], "zipfian");
+    assert_eq!(
+        json["queries"]["get"]["operations"],
+        report.queries["get"].operations
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_bench_churn() -> Result<()> {
+    let mut network = TestNetwork::builder(Config::new(4))
+        .nodes(16)
+        .in_memory()
+        .build()
+        .await?;
+
+    let workload = Workload {
+        keys: 256,
+        concurrency: 4,
+        duration: Duration::from_secs(2),
+        churn_interval: Some(Duration::from_millis(500)),
+        ..Workload::default()
+    };
+    let report = bench::run(&workload, Target::Network(&mut network)).await?;
+
+    assert!(report.churn.kills >= 1);
+    assert!(report.churn.restarts >= 1);
+    assert!(report.operations > report.errors);
+
+    // churn needs control over the nodes
+    let addrs = network
+        .nodes()
+        .into_iter()
+        .map(|node| node.pub_addr)
+        .collect();
+    let res = bench::run(
+        &workload,
+        Target::Nodes {
+            addrs,
+            config: ClientConfig::new(),
+        },
+    )
+    .await;
+    assert!(matches!(res, Err(Error::Config(_))));
+
+    Ok(())
+}
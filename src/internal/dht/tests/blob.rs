@@ -0,0 +1,45 @@
+use super::setup::*;
+use crate::{
+    client::PastryClient,
+    error::*,
+    internal::{blob::BLOB_CHUNK_SIZE, pastry::shared::Config},
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_blob() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 8,
+    })
+    .init()
+    .await?;
+
+    let (info, _) = network.get_random_node_connection().await?;
+    let mut client = PastryClient::connect(&info.pub_addr).await?;
+
+    let key = "blob".as_bytes();
+    let value: Vec<u8> = (0..5 * BLOB_CHUNK_SIZE / 2).map(|i| i as u8).collect();
+
+    let size = client.put_blob(key, value.as_slice()).await?;
+    assert_eq!(size, value.len() as u64);
+
+    let mut read = Vec::new();
+    assert_eq!(client.get_blob(key, &mut read).await?, Some(size));
+    assert_eq!(read, value);
+
+    // overwrite with a smaller value
+    let value = vec![7; BLOB_CHUNK_SIZE / 2];
+    client.put_blob(key, value.as_slice()).await?;
+
+    let mut read = Vec::new();
+    client.get_blob(key, &mut read).await?;
+    assert_eq!(read, value);
+
+    assert!(client.delete_blob(key).await?);
+    assert_eq!(client.get_blob(key, &mut Vec::new()).await?, None);
+
+    network.shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::{net::SocketAddr, time::Duration};
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt};
+
+use super::super::{events::NodeEvent, node::*, service::grpc::*};
+use crate::{admin::NodeState, error::*, internal::pastry::shared::Config, node::PastryNode};
+
+/// Waits for the first event matching the predicate, skipping the others.
+async fn wait_for<S, F>(events: &mut S, predicate: F) -> Result<NodeEvent>
+where
+    S: Stream<Item = NodeEvent> + Unpin,
+    F: Fn(&NodeEvent) -> bool,
+{
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(event) = events.next().await {
+            if predicate(&event) {
+                return Ok(event);
+            }
+        }
+        Err(Error::Internal("event stream ended".into()))
+    })
+    .await
+    .map_err(|_| Error::Internal("no matching event".into()))?
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_events() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29500".parse()?;
+    let other_addr: SocketAddr = "0.0.0.0:29501".parse()?;
+    let other_id = u64::MAX / 2;
+
+    let node = PastryNode::from_id(Config::new(4), addr, addr, 0)?;
+    let mut events = Box::pin(node.events());
+    let handle = tokio::spawn(node.clone().bootstrap_and_serve(None));
+
+    wait_for(&mut events, |e| {
+        matches!(e, NodeEvent::StateChanged(NodeState::RoutingRequests))
+    })
+    .await?;
+    for i in 0..16 {
+        node.set_kv(format!("key{}", i).as_bytes(), b"value")
+            .await?;
+    }
+
+    // a neighbor joins, receiving the keys it owns
+    let other = Node::from_id(Config::new(4), other_addr, other_addr, other_id)?;
+    let mut other_events = other.state.events.subscribe();
+    let other_handle = other
+        .bootstrap_and_serve(Some("http://0.0.0.0:29500"))
+        .await?;
+
+    let handed_off = match wait_for(&mut events, |e| {
+        matches!(e, NodeEvent::KeysHandedOff { .. })
+    })
+    .await?
+    {
+        NodeEvent::KeysHandedOff { to, keys } => {
+            assert_eq!(to, other_id);
+            keys
+        }
+        _ => unreachable!(),
+    };
+    let received = loop {
+        match other_events.recv().await {
+            Ok(NodeEvent::KeysReceived { from, keys }) => {
+                assert_eq!(from, 0);
+                break keys;
+            }
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(err) => return Err(Error::Internal(err.to_string())),
+        }
+    };
+    assert!(!handed_off.is_empty());
+    assert_eq!(handed_off.len(), received.len());
+
+    match wait_for(&mut events, |e| {
+        matches!(e, NodeEvent::RoutingTableChanged { .. })
+    })
+    .await?
+    {
+        NodeEvent::RoutingTableChanged { added, removed } => {
+            assert_eq!(added.map(|n| n.id), Some(other_id));
+            assert!(removed.is_none());
+        }
+        _ => unreachable!(),
+    }
+    match wait_for(&mut events, |e| matches!(e, NodeEvent::NeighborJoined(_))).await? {
+        NodeEvent::NeighborJoined(joined) => assert_eq!(joined.id, other_id),
+        _ => unreachable!(),
+    }
+    wait_for(&mut events, |e| {
+        matches!(e, NodeEvent::StateChanged(NodeState::RoutingRequests))
+    })
+    .await?;
+
+    // the neighbor fails and is found out by a query routed to it
+    other_handle.abort();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut client = Node::connect_with_retry("http://0.0.0.0:29500", &Config::new(4)).await?;
+    client
+        .query(QueryRequest {
+            key: other_id,
+            ..QueryRequest::new(QueryType::Get, Vec::new(), None)
+        })
+        .await?;
+
+    match wait_for(&mut events, |e| matches!(e, NodeEvent::NeighborFailed(_))).await? {
+        NodeEvent::NeighborFailed(failed) => assert_eq!(failed.id, other_id),
+        _ => unreachable!(),
+    }
+
+    handle.abort();
+
+    Ok(())
+}
@@ -42,7 +42,8 @@ async fn test_fail() -> Result<()> {
         // query its previous neighbors in order for them to fix their
         // leaf set and get their state
         for neighbor in prev_neighbors {
-            let mut client = Node::connect_with_retry(&neighbor.pub_addr).await?;
+            let mut client =
+                Node::connect_with_retry(&neighbor.pub_addr, &network.conf.pastry_conf).await?;
 
             // query neighbor for node
             client
@@ -53,6 +54,8 @@ async fn test_fail() -> Result<()> {
                     query_type: QueryType::Get.into(),
                     key: node_info.id,
                     value: None,
+                    raw_key: Vec::new(),
+                    options: None,
                 })
                 .await?;
 
@@ -91,3 +94,208 @@ async fn test_fail() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_concurrent_repair() -> Result<()> {
+    let mut network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 64,
+    })
+    .init()
+    .await?;
+
+    for _ in 0..8 {
+        let random_index = rand::thread_rng().gen_range(0..network.nodes.len());
+        let node = &network.nodes[random_index];
+        let failed_id = node.info.id;
+        let prev_neighbors =
+            get_neighbors(&network.nodes, random_index, network.conf.pastry_conf.k)
+                .iter()
+                .map(|&f| f.info.clone())
+                .filter(|f| f.id != failed_id)
+                .collect::<Vec<NodeInfo>>();
+
+        info!("TEST: Removing Node #{:016X}: ", failed_id);
+        node.handle.abort();
+        network.nodes.remove(random_index);
+
+        // all previous neighbors detect the failure at once, so they repair
+        // their leaf sets while contacting each other
+        let queries: Vec<_> = prev_neighbors
+            .iter()
+            .map(|neighbor| {
+                let config = network.conf.pastry_conf.clone();
+                let addr = neighbor.pub_addr.clone();
+                tokio::spawn(async move {
+                    let mut client = Node::connect_with_retry(&addr, &config).await?;
+                    let response = client
+                        .query(QueryRequest {
+                            from_id: 0,
+                            matched_digits: 0,
+                            hops: 0,
+                            query_type: QueryType::Get.into(),
+                            key: failed_id,
+                            value: None,
+                            raw_key: Vec::new(),
+                            options: None,
+                        })
+                        .await?
+                        .into_inner();
+                    Ok::<u64, Error>(response.from_id)
+                })
+            })
+            .collect();
+        let owners = tokio::time::timeout(std::time::Duration::from_secs(30), async {
+            let mut owners = Vec::new();
+            for query in queries {
+                owners.push(query.await.expect("query task panicked"));
+            }
+            owners
+        })
+        .await
+        .expect("concurrent repairs did not complete");
+
+        let owner = network.nodes[find_responsible(&network.nodes, failed_id)]
+            .info
+            .id;
+        for result in owners {
+            assert_eq!(result?, owner);
+        }
+
+        for neighbor in &prev_neighbors {
+            let mut client =
+                Node::connect_with_retry(&neighbor.pub_addr, &network.conf.pastry_conf).await?;
+            let state = client.get_node_state(()).await?.into_inner();
+            let mut leaf_set: Vec<u64> = state.leaf_set.iter().map(|f| f.id).collect();
+            leaf_set.sort();
+            let neighbor_index = network
+                .nodes
+                .iter()
+                .position(|e| e.info.id == neighbor.id)
+                .unwrap();
+            let mut neighbors: Vec<u64> =
+                get_neighbors(&network.nodes, neighbor_index, network.conf.pastry_conf.k)
+                    .iter()
+                    .map(|f| f.info.id)
+                    .collect();
+            neighbors.sort();
+
+            assert_eq!(
+                leaf_set.clone(),
+                neighbors.clone(),
+                "\nExpected left == right\n left: {}\n right: {}\n",
+                format_ids(leaf_set),
+                format_ids(neighbors)
+            );
+        }
+    }
+
+    network.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_table_repair_skips_own_entry() -> Result<()> {
+    let addr: std::net::SocketAddr = "0.0.0.0:29700".parse()?;
+    let node = Node::from_id(Config::new(4), addr, addr, 0)?;
+    let handle = node.clone().bootstrap_and_serve(None).await?;
+
+    // the failed node shares the first row with the node's own entry
+    let failed = NodeInfo::new(u64::MAX, "http://0.0.0.0:29701");
+    node.state
+        .data
+        .write()
+        .await
+        .table
+        .insert(failed.id, failed.clone())?;
+
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        node.fix_table_entry(&failed),
+    )
+    .await
+    .expect("table repair did not complete")?;
+
+    let table_requests = node
+        .state
+        .metrics
+        .rpc_duration
+        .with_label_values(&["get_node_table_entry"])
+        .get_sample_count();
+    assert_eq!(table_requests, 0);
+    assert!(node
+        .state
+        .data
+        .read()
+        .await
+        .table
+        .get_entries()
+        .iter()
+        .flatten()
+        .all(|e| e.id != failed.id));
+
+    handle.abort();
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_repair_releases_state() -> Result<()> {
+    let addr: std::net::SocketAddr = "0.0.0.0:29702".parse()?;
+    let node = Node::from_id(Config::new(4), addr, addr, 0)?;
+    let handle = node.clone().bootstrap_and_serve(None).await?;
+
+    // a peer accepting connections but never answering keeps the repair
+    // waiting on it
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:29703").await?;
+    let silent = tokio::spawn(async move {
+        let mut sockets = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            sockets.push(socket);
+        }
+    });
+
+    let failed = NodeInfo::new(u64::MAX, "http://0.0.0.0:29704");
+    let peer = NodeInfo::new(0x8000_0000_0000_0000, "http://0.0.0.0:29703");
+    {
+        let mut data = node.state.data.write().await;
+        data.table.insert(failed.id, failed.clone())?;
+        data.table.insert(peer.id, peer.clone())?;
+    }
+
+    let repair = tokio::spawn({
+        let node = node.clone();
+        let failed = failed.clone();
+        async move { node.fix_table_entry(&failed).await }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(!repair.is_finished());
+
+    // the node keeps routing requests and its state unlocked, so the peer
+    // could contact it while it is repairing its own state
+    assert_eq!(*node.state.name.read().await, NodeState::RoutingRequests);
+    assert!(node.state.data.try_write().is_ok());
+    let mut client = Node::connect_with_retry(&node.pub_addr, &node.config).await?;
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        client.get_node_table_entry(GetNodeTableEntryRequest { row: 0, column: 8 }),
+    )
+    .await
+    .expect("node stopped serving during repair")?;
+
+    // a concurrent repair of the same node finds it already removed
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        node.fix_table_entry(&failed),
+    )
+    .await
+    .expect("concurrent repair did not return")?;
+
+    repair.abort();
+    silent.abort();
+    handle.abort();
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::super::{
+    auth::{Principal, Role, TokenAuthenticator},
+    gateway::max_value_size,
+    node::{Node, MAX_MESSAGE_SIZE},
+};
+use crate::{
+    error::*,
+    internal::{hring::hasher::Sha256Hasher, pastry::shared::Config},
+};
+
+struct HttpResponse {
+    status: u16,
+    head: String,
+    body: Vec<u8>,
+}
+
+/// Sends a single HTTP/1.1 request to the gateway.
+async fn request(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &[u8],
+) -> Result<HttpResponse> {
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        body.len()
+    );
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    head.push_str("\r\n");
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let split = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| Error::Internal("malformed HTTP response".into()))?;
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head[9..12]
+        .parse()
+        .map_err(|_| Error::Internal("malformed HTTP status".into()))?;
+    Ok(HttpResponse {
+        status,
+        head,
+        body: response[split + 4..].to_vec(),
+    })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_gateway() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29100".parse()?;
+    let http_addr: SocketAddr = "127.0.0.1:29101".parse()?;
+    let other_addr: SocketAddr = "0.0.0.0:29102".parse()?;
+
+    let node = Node::new(Config::new(4).with_http_addr(http_addr), addr, addr)?;
+    let id = node.id;
+    let handle = node.bootstrap_and_serve(None).await?;
+    let other = Node::new(Config::new(4), other_addr, other_addr)?;
+    let other_id = other.id;
+    let other_handle = other
+        .bootstrap_and_serve(Some("http://0.0.0.0:29100"))
+        .await?;
+
+    // keys are percent-decoded and values are raw bytes
+    let res = request(http_addr, "PUT", "/kv/greeting%2Fen", None, b"hello\0").await?;
+    assert_eq!(res.status, 201);
+    let res = request(http_addr, "PUT", "/kv/greeting%2Fen", None, b"hi\0").await?;
+    assert_eq!(res.status, 204);
+
+    let res = request(http_addr, "GET", "/kv/greeting%2Fen", None, b"").await?;
+    assert_eq!(res.status, 200);
+    assert!(res.head.contains("content-type: application/octet-stream"));
+    assert_eq!(res.body, b"hi\0");
+
+    let res = request(http_addr, "GET", "/status", None, b"").await?;
+    assert_eq!(res.status, 200);
+    let status: serde_json::Value = serde_json::from_slice(&res.body)?;
+    assert_eq!(status["id"], format!("{:016X}", id));
+    assert_eq!(status["state"], "RoutingRequests");
+
+    let res = request(http_addr, "GET", "/leaf-set", None, b"").await?;
+    assert_eq!(res.status, 200);
+    let leaf_set: serde_json::Value = serde_json::from_slice(&res.body)?;
+    let other_id = format!("{:016X}", other_id);
+    assert!(leaf_set["leaf_set"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|entry| entry["id"] == other_id));
+
+    let res = request(http_addr, "DELETE", "/kv/greeting%2Fen", None, b"").await?;
+    assert_eq!(res.status, 204);
+    let res = request(http_addr, "DELETE", "/kv/greeting%2Fen", None, b"").await?;
+    assert_eq!(res.status, 404);
+    let res = request(http_addr, "GET", "/kv/greeting%2Fen", None, b"").await?;
+    assert_eq!(res.status, 404);
+    assert!(res.head.contains("content-type: application/json"));
+    let error: serde_json::Value = serde_json::from_slice(&res.body)?;
+    assert_eq!(error["error"], "key not found");
+
+    let res = request(http_addr, "POST", "/kv/greeting", None, b"").await?;
+    assert_eq!(res.status, 405);
+    assert!(res.head.contains("allow: GET, PUT, DELETE"));
+    assert_eq!(
+        request(http_addr, "GET", "/kv/", None, b"").await?.status,
+        400
+    );
+    assert_eq!(
+        request(http_addr, "GET", "/other", None, b"").await?.status,
+        404
+    );
+
+    handle.abort();
+    other_handle.abort();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_gateway_auth() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29103".parse()?;
+    let http_addr: SocketAddr = "127.0.0.1:29104".parse()?;
+
+    let authenticator = TokenAuthenticator::new()
+        .with_token("admin", Principal::new("admin", Role::Admin))
+        .with_token(
+            "alice",
+            Principal::new("alice", Role::Client).with_key_prefix(b"alice/"),
+        );
+    let config = Config::new(4)
+        .with_authenticator(Arc::new(authenticator))
+        .with_http_addr(http_addr);
+    let handle = Node::new(config, addr, addr)?
+        .bootstrap_and_serve(None)
+        .await?;
+
+    let res = request(http_addr, "GET", "/kv/alice%2Fkey", None, b"").await?;
+    assert_eq!(res.status, 401);
+    assert!(res.head.contains("www-authenticate: Bearer"));
+
+    let alice = Some("alice");
+    let res = request(http_addr, "PUT", "/kv/alice%2Fkey", alice, b"value").await?;
+    assert_eq!(res.status, 201);
+    let res = request(http_addr, "PUT", "/kv/bob%2Fkey", alice, b"value").await?;
+    assert_eq!(res.status, 403);
+    assert_eq!(
+        request(http_addr, "GET", "/status", alice, b"")
+            .await?
+            .status,
+        403
+    );
+
+    let res = request(http_addr, "GET", "/status", Some("admin"), b"").await?;
+    assert_eq!(res.status, 200);
+
+    handle.abort();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_gateway_value_limit() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29105".parse()?;
+    let http_addr: SocketAddr = "127.0.0.1:29106".parse()?;
+    let other_addr: SocketAddr = "0.0.0.0:29107".parse()?;
+
+    let config = Config::new(4).with_http_addr(http_addr);
+    let handle = Node::from_id(config, addr, addr, 0)?
+        .bootstrap_and_serve(None)
+        .await?;
+    let other_handle = Node::from_id(Config::new(4), other_addr, other_addr, 1 << 63)?
+        .bootstrap_and_serve(Some("http://0.0.0.0:29105"))
+        .await?;
+
+    // a key owned by the other node, so its queries and responses are
+    // forwarded as gRPC messages
+    let key = (0..)
+        .map(|i| format!("big_{}", i))
+        .find(|key| (1 << 62..3 << 62).contains(&Sha256Hasher::hash_once(key.as_bytes())))
+        .unwrap();
+    let path = format!("{}{}", "/kv/", key);
+    let limit = max_value_size(key.as_bytes());
+    assert!(limit < MAX_MESSAGE_SIZE);
+
+    let value = vec![7u8; limit];
+    let res = request(http_addr, "PUT", &path, None, &value).await?;
+    assert_eq!(res.status, 201);
+    let res = request(http_addr, "GET", &path, None, b"").await?;
+    assert_eq!(res.status, 200);
+    assert_eq!(res.body, value);
+
+    // bodies with a length over the limit are rejected before being sent,
+    // including those that would fit in a gRPC message without the query
+    for len in [limit + 1, MAX_MESSAGE_SIZE] {
+        let mut stream = TcpStream::connect(http_addr).await?;
+        stream
+            .write_all(
+                format!(
+                    "PUT {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+                    path, len
+                )
+                .as_bytes(),
+            )
+            .await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        assert!(response.starts_with(b"HTTP/1.1 413"));
+    }
+
+    // a chunked body has no length up front, so it is rejected once it
+    // exceeds the limit while being read
+    let mut stream = TcpStream::connect(http_addr).await?;
+    stream
+        .write_all(
+            format!(
+                "PUT {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n",
+                path
+            )
+            .as_bytes(),
+        )
+        .await?;
+    for chunk in vec![0u8; limit].chunks(64 * 1024) {
+        stream
+            .write_all(format!("{:x}\r\n", chunk.len()).as_bytes())
+            .await?;
+        stream.write_all(chunk).await?;
+        stream.write_all(b"\r\n").await?;
+    }
+    // the last byte over the limit, left unterminated so that the gateway
+    // has read everything sent when it responds
+    stream.write_all(b"1\r\n\0").await?;
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+        .await
+        .expect("the gateway waited for the rest of the body")?;
+    assert!(response.starts_with(b"HTTP/1.1 413"));
+
+    let res = request(http_addr, "GET", &path, None, b"").await?;
+    assert_eq!(res.body, value);
+
+    handle.abort();
+    other_handle.abort();
+
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{error::*, internal::pastry::shared::Config, node::PastryNode, Entry};
+
+/// Collects the entries passed to a callback.
+fn collect(entries: &Arc<Mutex<Vec<Entry>>>) -> impl Fn(&[Entry]) + Send + Sync + 'static {
+    let entries = entries.clone();
+    move |received: &[Entry]| entries.lock().unwrap().extend_from_slice(received)
+}
+
+fn keys(entries: &[Entry]) -> Vec<Vec<u8>> {
+    let mut keys: Vec<Vec<u8>> = entries.iter().map(|e| e.key.clone()).collect();
+    keys.sort();
+    keys
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_local_store() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29400".parse()?;
+    let other_addr: SocketAddr = "0.0.0.0:29401".parse()?;
+
+    let node = PastryNode::from_id(Config::new(4), addr, addr, 0)?;
+    let handle = tokio::spawn(node.clone().bootstrap_and_serve(None));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let all_keys: Vec<Vec<u8>> = (0..32)
+        .map(|i| format!("key{:02}", i).into_bytes())
+        .collect();
+    for key in &all_keys {
+        node.set_kv(key, b"value").await?;
+    }
+    assert_eq!(keys(&node.local_entries().await), all_keys);
+    assert!(node.is_owner(b"key00").await);
+    assert_eq!(node.local_store_stats().await.keys, 32);
+
+    let handed_off = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::new(Mutex::new(Vec::new()));
+    node.on_keys_handed_off(collect(&handed_off)).await;
+
+    let other = PastryNode::from_id(Config::new(4), other_addr, other_addr, u64::MAX / 2)?;
+    other.on_keys_received(collect(&received)).await;
+    let other_handle = tokio::spawn(
+        other
+            .clone()
+            .bootstrap_and_serve(Some("http://0.0.0.0:29400")),
+    );
+
+    // wait until the transfer completed on both sides
+    for _ in 0..50 {
+        if !handed_off.lock().unwrap().is_empty() && !received.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let handed_off = handed_off.lock().unwrap().clone();
+    assert!(!handed_off.is_empty());
+    assert_eq!(keys(&handed_off), keys(&received.lock().unwrap()));
+    assert_eq!(keys(&handed_off), keys(&other.local_entries().await));
+
+    // every key is stored in exactly one node, its owner
+    let local = node.local_entries().await;
+    assert_eq!(local.len() + handed_off.len(), all_keys.len());
+    for key in &all_keys {
+        let owned = node.is_owner(key).await;
+        assert_ne!(owned, other.is_owner(key).await);
+        assert_eq!(node.get_local(key).await.is_some(), owned);
+        assert_eq!(other.get_local(key).await.is_some(), !owned);
+    }
+
+    let entry = other.get_local(&handed_off[0].key).await.unwrap();
+    assert_eq!(entry, handed_off[0]);
+    assert_eq!(
+        other.local_store_stats().await.keys,
+        handed_off.len() as u64
+    );
+
+    handle.abort();
+    other_handle.abort();
+
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use tonic::Request;
 
 use crate::{
@@ -6,6 +7,7 @@ use crate::{
         pastry::shared::Config,
         util::{self, get_neighbors},
     },
+    testkit::TestNetwork,
 };
 
 use super::{
@@ -27,7 +29,9 @@ async fn test_join() -> Result<()> {
             .await?;
 
             for (idx, node) in network.nodes.iter().enumerate() {
-                let mut client = Node::connect_with_retry(&node.info.pub_addr).await?;
+                let mut client =
+                    Node::connect_with_retry(&node.info.pub_addr, &network.conf.pastry_conf)
+                        .await?;
                 let state = client.get_node_state(Request::new(())).await?.into_inner();
                 let mut leaf_set = state
                     .leaf_set
@@ -88,6 +92,8 @@ async fn test_transfer_keys() -> Result<()> {
                 query_type: QueryType::Set.into(),
                 key: *key,
                 value: Some(key.to_be_bytes().to_vec()),
+                raw_key: key.to_be_bytes().to_vec(),
+                options: None,
             })
             .await?;
     }
@@ -109,6 +115,8 @@ async fn test_transfer_keys() -> Result<()> {
                 query_type: QueryType::Get.into(),
                 key: *key,
                 value: None,
+                raw_key: key.to_be_bytes().to_vec(),
+                options: None,
             })
             .await?
             .into_inner();
@@ -116,8 +124,43 @@ async fn test_transfer_keys() -> Result<()> {
 
         assert_eq!(res.from_id, network.nodes[idx].info.id);
         assert_eq!(res.value, Some(key.to_be_bytes().to_vec()));
+        assert_eq!(res.raw_key, key.to_be_bytes().to_vec());
+        assert!(res.version.is_some());
     }
     network.shutdown();
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_rejoin() -> Result<()> {
+    let mut network = TestNetwork::builder(Config::new(4))
+        .nodes(16)
+        .in_memory()
+        .build()
+        .await?;
+    network
+        .wait_for_convergence(Duration::from_secs(30))
+        .await?;
+
+    // nodes restarted before the others noticed their failure rejoin through
+    // nodes whose entries still route the join back to them
+    for i in 0..4 {
+        let id = network.random_node().unwrap().id;
+        network.kill(id).await?;
+        tokio::time::timeout(Duration::from_secs(10), network.restart(id))
+            .await
+            .expect("rejoin did not complete")?;
+
+        let key = format!("key_{}", i);
+        let mut client = network.client(id).await?;
+        client.set_kv(key.as_bytes(), b"value").await?;
+        assert_eq!(
+            client.get_kv(key.as_bytes()).await?,
+            Some(b"value".to_vec())
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use tonic::Request;
+
+use super::{super::service::grpc::*, setup::*, util::*};
+use crate::{
+    error::*,
+    internal::{
+        dht::node::Lookup,
+        pastry::shared::Config,
+        util::{self, get_neighbors},
+    },
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_lookup() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 64,
+    })
+    .init()
+    .await?;
+
+    for i in 0..64 {
+        let (_, mut client) = network.get_random_node_connection().await?;
+        let raw_key = format!("key{}", i).into_bytes();
+        let request = LookupRequest::new(&raw_key);
+        let key = request.key;
+
+        let lookup: Lookup = client
+            .lookup(Request::new(request))
+            .await?
+            .into_inner()
+            .into();
+
+        let idx = find_responsible(&network.nodes, key);
+        assert_eq!(
+            lookup.owner.id, network.nodes[idx].info.id,
+            "\nKey {:016X} looked up at wrong owner",
+            key
+        );
+
+        let mut leaf_set: Vec<u64> = lookup.leaf_set.iter().map(|n| n.id).collect();
+        leaf_set.sort();
+        let mut neighbors: Vec<u64> =
+            get_neighbors(&network.nodes, idx, network.conf.pastry_conf.k)
+                .iter()
+                .map(|n| n.info.id)
+                .filter(|&id| id != lookup.owner.id)
+                .collect();
+        neighbors.sort();
+        assert_eq!(leaf_set, neighbors);
+
+        // lookups are routed like queries
+        let query = client
+            .query(Request::new(QueryRequest::new(
+                QueryType::Get,
+                raw_key,
+                None,
+            )))
+            .await?
+            .into_inner();
+        assert_eq!(query.from_id, lookup.owner.id);
+        assert_eq!(query.hops, lookup.hops);
+        assert!(lookup.hops <= util::U64_HEX_NUM_OF_DIGITS);
+    }
+
+    network.shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::super::node::Node;
+use crate::{error::*, internal::pastry::shared::Config};
+
+/// Sends raw commands and reads until the reply ends with `terminator`.
+async fn call(stream: &mut TcpStream, commands: &str, terminator: &str) -> Result<String> {
+    stream.write_all(commands.as_bytes()).await?;
+
+    let mut reply = Vec::new();
+    let mut buf = [0; 1024];
+    while !reply.ends_with(terminator.as_bytes()) {
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .map_err(|_| Error::Internal(format!("no reply to {:?}", commands)))??;
+        if n == 0 {
+            break;
+        }
+        reply.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&reply).to_string())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_memcache() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29300".parse()?;
+    let memcache_addr: SocketAddr = "127.0.0.1:29301".parse()?;
+    let other_addr: SocketAddr = "0.0.0.0:29302".parse()?;
+
+    let config = Config::new(4).with_memcache_addr(memcache_addr);
+    let handle = Node::new(config, addr, addr)?
+        .bootstrap_and_serve(None)
+        .await?;
+    let other_handle = Node::new(Config::new(4), other_addr, other_addr)?
+        .bootstrap_and_serve(Some("http://0.0.0.0:29300"))
+        .await?;
+
+    let mut stream = TcpStream::connect(memcache_addr).await?;
+    let s = &mut stream;
+
+    // flags are stored with the value
+    assert_eq!(
+        call(s, "set key 42 0 5\r\nvalue\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "get key missing\r\n", "END\r\n").await?,
+        "VALUE key 42 5\r\nvalue\r\nEND\r\n"
+    );
+
+    assert_eq!(
+        call(s, "add key 0 0 3\r\nnew\r\n", "\r\n").await?,
+        "NOT_STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "replace missing 0 0 3\r\nnew\r\n", "\r\n").await?,
+        "NOT_STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "add other 7 0 5\r\nother\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "replace other 8 0 3\r\nnew\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+
+    // a CAS succeeds only with the value's current CAS unique
+    let reply = call(s, "gets other\r\n", "END\r\n").await?;
+    let cas: u64 = reply
+        .split_whitespace()
+        .nth(4)
+        .and_then(|cas| cas.parse().ok())
+        .unwrap();
+    assert!(reply.starts_with("VALUE other 8 3 "));
+    assert!(reply.ends_with("\r\nnew\r\nEND\r\n"));
+    assert_eq!(
+        call(s, &format!("cas other 0 0 1 {}\r\na\r\n", cas + 1), "\r\n").await?,
+        "EXISTS\r\n"
+    );
+    assert_eq!(
+        call(s, &format!("cas other 0 0 1 {}\r\nb\r\n", cas), "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        call(s, &format!("cas other 0 0 1 {}\r\nc\r\n", cas), "\r\n").await?,
+        "EXISTS\r\n"
+    );
+    assert_eq!(
+        call(s, "cas missing 0 0 1 1\r\nd\r\n", "\r\n").await?,
+        "NOT_FOUND\r\n"
+    );
+
+    // expiration times are relative seconds or Unix timestamps
+    assert_eq!(
+        call(s, "set ttl 0 1 5\r\nvalue\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(call(s, "touch ttl 0\r\n", "\r\n").await?, "TOUCHED\r\n");
+    assert_eq!(
+        call(s, "set gone 0 -1 5\r\nvalue\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "set old 0 1000000000 5\r\nvalue\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "set short 0 1 5\r\nvalue\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(
+        call(s, "get ttl gone old short\r\n", "END\r\n").await?,
+        "VALUE ttl 0 5\r\nvalue\r\nEND\r\n"
+    );
+    assert_eq!(call(s, "touch short 0\r\n", "\r\n").await?, "NOT_FOUND\r\n");
+
+    assert_eq!(call(s, "delete key\r\n", "\r\n").await?, "DELETED\r\n");
+    assert_eq!(call(s, "delete key\r\n", "\r\n").await?, "NOT_FOUND\r\n");
+
+    // noreply commands are answered by the next command only
+    assert_eq!(
+        call(
+            s,
+            "set quiet 0 0 1 noreply\r\nq\r\ndelete quiet noreply\r\nget quiet\r\n",
+            "END\r\n"
+        )
+        .await?,
+        "END\r\n"
+    );
+
+    assert_eq!(call(s, "flush_all\r\n", "\r\n").await?, "ERROR\r\n");
+    assert_eq!(
+        call(s, "set key x 0 1\r\na\r\n", "\r\n").await?,
+        "CLIENT_ERROR bad command line format\r\n"
+    );
+
+    // the data block of a rejected command is not run as commands
+    assert_eq!(
+        call(s, "set victim 0 0 5\r\nvalue\r\n", "\r\n").await?,
+        "STORED\r\n"
+    );
+    assert_eq!(
+        call(s, "set key x 0 13\r\ndelete victim\r\n", "\r\n").await?,
+        "CLIENT_ERROR bad command line format\r\n"
+    );
+    assert_eq!(
+        call(s, "get victim\r\n", "END\r\n").await?,
+        "VALUE victim 0 5\r\nvalue\r\nEND\r\n"
+    );
+    assert_eq!(
+        call(s, "set key 0 9223372036854775807 1\r\na\r\n", "\r\n").await?,
+        "CLIENT_ERROR invalid exptime argument\r\n"
+    );
+    assert!(call(s, "version\r\n", "\r\n")
+        .await?
+        .starts_with("VERSION "));
+    assert_eq!(call(s, "quit\r\n", "").await?, "");
+
+    // without the length of the data block there is no telling where it
+    // ends, so the connection is closed
+    let mut stream = TcpStream::connect(memcache_addr).await?;
+    assert_eq!(
+        call(&mut stream, "set key 0 0 x\r\ndelete victim\r\n", "\r\n").await?,
+        "CLIENT_ERROR bad command line format\r\n"
+    );
+    let read = stream.read(&mut [0; 64]).await;
+    assert!(!matches!(read, Ok(n) if n > 0));
+    let mut stream = TcpStream::connect(memcache_addr).await?;
+    assert_eq!(
+        call(&mut stream, "get victim\r\n", "END\r\n").await?,
+        "VALUE victim 0 5\r\nvalue\r\nEND\r\n"
+    );
+
+    handle.abort();
+    other_handle.abort();
+
+    Ok(())
+}
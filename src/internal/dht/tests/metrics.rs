@@ -0,0 +1,46 @@
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::super::node::Node;
+use crate::{
+    client::PastryClient,
+    error::*,
+    internal::{hring::hasher::Sha256Hasher, pastry::shared::Config},
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_metrics() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29000".parse()?;
+    let metrics_addr: SocketAddr = "127.0.0.1:29001".parse()?;
+
+    let node = Node::new(Config::new(4).with_metrics_addr(metrics_addr), addr, addr)?;
+    let handle = node.bootstrap_and_serve(None).await?;
+
+    let mut client = PastryClient::connect(&format!("http://{}", addr)).await?;
+    client.set_kv("key".as_bytes(), "value".as_bytes()).await?;
+    client.get_kv("key".as_bytes()).await?;
+
+    let mut stream = TcpStream::connect(metrics_addr).await?;
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let node_id = format!("{:016X}", Sha256Hasher::hash_once(b"http://0.0.0.0:29000"));
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(&format!(
+        "pastry_queries_served_total{{node_id=\"{}\"}} 2",
+        node_id
+    )));
+    assert!(response.contains(&format!("pastry_store_keys{{node_id=\"{}\"}} 1", node_id)));
+    assert!(response.contains("pastry_rpc_duration_seconds_count"));
+
+    handle.abort();
+
+    Ok(())
+}
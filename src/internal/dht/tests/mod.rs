@@ -1,5 +1,27 @@
+mod admin;
+mod admission;
+mod auth;
+#[cfg(feature = "bench")]
+mod bench;
+mod blob;
+mod events;
 mod fail;
+mod gateway;
+mod hooks;
 mod join;
+mod lookup;
+mod memcache;
+mod metrics;
 mod query;
+mod resp;
+mod ring;
 mod setup;
+#[cfg(feature = "sim")]
+mod sim;
+#[cfg(feature = "testkit")]
+mod testkit;
+mod tls;
+mod trace;
+mod transport;
 mod util;
+mod watch;
@@ -1,6 +1,6 @@
 use log::info;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tonic::Request;
+use tonic::{Code, Request};
 
 use super::{super::service::grpc::*, setup::*};
 use crate::{
@@ -49,6 +49,8 @@ async fn test_query() -> Result<()> {
                 query_type: QueryType::Get.into(),
                 key,
                 value: None,
+                raw_key: Vec::new(),
+                options: None,
             }))
             .await?
             .into_inner();
@@ -70,3 +72,170 @@ async fn test_query() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_batch_query() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 64,
+    })
+    .init()
+    .await?;
+
+    let keys: Vec<u64> = (0..128).map(get_random_key).collect::<Result<_>>()?;
+    let queries = |query_type: QueryType| -> Vec<QueryRequest> {
+        keys.iter()
+            .map(|&key| QueryRequest {
+                from_id: 0,
+                matched_digits: 0,
+                hops: 0,
+                query_type: query_type.into(),
+                key,
+                value: Some(key.to_be_bytes().to_vec()),
+                raw_key: Vec::new(),
+                options: None,
+            })
+            .collect()
+    };
+
+    let (_, mut client) = network.get_random_node_connection().await?;
+    client
+        .batch_query(Request::new(BatchQueryRequest {
+            from_id: 0,
+            queries: queries(QueryType::Set),
+        }))
+        .await?;
+
+    let (_, mut client) = network.get_random_node_connection().await?;
+    let res = client
+        .batch_query(Request::new(BatchQueryRequest {
+            from_id: 0,
+            queries: queries(QueryType::Get),
+        }))
+        .await?
+        .into_inner();
+
+    assert_eq!(res.responses.len(), keys.len());
+    for (key, response) in keys.iter().zip(res.responses) {
+        let idx = find_responsible(&network.nodes, *key);
+
+        assert_eq!(response.key, *key);
+        assert_eq!(response.from_id, network.nodes[idx].info.id);
+        assert_eq!(response.value, Some(key.to_be_bytes().to_vec()));
+    }
+
+    network.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_batch_query_results() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 16,
+    })
+    .init()
+    .await?;
+
+    let (_, mut client) = network.get_random_node_connection().await?;
+    let mut request = BatchQueryRequest::set(&[(b"present", b"value"), (b"unset", b"value")]);
+    request.queries[1].value = None;
+    let results = client
+        .batch_query(Request::new(request))
+        .await?
+        .into_inner()
+        .into_results();
+
+    assert!(matches!(results[0], Ok(None)));
+    assert!(matches!(results[1], Err(Error::Value(_))));
+
+    let results = client
+        .batch_query(Request::new(BatchQueryRequest::get(&[
+            b"present", b"unset",
+        ])))
+        .await?
+        .into_inner()
+        .into_results();
+
+    assert_eq!(results[0].as_ref().ok(), Some(&Some(b"value".to_vec())));
+    assert!(matches!(results[1], Ok(None)));
+
+    network.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_set_conditions() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 8,
+    })
+    .init()
+    .await?;
+    let (_, mut client) = network.get_random_node_connection().await?;
+
+    let set = |value: &[u8], options: SetOptions| {
+        let mut query = QueryRequest::new(QueryType::Set, b"key".to_vec(), Some(value.to_vec()));
+        query.options = Some(options);
+        query
+    };
+    let if_version = |version: &Version, expected_node_id: Option<u64>| SetOptions {
+        condition: SetCondition::IfVersion.into(),
+        expected_counter: version.counter,
+        expected_node_id,
+        ..Default::default()
+    };
+
+    let version = client
+        .query(set(b"first", SetOptions::default()))
+        .await?
+        .into_inner()
+        .version
+        .unwrap();
+
+    // a version with the same counter written by another node is not the
+    // expected one
+    let res = client
+        .query(set(
+            b"second",
+            if_version(&version, Some(version.node_id ^ 1)),
+        ))
+        .await?
+        .into_inner();
+    assert_eq!(res.error, Some(QueryError::ConditionNotMet.into()));
+    assert_eq!(res.value, Some(b"first".to_vec()));
+
+    let res = client
+        .query(set(b"second", if_version(&version, Some(version.node_id))))
+        .await?
+        .into_inner();
+    assert_eq!(res.error, None);
+
+    // without a node ID, as from memcached's cas, only the counter is compared
+    let version = res.version.unwrap();
+    let res = client
+        .query(set(b"third", if_version(&version, None)))
+        .await?
+        .into_inner();
+    assert_eq!(res.error, None);
+
+    let unknown = SetOptions {
+        condition: 42,
+        ..Default::default()
+    };
+    let status = client.query(set(b"fourth", unknown)).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    let query = QueryRequest::new(QueryType::Get, b"key".to_vec(), None);
+    let res = client.query(query).await?.into_inner();
+    assert_eq!(res.value, Some(b"third".to_vec()));
+
+    network.shutdown();
+
+    Ok(())
+}
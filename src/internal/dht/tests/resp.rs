@@ -0,0 +1,191 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use super::super::{
+    auth::{Principal, Role, TokenAuthenticator},
+    node::Node,
+};
+use crate::{error::*, internal::pastry::shared::Config};
+
+/// A raw RESP connection, reading replies back in their wire format.
+struct RespClient {
+    stream: BufReader<TcpStream>,
+}
+
+impl RespClient {
+    async fn connect(addr: SocketAddr) -> Result<Self> {
+        Ok(RespClient {
+            stream: BufReader::new(TcpStream::connect(addr).await?),
+        })
+    }
+
+    /// Sends a command as an array of bulk strings and reads its reply.
+    async fn call(&mut self, args: &[&str]) -> Result<String> {
+        let mut command = format!("*{}\r\n", args.len());
+        for arg in args {
+            command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        self.stream.get_mut().write_all(command.as_bytes()).await?;
+        self.read_reply().await
+    }
+
+    async fn read_reply(&mut self) -> Result<String> {
+        let mut line = String::new();
+        self.stream.read_line(&mut line).await?;
+
+        let count = line
+            .get(1..)
+            .unwrap_or_default()
+            .trim_end()
+            .parse::<i64>()
+            .unwrap_or(-1);
+        match line.as_bytes().first() {
+            Some(b'$') if count >= 0 => {
+                let mut value = vec![0; count as usize + 2];
+                self.stream.read_exact(&mut value).await?;
+                line.push_str(&String::from_utf8_lossy(&value));
+            }
+            Some(b'*') => {
+                for _ in 0..count {
+                    line.push_str(&Box::pin(self.read_reply()).await?);
+                }
+            }
+            _ => {}
+        }
+        Ok(line)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_resp() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29200".parse()?;
+    let resp_addr: SocketAddr = "127.0.0.1:29201".parse()?;
+    let other_addr: SocketAddr = "0.0.0.0:29202".parse()?;
+
+    let handle = Node::new(Config::new(4).with_resp_addr(resp_addr), addr, addr)?
+        .bootstrap_and_serve(None)
+        .await?;
+    let other_handle = Node::new(Config::new(4), other_addr, other_addr)?
+        .bootstrap_and_serve(Some("http://0.0.0.0:29200"))
+        .await?;
+
+    let mut client = RespClient::connect(resp_addr).await?;
+    assert_eq!(client.call(&["PING"]).await?, "+PONG\r\n");
+    assert_eq!(client.call(&["set", "key", "value"]).await?, "+OK\r\n");
+    assert_eq!(client.call(&["GET", "key"]).await?, "$5\r\nvalue\r\n");
+    assert_eq!(client.call(&["GET", "other"]).await?, "$-1\r\n");
+
+    // conditional sets reply nil when the condition is not met
+    assert_eq!(client.call(&["SET", "key", "new", "NX"]).await?, "$-1\r\n");
+    assert_eq!(
+        client.call(&["SET", "other", "new", "XX"]).await?,
+        "$-1\r\n"
+    );
+    assert_eq!(client.call(&["SET", "key", "new", "XX"]).await?, "+OK\r\n");
+    assert_eq!(client.call(&["GET", "key"]).await?, "$3\r\nnew\r\n");
+
+    assert_eq!(
+        client.call(&["SET", "ttl", "value", "PX", "200"]).await?,
+        "+OK\r\n"
+    );
+    assert_eq!(client.call(&["GET", "ttl"]).await?, "$5\r\nvalue\r\n");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(client.call(&["GET", "ttl"]).await?, "$-1\r\n");
+
+    assert_eq!(
+        client.call(&["SET", "key", "value", "EX", "0"]).await?,
+        "-ERR invalid expire time in 'set' command\r\n"
+    );
+    assert_eq!(
+        client.call(&["SET", "key", "value", "NX", "XX"]).await?,
+        "-ERR syntax error\r\n"
+    );
+
+    assert_eq!(client.call(&["MSET", "a", "1", "b", "2"]).await?, "+OK\r\n");
+    assert_eq!(
+        client.call(&["MGET", "a", "b", "c"]).await?,
+        "*3\r\n$1\r\n1\r\n$1\r\n2\r\n$-1\r\n"
+    );
+    assert_eq!(
+        client.call(&["EXISTS", "a", "b", "c", "a"]).await?,
+        ":3\r\n"
+    );
+    assert_eq!(client.call(&["DEL", "a", "b", "c"]).await?, ":2\r\n");
+    assert_eq!(client.call(&["EXISTS", "a"]).await?, ":0\r\n");
+
+    assert_eq!(
+        client.call(&["GET"]).await?,
+        "-ERR wrong number of arguments for 'get' command\r\n"
+    );
+    assert_eq!(
+        client.call(&["FLUSHALL"]).await?,
+        "-ERR unknown command 'FLUSHALL'\r\n"
+    );
+
+    // inline and pipelined commands
+    client
+        .stream
+        .get_mut()
+        .write_all(b"PING\r\nGET key\r\n")
+        .await?;
+    assert_eq!(client.read_reply().await?, "+PONG\r\n");
+    assert_eq!(client.read_reply().await?, "$3\r\nnew\r\n");
+
+    assert_eq!(client.call(&["QUIT"]).await?, "+OK\r\n");
+    assert_eq!(client.read_reply().await?, "");
+
+    handle.abort();
+    other_handle.abort();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_resp_auth() -> Result<()> {
+    let addr: SocketAddr = "0.0.0.0:29203".parse()?;
+    let resp_addr: SocketAddr = "127.0.0.1:29204".parse()?;
+
+    let authenticator = TokenAuthenticator::new().with_token(
+        "alice",
+        Principal::new("alice", Role::Client).with_key_prefix(b"alice/"),
+    );
+    let config = Config::new(4)
+        .with_authenticator(Arc::new(authenticator))
+        .with_resp_addr(resp_addr);
+    let handle = Node::new(config, addr, addr)?
+        .bootstrap_and_serve(None)
+        .await?;
+
+    let mut client = RespClient::connect(resp_addr).await?;
+    assert!(client
+        .call(&["GET", "alice/key"])
+        .await?
+        .starts_with("-NOAUTH"));
+    assert!(client
+        .call(&["AUTH", "mallory"])
+        .await?
+        .starts_with("-WRONGPASS"));
+
+    assert_eq!(client.call(&["AUTH", "alice"]).await?, "+OK\r\n");
+    assert_eq!(
+        client.call(&["SET", "alice/key", "value"]).await?,
+        "+OK\r\n"
+    );
+    assert!(client
+        .call(&["SET", "bob/key", "value"])
+        .await?
+        .starts_with("-NOPERM"));
+    assert!(client
+        .call(&["MGET", "alice/key", "bob/key"])
+        .await?
+        .starts_with("-NOPERM"));
+
+    handle.abort();
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use super::setup::*;
+use crate::{
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::pastry::shared::Config,
+    ring,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_crawl_and_check() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 32,
+    })
+    .init()
+    .await?;
+
+    let mut client = PastryClient::connect(&network.nodes[0].info.pub_addr).await?;
+    for i in 0..100 {
+        client
+            .set_kv(format!("key_{:03}", i).as_bytes(), b"value")
+            .await?;
+    }
+
+    let snapshot = ring::crawl(&network.nodes[7].info.pub_addr, &ClientConfig::new(), true).await?;
+
+    assert_eq!(snapshot.nodes.len(), network.nodes.len());
+    assert!(snapshot.unreachable.is_empty());
+    for node in &network.nodes {
+        assert!(snapshot.nodes.contains_key(&node.info.id));
+    }
+    assert_eq!(
+        snapshot.keys.values().map(|keys| keys.len()).sum::<usize>(),
+        100
+    );
+
+    let violations = snapshot.check();
+    assert!(violations.is_empty(), "{:?}", violations);
+
+    network.shutdown();
+    Ok(())
+}
@@ -308,12 +308,10 @@ impl Network {
     /// A Result containing a client connection to a random node in the network and the node
     /// information.
     ///
-    pub async fn get_random_node_connection(
-        &self,
-    ) -> Result<(NodeInfo, NodeServiceClient<Channel>)> {
+    pub async fn get_random_node_connection(&self) -> Result<(NodeInfo, NodeClient)> {
         let random_index = rand::thread_rng().gen_range(0..self.nodes.len());
         let node = &self.nodes[random_index];
-        let client = Node::connect_with_retry(&node.info.pub_addr).await?;
+        let client = Node::connect_with_retry(&node.info.pub_addr, &self.conf.pastry_conf).await?;
 
         Ok((node.info.clone(), client))
     }
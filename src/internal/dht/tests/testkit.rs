@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use crate::{error::*, internal::pastry::shared::Config, testkit::TestNetwork};
+
+const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_testkit() -> Result<()> {
+    let mut network = TestNetwork::builder(Config::new(4))
+        .nodes(24)
+        .in_memory()
+        .build()
+        .await?;
+    network.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+
+    let mut client = network.client(network.nodes()[0].id).await?;
+    for i in 0..32 {
+        client
+            .set_kv(format!("key_{}", i).as_bytes(), b"value")
+            .await?;
+    }
+
+    // killed nodes lose their keys, which their restarted instances do not
+    // recover
+    let owner = network.owner(b"key_0").unwrap();
+    network.kill(owner.id).await?;
+    assert_eq!(network.killed()[0].id, owner.id);
+    network.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+    assert_ne!(network.owner(b"key_0").unwrap().id, owner.id);
+    assert_eq!(client.get_kv(b"key_0").await?, None);
+
+    network.restart(owner.id).await?;
+    network.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+    assert_eq!(network.owner(b"key_0").unwrap().id, owner.id);
+    assert!(network.killed().is_empty());
+
+    let id = network.add_node().await?.id;
+    network.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+    assert_eq!(network.nodes().len(), 25);
+    assert_eq!(network.crawl(false).await?.nodes.len(), 25);
+    assert!(network.nodes().iter().any(|node| node.id == id));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_testkit_tcp() -> Result<()> {
+    let ids = vec![0x1000_0000_0000_0000, 0x8000_0000_0000_0000];
+    let network = TestNetwork::builder(Config::new(4))
+        .nodes(4)
+        .ids(ids.clone())
+        .build()
+        .await?;
+    network.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+
+    assert_eq!(network.nodes().len(), 4);
+    assert!(ids
+        .iter()
+        .all(|id| network.nodes().iter().any(|node| node.id == *id)));
+    assert!(network.nodes()[0].pub_addr.starts_with("http://0.0.0.0:"));
+
+    Ok(())
+}
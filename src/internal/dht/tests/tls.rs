@@ -0,0 +1,79 @@
+use super::setup::*;
+use crate::{client::PastryClient, error::*, internal::pastry::shared::Config, Tls};
+
+/// Generates a CA and a certificate for the test nodes signed by it.
+///
+/// # Returns
+///
+/// The PEM encoded CA certificate, node certificate and node private key.
+///
+#[cfg(test)]
+fn generate_certificates() -> (String, String, String) {
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, SanType};
+
+    let mut ca_params = CertificateParams::new(Vec::new());
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+
+    let mut node_params = CertificateParams::new(Vec::new());
+    node_params.subject_alt_names = vec![
+        SanType::IpAddress([0, 0, 0, 0].into()),
+        SanType::IpAddress([127, 0, 0, 1].into()),
+    ];
+    let node = Certificate::from_params(node_params).unwrap();
+
+    (
+        ca.serialize_pem().unwrap(),
+        node.serialize_pem_with_signer(&ca).unwrap(),
+        node.serialize_private_key_pem(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_mutual_tls() -> Result<()> {
+    let (ca, cert, key) = generate_certificates();
+    let tls = Tls::new().with_identity(&cert, &key).with_ca(&ca);
+
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4).with_tls(tls.clone()),
+        num_nodes: 8,
+    })
+    .init()
+    .await?;
+
+    for node in &network.nodes {
+        assert!(node.info.pub_addr.starts_with("https://"));
+    }
+
+    // nodes route requests to each other over mutual TLS
+    let addr = &network.nodes[0].info.pub_addr;
+    let mut client = PastryClient::connect_with_tls(addr, &tls).await?;
+    for i in 0..20 {
+        let key = format!("key_{:02}", i);
+        client.set_kv(key.as_bytes(), b"value").await?;
+        assert_eq!(
+            client.get_kv(key.as_bytes()).await?,
+            Some(b"value".to_vec())
+        );
+    }
+
+    // clients without a certificate signed by the CA are rejected
+    let anonymous = Tls::new().with_ca(&ca);
+    let rejected = match PastryClient::connect_with_tls(addr, &anonymous).await {
+        Ok(mut client) => client.get_kv(b"key_00").await.is_err(),
+        Err(_) => true,
+    };
+    assert!(rejected);
+
+    // plaintext clients are rejected
+    let plaintext = addr.replacen("https://", "http://", 1);
+    let rejected = match PastryClient::connect(&plaintext).await {
+        Ok(mut client) => client.get_kv(b"key_00").await.is_err(),
+        Err(_) => true,
+    };
+    assert!(rejected);
+
+    network.shutdown();
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use tonic::Request;
+
+use super::{super::service::grpc::*, setup::*};
+use crate::{
+    error::*,
+    internal::{
+        dht::{
+            tests::util::find_responsible,
+            trace::{InMemorySpanExporter, TraceContext},
+        },
+        hring::hasher::Sha256Hasher,
+        pastry::shared::Config,
+    },
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_trace_query() -> Result<()> {
+    let exporter = Arc::new(InMemorySpanExporter::default());
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4).with_span_exporter(exporter.clone()),
+        num_nodes: 64,
+    })
+    .init()
+    .await?;
+
+    for i in 0..16u64 {
+        let (info, mut client) = network.get_random_node_connection().await?;
+        let key = Sha256Hasher::hash_once(format!("trace_{}", i).as_bytes());
+
+        let ctx = TraceContext {
+            trace_id: rand::random(),
+            span_id: rand::random(),
+        };
+        let mut request = Request::new(QueryRequest {
+            from_id: 0,
+            matched_digits: 0,
+            hops: 0,
+            query_type: QueryType::Get.into(),
+            key,
+            value: None,
+            raw_key: Vec::new(),
+            options: None,
+        });
+        ctx.inject(request.metadata_mut());
+
+        let res = client.query(request).await?.into_inner();
+
+        // every hop is a child of the previous one, from the entry node to the
+        // owner of the key
+        let path = exporter.trace(ctx.trace_id);
+        assert_eq!(path.len(), res.hops as usize + 1);
+        assert_eq!(path[0].parent_id, Some(ctx.span_id));
+        assert_eq!(path[0].node_id, info.id);
+        for pair in path.windows(2) {
+            assert_eq!(pair[1].parent_id, Some(pair[0].span_id));
+        }
+        assert!(path.iter().all(|span| span.name == "query"));
+
+        let idx = find_responsible(&network.nodes, key);
+        assert_eq!(path.last().unwrap().node_id, network.nodes[idx].info.id);
+    }
+
+    network.shutdown();
+
+    Ok(())
+}
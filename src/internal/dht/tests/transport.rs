@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use super::{super::transport::MemoryTransport, setup::*};
+use crate::{
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::pastry::shared::Config,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_memory_transport() -> Result<()> {
+    let transport = Arc::new(MemoryTransport::new());
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4).with_transport(transport.clone()),
+        num_nodes: 64,
+    })
+    .init_by_join()
+    .await?;
+
+    let config = ClientConfig::new().with_transport(transport);
+    for i in 0..network.nodes.len() {
+        let node = &network.nodes[i].info;
+        let other = &network.nodes[(i + 1) % network.nodes.len()].info;
+        let key = format!("key_{}", i);
+
+        // keys set through one node are routed to their owner and found
+        // through another
+        let mut client = PastryClient::connect_with_config(&node.pub_addr, &config).await?;
+        client.set_kv(key.as_bytes(), b"value").await?;
+        let mut client = PastryClient::connect_with_config(&other.pub_addr, &config).await?;
+        assert_eq!(
+            client.get_kv(key.as_bytes()).await?,
+            Some(b"value".to_vec())
+        );
+    }
+
+    // nodes are not listening on sockets
+    let addr = network.nodes[0].info.pub_addr.clone();
+    assert!(PastryClient::connect(&addr).await.is_err());
+
+    network.shutdown();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(PastryClient::connect_with_config(&addr, &config)
+        .await
+        .is_err());
+
+    Ok(())
+}
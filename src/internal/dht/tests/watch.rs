@@ -0,0 +1,114 @@
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+use super::{super::service::grpc::*, setup::*};
+use crate::{
+    client::PastryClient,
+    error::*,
+    internal::{hring::hasher::Sha256Hasher, pastry::shared::Config},
+    WatchEvent,
+};
+
+const EVENT_TIMEOUT_SECONDS: u64 = 5;
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_watch() -> Result<()> {
+    let mut network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 16,
+    })
+    .init()
+    .await?;
+
+    let key = "config".as_bytes();
+    let timeout = Duration::from_secs(EVENT_TIMEOUT_SECONDS);
+
+    let (info, _) = network.get_random_node_connection().await?;
+    let events = PastryClient::connect(&info.pub_addr)
+        .await?
+        .watch(key)
+        .await?;
+    tokio::pin!(events);
+
+    let (info, _) = network.get_random_node_connection().await?;
+    let mut client = PastryClient::connect(&info.pub_addr).await?;
+
+    client.set_kv(key, "v1".as_bytes()).await?;
+    match tokio::time::timeout(timeout, events.next()).await {
+        Ok(Some(WatchEvent::Set { value, .. })) => assert_eq!(value, "v1".as_bytes()),
+        other => panic!("expected set event, got {:?}", other),
+    }
+
+    client.delete_kv(key).await?;
+    match tokio::time::timeout(timeout, events.next()).await {
+        Ok(Some(WatchEvent::Delete { .. })) => {}
+        other => panic!("expected delete event, got {:?}", other),
+    }
+
+    // move ownership of the key to a new node
+    network
+        .add_node_with_id(Sha256Hasher::hash_once(key))
+        .await?;
+
+    let mut received = false;
+    for i in 0..EVENT_TIMEOUT_SECONDS {
+        let value = format!("v{}", i + 2);
+        client.set_kv(key, value.as_bytes()).await?;
+
+        if let Ok(Some(WatchEvent::Set { value: event, .. })) =
+            tokio::time::timeout(Duration::from_secs(1), events.next()).await
+        {
+            assert_eq!(event, value.as_bytes());
+            received = true;
+            break;
+        }
+    }
+    assert!(received, "watch was not re-established on the new owner");
+
+    network.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn test_watch_expiry() -> Result<()> {
+    let network = Network::new(NetworkConfiguration {
+        pastry_conf: Config::new(4),
+        num_nodes: 8,
+    })
+    .init()
+    .await?;
+
+    let key = "session".as_bytes();
+    let timeout = Duration::from_secs(EVENT_TIMEOUT_SECONDS);
+
+    let (info, mut client) = network.get_random_node_connection().await?;
+    let events = PastryClient::connect(&info.pub_addr)
+        .await?
+        .watch(key)
+        .await?;
+    tokio::pin!(events);
+
+    let mut query = QueryRequest::new(QueryType::Set, key.to_vec(), Some(b"v".to_vec()));
+    query.options = Some(SetOptions {
+        ttl_ms: Some(100),
+        ..Default::default()
+    });
+    client.query(query).await?;
+
+    match tokio::time::timeout(timeout, events.next()).await {
+        Ok(Some(WatchEvent::Set { value, .. })) => assert_eq!(value, b"v"),
+        other => panic!("expected set event, got {:?}", other),
+    }
+    // nothing reads the key, so it expires when the owner purges its store
+    match tokio::time::timeout(timeout, events.next()).await {
+        Ok(Some(WatchEvent::Expire { .. })) => {}
+        other => panic!("expected expire event, got {:?}", other),
+    }
+
+    network.shutdown();
+
+    Ok(())
+}
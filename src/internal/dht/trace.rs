@@ -0,0 +1,215 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+use tonic::{metadata::MetadataMap, Request};
+
+use super::node::Node;
+
+const TRACE_ID_HEADER: &str = "x-pastry-trace-id";
+const SPAN_ID_HEADER: &str = "x-pastry-span-id";
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+/// Identifies a span inside a trace. It is propagated in the metadata of
+/// every request a node sends while handling the span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u64,
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// Gets the context of the span being handled by the current task.
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(|ctx| *ctx).ok()
+    }
+
+    /// Extracts the context propagated in a request's metadata.
+    pub fn from_metadata(metadata: &MetadataMap) -> Option<Self> {
+        let parse = |header| {
+            metadata
+                .get(header)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| u64::from_str_radix(value, 16).ok())
+        };
+
+        Some(TraceContext {
+            trace_id: parse(TRACE_ID_HEADER)?,
+            span_id: parse(SPAN_ID_HEADER)?,
+        })
+    }
+
+    /// Propagates the context in a request's metadata.
+    pub fn inject(&self, metadata: &mut MetadataMap) {
+        for (header, id) in [
+            (TRACE_ID_HEADER, self.trace_id),
+            (SPAN_ID_HEADER, self.span_id),
+        ] {
+            if let Ok(value) = format!("{:016X}", id).parse() {
+                metadata.insert(header, value);
+            }
+        }
+    }
+}
+
+/// Wraps a message in a request carrying the current trace context.
+pub fn traced<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(ctx) = TraceContext::current() {
+        ctx.inject(request.metadata_mut());
+    }
+    request
+}
+
+/// A finished span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: u64,
+    pub span_id: u64,
+    pub parent_id: Option<u64>,
+    pub node_id: u64,
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub duration: Duration,
+}
+
+/// Receives the spans finished by a node.
+pub trait SpanExporter: Debug + Send + Sync {
+    fn export(&self, span: Span);
+}
+
+/// A span exporter that keeps every span in memory.
+#[derive(Debug, Default)]
+pub struct InMemorySpanExporter {
+    spans: Mutex<Vec<Span>>,
+}
+
+impl InMemorySpanExporter {
+    /// Gets all spans exported so far.
+    pub fn spans(&self) -> Vec<Span> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// Gets the spans of a trace, ordered from the root to the leaves. Spans
+    /// whose parent was not recorded, such as those started by a client, are
+    /// treated as roots.
+    pub fn trace(&self, trace_id: u64) -> Vec<Span> {
+        let spans: Vec<Span> = self
+            .spans()
+            .into_iter()
+            .filter(|s| s.trace_id == trace_id)
+            .collect();
+
+        let mut path: Vec<Span> = spans
+            .iter()
+            .filter(|s| {
+                s.parent_id
+                    .is_none_or(|parent| spans.iter().all(|p| p.span_id != parent))
+            })
+            .cloned()
+            .collect();
+        let mut idx = 0;
+        while idx < path.len() {
+            let parent = path[idx].span_id;
+            path.extend(
+                spans
+                    .iter()
+                    .filter(|s| s.parent_id == Some(parent))
+                    .cloned(),
+            );
+            idx += 1;
+        }
+        path
+    }
+}
+
+impl SpanExporter for InMemorySpanExporter {
+    fn export(&self, span: Span) {
+        self.spans.lock().unwrap().push(span);
+    }
+}
+
+impl Node {
+    /// Runs a future inside a new span, child of `parent` or, if not
+    /// supplied, of the span being handled by the current task.
+    ///
+    /// Requests sent with `traced` while the future runs carry the new span's
+    /// context. Without a parent and a configured exporter the future runs
+    /// untraced.
+    ///
+    pub async fn in_span<F>(
+        &self,
+        name: &'static str,
+        parent: Option<TraceContext>,
+        f: F,
+    ) -> F::Output
+    where
+        F: Future,
+    {
+        let parent = parent.or_else(TraceContext::current);
+        if parent.is_none() && self.config.span_exporter.is_none() {
+            return f.await;
+        }
+
+        let ctx = TraceContext {
+            trace_id: parent.map_or_else(rand::random, |p| p.trace_id),
+            span_id: rand::random(),
+        };
+
+        let start = SystemTime::now();
+        let instant = Instant::now();
+        let output = CURRENT.scope(ctx, f).await;
+
+        if let Some(exporter) = &self.config.span_exporter {
+            exporter.export(Span {
+                trace_id: ctx.trace_id,
+                span_id: ctx.span_id,
+                parent_id: parent.map(|p| p.span_id),
+                node_id: self.id,
+                name,
+                start,
+                duration: instant.elapsed(),
+            });
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let ctx = TraceContext {
+            trace_id: 0xFEDCBA9876543210,
+            span_id: 0x1,
+        };
+
+        let mut metadata = MetadataMap::new();
+        ctx.inject(&mut metadata);
+
+        assert_eq!(TraceContext::from_metadata(&metadata), Some(ctx));
+        assert_eq!(TraceContext::from_metadata(&MetadataMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_traced_request_carries_current_context() {
+        let ctx = TraceContext {
+            trace_id: 1,
+            span_id: 2,
+        };
+
+        let request = CURRENT.scope(ctx, async { traced(()) }).await;
+        assert_eq!(TraceContext::from_metadata(request.metadata()), Some(ctx));
+
+        let request = traced(());
+        assert_eq!(TraceContext::from_metadata(request.metadata()), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::DuplexStream,
+    sync::mpsc::{self, UnboundedSender},
+};
+use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
+use tonic::{
+    codegen::Service,
+    transport::{server::Router, Channel, Endpoint, Uri},
+};
+
+use crate::error::*;
+
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A future serving a node until it fails or is dropped.
+pub type ServeFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// How nodes reach each other: serving a node's gRPC service and opening
+/// channels to other nodes.
+///
+#[tonic::async_trait]
+pub trait Transport: Debug + Send + Sync {
+    /// Binds a node's service, so other nodes can reach it at its public
+    /// address.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address the node listens on.
+    /// * `pub_addr` - The public address the node is exposed on.
+    /// * `router` - The node's gRPC service.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the future serving the node, or an error if the
+    /// address cannot be bound.
+    ///
+    fn bind(&self, addr: SocketAddr, pub_addr: &str, router: Router) -> Result<ServeFuture>;
+
+    /// Opens a channel to a node.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The node's public address, with the timeouts and TLS
+    ///   settings to connect with.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the channel.
+    ///
+    async fn connect(&self, endpoint: Endpoint) -> Result<Channel>;
+}
+
+/// Reaches nodes over TCP sockets.
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+#[tonic::async_trait]
+impl Transport for TcpTransport {
+    fn bind(&self, addr: SocketAddr, _pub_addr: &str, router: Router) -> Result<ServeFuture> {
+        let incoming = tonic::transport::server::TcpIncoming::new(addr, true, None)?;
+        Ok(Box::pin(async move {
+            router
+                .serve_with_incoming(incoming)
+                .await
+                .map_err(Error::from)
+        }))
+    }
+
+    async fn connect(&self, endpoint: Endpoint) -> Result<Channel> {
+        Ok(endpoint.connect().await?)
+    }
+}
+
+/// Reaches nodes in the same process through in-memory pipes, without
+/// opening sockets.
+///
+/// Nodes are found by the authority of their public address, so every node
+/// sharing a transport needs a distinct public address. A node stops being
+/// reachable once its serving future is dropped.
+///
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransport {
+    listeners: Arc<Mutex<HashMap<String, UnboundedSender<DuplexStream>>>>,
+}
+
+impl MemoryTransport {
+    /// Creates a transport with no nodes bound.
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a pipe to the node bound to an authority, or fails with
+    /// `ConnectionRefused` if no node is serving there.
+    pub(crate) fn open(&self, authority: &str) -> io::Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+
+        let listeners = self
+            .listeners
+            .lock()
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        listeners
+            .get(authority)
+            .and_then(|listener| listener.send(server).ok())
+            .map(|_| client)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::ConnectionRefused, authority))
+    }
+}
+
+pub(crate) fn authority(uri: &Uri) -> Result<String> {
+    uri.authority()
+        .map(|authority| authority.to_string())
+        .ok_or_else(|| Error::Config(format!("address without authority: {}", uri)))
+}
+
+#[tonic::async_trait]
+impl Transport for MemoryTransport {
+    fn bind(&self, _addr: SocketAddr, pub_addr: &str, router: Router) -> Result<ServeFuture> {
+        let uri: Uri = pub_addr
+            .parse()
+            .map_err(|_| Error::Config(format!("invalid public address: {}", pub_addr)))?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.listeners.lock()?.insert(authority(&uri)?, tx);
+
+        let incoming = UnboundedReceiverStream::new(rx).map(Ok::<_, io::Error>);
+        Ok(Box::pin(async move {
+            router
+                .serve_with_incoming(incoming)
+                .await
+                .map_err(Error::from)
+        }))
+    }
+
+    async fn connect(&self, endpoint: Endpoint) -> Result<Channel> {
+        Ok(endpoint.connect_with_connector(self.clone()).await?)
+    }
+}
+
+impl Service<Uri> for MemoryTransport {
+    type Response = DuplexStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<DuplexStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let res = authority(&uri)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+            .and_then(|authority| self.open(&authority));
+        Box::pin(async move { res })
+    }
+}
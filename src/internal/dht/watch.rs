@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+use super::{service::grpc::*, store::Version};
+
+/// A change observed on a watched key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+    /// The key was set to a new value.
+    Set { value: Vec<u8>, version: Version },
+    /// The key was deleted.
+    Delete { version: Version },
+    /// The key expired.
+    Expire { version: Version },
+}
+
+impl From<WatchResponse> for WatchEvent {
+    fn from(response: WatchResponse) -> Self {
+        let version = response.version.unwrap_or_default().into();
+
+        match WatchEventType::try_from(response.event_type).unwrap_or(WatchEventType::Updated) {
+            WatchEventType::Updated => WatchEvent::Set {
+                value: response.value.unwrap_or_default(),
+                version,
+            },
+            WatchEventType::Deleted => WatchEvent::Delete { version },
+            WatchEventType::Expired => WatchEvent::Expire { version },
+        }
+    }
+}
+
+const WATCH_CHANNEL_CAPACITY: usize = 64;
+
+/// Keeps the subscribers of every watched key owned by the node.
+#[derive(Debug, Default)]
+pub struct Watchers {
+    senders: HashMap<(u64, Vec<u8>), broadcast::Sender<WatchResponse>>,
+}
+
+impl Watchers {
+    /// Subscribes to the events of a key.
+    pub fn subscribe(&mut self, id: u64, key: &[u8]) -> broadcast::Receiver<WatchResponse> {
+        self.senders
+            .entry((id, key.to_vec()))
+            .or_insert_with(|| broadcast::channel(WATCH_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// Sends an event to the subscribers of its key, forgetting the key once
+    /// it has no subscribers left.
+    pub fn notify(&mut self, event: WatchResponse) {
+        let watched = (event.key, event.raw_key.clone());
+
+        if let Some(sender) = self.senders.get(&watched) {
+            if sender.send(event).is_err() {
+                self.senders.remove(&watched);
+            }
+        }
+    }
+
+    /// Drops the subscribers of every key whose ID satisfies the filter,
+    /// closing their streams so that they can be re-established on the new
+    /// owner.
+    pub fn close<F>(&mut self, f: F)
+    where
+        F: Fn(u64) -> bool,
+    {
+        self.senders.retain(|(id, _), _| !f(*id));
+    }
+}
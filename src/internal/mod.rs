@@ -1,3 +1,4 @@
+pub mod blob;
 pub mod dht;
 pub mod hring;
 pub mod pastry;
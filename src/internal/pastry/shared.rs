@@ -1,3 +1,16 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+use crate::{
+    error::*,
+    internal::dht::{
+        admission::Admission,
+        auth::Authenticator,
+        trace::SpanExporter,
+        transport::{TcpTransport, Transport},
+    },
+};
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct KeyValuePair<T, U> {
     pub key: T,
@@ -18,11 +31,113 @@ impl<T, U> KeyValuePair<T, U> {
     }
 }
 
+/// Timeouts used when connecting to other nodes.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeouts {
+    /// Time to wait for a connection to be established.
+    pub connect: Duration,
+    /// Time to wait before retrying a failed connection.
+    pub retry_interval: Duration,
+    /// Connection attempts made before giving up.
+    pub max_retries: usize,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_secs(1),
+            retry_interval: Duration::from_secs(1),
+            max_retries: 10,
+        }
+    }
+}
+
+/// TLS settings of a node's server and of the connections it opens to
+/// other nodes, or of a client's connections to nodes.
+///
+/// Peers are verified against the CA certificate if one is set, or against
+/// the system's trusted roots otherwise. Setting a CA also makes the server
+/// require clients to present a certificate signed by it (mutual TLS).
+///
+#[derive(Debug, Clone, Default)]
+pub struct Tls {
+    /// The certificate chain and private key presented to peers.
+    pub identity: Option<Identity>,
+    /// The CA certificate peers are verified against.
+    pub ca: Option<Certificate>,
+}
+
+impl Tls {
+    /// Creates TLS settings without an identity or CA.
+    ///
+    pub fn new() -> Self {
+        Tls::default()
+    }
+
+    /// Sets the certificate chain and private key presented to peers.
+    ///
+    /// # Arguments
+    ///
+    /// * `cert` - The PEM encoded certificate chain.
+    /// * `key` - The PEM encoded private key.
+    ///
+    pub fn with_identity(mut self, cert: impl AsRef<[u8]>, key: impl AsRef<[u8]>) -> Self {
+        self.identity = Some(Identity::from_pem(cert, key));
+        self
+    }
+
+    /// Sets the CA certificate peers are verified against.
+    ///
+    /// # Arguments
+    ///
+    /// * `ca` - The PEM encoded CA certificate.
+    ///
+    pub fn with_ca(mut self, ca: impl AsRef<[u8]>) -> Self {
+        self.ca = Some(Certificate::from_pem(ca));
+        self
+    }
+
+    pub(crate) fn server_config(&self) -> Result<ServerTlsConfig> {
+        let identity = self.identity.clone().ok_or_else(|| {
+            Error::Config("a TLS certificate and key are required to serve over TLS".into())
+        })?;
+
+        let config = ServerTlsConfig::new().identity(identity);
+        Ok(match &self.ca {
+            Some(ca) => config.client_ca_root(ca.clone()),
+            None => config,
+        })
+    }
+
+    pub(crate) fn client_config(&self) -> ClientTlsConfig {
+        let mut config = ClientTlsConfig::new();
+        if let Some(identity) = &self.identity {
+            config = config.identity(identity.clone());
+        }
+        if let Some(ca) = &self.ca {
+            config = config.ca_certificate(ca.clone());
+        }
+        config
+    }
+}
+
 /// Pastry Network Config
 ///
 #[derive(Debug, Clone)]
 pub struct Config {
     pub k: usize,
+    pub timeouts: Timeouts,
+    pub metrics_addr: Option<SocketAddr>,
+    pub http_addr: Option<SocketAddr>,
+    pub resp_addr: Option<SocketAddr>,
+    pub memcache_addr: Option<SocketAddr>,
+    pub span_exporter: Option<Arc<dyn SpanExporter>>,
+    pub tls: Option<Tls>,
+    pub admission: Admission,
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    pub peer_token: Option<String>,
+    pub transport: Arc<dyn Transport>,
 }
 
 impl Config {
@@ -38,6 +153,149 @@ impl Config {
     /// A new Pastry `Config` object.
     ///
     pub fn new(leaf_set_k: usize) -> Self {
-        Config { k: leaf_set_k }
+        Config {
+            k: leaf_set_k,
+            timeouts: Timeouts::default(),
+            metrics_addr: None,
+            http_addr: None,
+            resp_addr: None,
+            memcache_addr: None,
+            span_exporter: None,
+            tls: None,
+            admission: Admission::Open,
+            authenticator: None,
+            peer_token: None,
+            transport: Arc::new(TcpTransport),
+        }
+    }
+
+    /// Sets the timeouts used when connecting to other nodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeouts` - The connection timeouts and retry policy.
+    ///
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Serves the node's Prometheus metrics over HTTP on `/metrics`.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the socket the metrics listener binds to.
+    ///
+    pub fn with_metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Serves key-value operations and the node's state as REST endpoints
+    /// over HTTP.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the socket the HTTP gateway binds to.
+    ///
+    pub fn with_http_addr(mut self, addr: SocketAddr) -> Self {
+        self.http_addr = Some(addr);
+        self
+    }
+
+    /// Serves a Redis-compatible listener, so Redis clients can get and set
+    /// keys in the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the socket the RESP listener binds to.
+    ///
+    pub fn with_resp_addr(mut self, addr: SocketAddr) -> Self {
+        self.resp_addr = Some(addr);
+        self
+    }
+
+    /// Serves a listener speaking the memcached text protocol, so memcached
+    /// clients can use the network as a cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address of the socket the memcached listener binds to.
+    ///
+    pub fn with_memcache_addr(mut self, addr: SocketAddr) -> Self {
+        self.memcache_addr = Some(addr);
+        self
+    }
+
+    /// Records the spans of the requests handled by the node.
+    ///
+    /// # Arguments
+    ///
+    /// * `exporter` - The exporter every finished span is sent to.
+    ///
+    pub fn with_span_exporter(mut self, exporter: Arc<dyn SpanExporter>) -> Self {
+        self.span_exporter = Some(exporter);
+        self
+    }
+
+    /// Serves the node over TLS and connects to other nodes over TLS. The
+    /// node's public address uses the `https` scheme.
+    ///
+    /// # Arguments
+    ///
+    /// * `tls` - The node's identity and the CA its peers are verified
+    ///   against.
+    ///
+    pub fn with_tls(mut self, tls: Tls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets how the node verifies that peers own the IDs they claim.
+    ///
+    /// # Arguments
+    ///
+    /// * `admission` - The admission policy, shared by every node in the
+    ///   network.
+    ///
+    pub fn with_admission(mut self, admission: Admission) -> Self {
+        self.admission = admission;
+        self
+    }
+
+    /// Authenticates every request served by the node and authorizes it by
+    /// the sender's role. Requests without credentials are rejected, so the
+    /// node also needs a peer token accepted by the other nodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `authenticator` - Identifies the sender of a request.
+    ///
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Sets the bearer token the node sends to other nodes.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - A token the other nodes authenticate as a peer.
+    ///
+    pub fn with_peer_token(mut self, token: &str) -> Self {
+        self.peer_token = Some(token.to_owned());
+        self
+    }
+
+    /// Sets how the node serves requests and reaches other nodes. Nodes use
+    /// TCP sockets by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `transport` - The transport shared by the nodes the node talks to.
+    ///
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
     }
 }
@@ -29,7 +29,8 @@ impl<T: Clone> RoutingTable<T> {
     }
 
     /// Inserts a value into the table, overwriting the previous if not empty.
-    pub fn insert(&mut self, key: u64, value: T) -> Result<()> {
+    /// Returns the overwritten value, if any.
+    pub fn insert(&mut self, key: u64, value: T) -> Result<Option<T>> {
         let new_pair = KeyValuePair::new(key, value);
 
         for i in 0..U64_HEX_NUM_OF_DIGITS as usize {
@@ -46,16 +47,17 @@ impl<T: Clone> RoutingTable<T> {
                         Some(self.node.clone());
                 }
 
-                self.table[i][key_digit as usize] = Some(new_pair);
-                break;
+                let previous = self.table[i][key_digit as usize].replace(new_pair);
+                return Ok(previous.map(|kv| kv.value));
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    /// Removes a value from the table if it exists.
-    pub fn remove(&mut self, key: u64) -> Result<()> {
+    /// Removes a value from the table if it exists. Returns the removed value,
+    /// if any.
+    pub fn remove(&mut self, key: u64) -> Result<Option<T>> {
         for i in 0..U64_HEX_NUM_OF_DIGITS as usize {
             let table_digit = get_nth_digit_in_u64_hex(self.node.key, i)?;
             let key_digit = get_nth_digit_in_u64_hex(key, i)?;
@@ -65,12 +67,12 @@ impl<T: Clone> RoutingTable<T> {
             }
 
             if table_digit != key_digit {
-                self.table[i][key_digit as usize] = None;
-                break;
+                let removed = self.table[i][key_digit as usize].take();
+                return Ok(removed.map(|kv| kv.value));
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
     /// Returns the next node to route the request to in the Pastry algorithm and the number of
@@ -177,7 +179,12 @@ mod tests {
         assert_eq!(t.table[6][0], Some(kv));
 
         let kv = KeyValuePair::new(0xFEDCBA9400000000, 0xFEDCBA9400000000);
-        t.insert(kv.key, kv.value)?;
+        assert_eq!(t.insert(kv.key, kv.value)?, None);
+        assert_eq!(t.table[7][4], Some(kv));
+
+        // the previous entry is returned when overwritten
+        let kv = KeyValuePair::new(0xFEDCBA9411111111, 0xFEDCBA9411111111);
+        assert_eq!(t.insert(kv.key, kv.value)?, Some(0xFEDCBA9400000000));
         assert_eq!(t.table[7][4], Some(kv));
 
         Ok(())
@@ -191,8 +198,9 @@ mod tests {
         let kv2 = KeyValuePair::new(0xFEDCBA9400000000, 0xFEDCBA9400000000);
         t.insert(kv2.key, kv2.value)?;
 
-        t.remove(kv1.key)?;
+        assert_eq!(t.remove(kv1.key)?, Some(kv1.value));
         assert_eq!(t.table[6][0], None);
+        assert_eq!(t.remove(kv1.key)?, None);
 
         Ok(())
     }
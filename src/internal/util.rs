@@ -1,3 +1,5 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use crate::error::Result;
 
 pub const HEX_BASE: u32 = 16;
@@ -50,6 +52,18 @@ pub fn get_num_matched_digits(a: u64, b: u64) -> Result<u32> {
     Ok(U64_HEX_NUM_OF_DIGITS - 1)
 }
 
+/// Gets the milliseconds elapsed from the Unix epoch to a time, as times
+/// are sent between nodes.
+pub fn get_unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Gets the time a number of milliseconds after the Unix epoch.
+pub fn from_unix_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
 pub fn get_distance_between_unsigned<T>(a: T, b: T) -> T
 where
     T: PartialOrd + std::ops::Sub<Output = T>,
@@ -1,6 +1,24 @@
+pub mod admin;
 pub mod error;
 mod internal;
 
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod builder;
 pub mod client;
 pub mod node;
-pub use internal::pastry::shared::Config;
+pub mod ring;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+pub mod topology;
+pub use internal::dht::admission::Admission;
+pub use internal::dht::auth::{Authenticator, Operation, Principal, Role, TokenAuthenticator};
+pub use internal::dht::events::NodeEvent;
+pub use internal::dht::node::{Lookup, NodeInfo};
+pub use internal::dht::store::{Entry, Version};
+pub use internal::dht::trace::{InMemorySpanExporter, Span, SpanExporter, TraceContext};
+pub use internal::dht::transport::{MemoryTransport, TcpTransport, Transport};
+pub use internal::dht::watch::WatchEvent;
+pub use internal::pastry::shared::{Config, Timeouts, Tls};
@@ -1,14 +1,16 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
-use tonic::Request;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 
 use crate::{
+    admin::StoreStats,
     error::*,
     internal::{
         dht::{node::Node, service::grpc::*},
         hring::hasher::Sha256Hasher,
         pastry::shared::Config,
     },
+    Entry, Lookup, NodeEvent, Version,
 };
 
 /// An instance of a Pastry node.
@@ -21,7 +23,7 @@ pub struct PastryNode {
 // Basic Node methods
 impl PastryNode {
     /// Registers a new Pastry node which will be available publicly on
-    /// http://hostname:port
+    /// http://hostname:port, or https://hostname:port if the config sets TLS
     ///
     /// # Arguments
     ///
@@ -39,6 +41,31 @@ impl PastryNode {
         })
     }
 
+    /// Registers a new Pastry node with the given ID instead of one derived
+    /// from its public address.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Pastry network configuration.
+    /// * `addr` - The address of the socket to listen on.
+    /// * `pub_addr` - The address the node will be exposed on.
+    /// * `id` - The node's ID.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the newly registered node.
+    ///
+    pub fn from_id(
+        config: Config,
+        addr: SocketAddr,
+        pub_addr: SocketAddr,
+        id: u64,
+    ) -> Result<Self> {
+        Ok(PastryNode {
+            node: Node::from_id(config, addr, pub_addr, id)?,
+        })
+    }
+
     /// Connects to Pastry network via bootstrap node and serves node server.
     /// Consumes node.
     ///
@@ -65,6 +92,139 @@ impl PastryNode {
     pub fn get_public_address(&self) -> String {
         self.node.pub_addr.clone()
     }
+
+    /// Subscribes to the node's membership, topology and key events.
+    ///
+    /// Only events emitted after subscribing are received. A subscriber
+    /// lagging behind by too many events misses the oldest ones.
+    ///
+    /// # Returns
+    ///
+    /// A stream of the node's events, ending when the node is dropped.
+    ///
+    pub fn events(&self) -> impl Stream<Item = NodeEvent> {
+        BroadcastStream::new(self.node.state.events.subscribe()).filter_map(|event| event.ok())
+    }
+
+    /// Lists the original keys of the entries stored locally in this node,
+    /// in key order.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - The key to start after, or `None` to start at the first key.
+    /// * `limit` - The maximum number of keys returned.
+    ///
+    pub async fn scan_local_keys(&self, from: Option<&[u8]>, limit: usize) -> Vec<Vec<u8>> {
+        self.node
+            .state
+            .store
+            .read()
+            .await
+            .scan(from, limit)
+            .into_iter()
+            .map(|(_, entry)| entry.key.clone())
+            .collect()
+    }
+}
+
+// Local store methods
+impl PastryNode {
+    /// Gets the entry of a key stored locally in this node, without routing
+    /// the request through the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The original key.
+    ///
+    /// # Returns
+    ///
+    /// The entry, or `None` if the key is not stored in this node.
+    ///
+    pub async fn get_local(&self, key: &[u8]) -> Option<Entry> {
+        self.node
+            .state
+            .store
+            .read()
+            .await
+            .get(&Sha256Hasher::hash_once(key), key)
+            .cloned()
+    }
+
+    /// Lists the entries stored locally in this node, in key order.
+    ///
+    pub async fn local_entries(&self) -> Vec<Entry> {
+        self.node
+            .state
+            .store
+            .read()
+            .await
+            .scan(None, usize::MAX)
+            .into_iter()
+            .map(|(_, entry)| entry.clone())
+            .collect()
+    }
+
+    /// Checks whether this node owns a key, that is whether its ID is the
+    /// closest to the key's in the node's leaf set.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The original key.
+    ///
+    pub async fn is_owner(&self, key: &[u8]) -> bool {
+        self.node.is_owner(Sha256Hasher::hash_once(key)).await
+    }
+
+    /// Counts the keys stored locally in this node and the bytes of their
+    /// keys and values.
+    ///
+    pub async fn local_store_stats(&self) -> StoreStats {
+        self.node.state.store.read().await.stats()
+    }
+
+    /// Registers a callback called with the entries received from a neighbor
+    /// when this node joins the network next to it.
+    ///
+    /// Callbacks are called after the entries were stored and must not
+    /// block.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The function called with the received entries.
+    ///
+    pub async fn on_keys_received<F>(&self, callback: F)
+    where
+        F: Fn(&[Entry]) + Send + Sync + 'static,
+    {
+        self.node
+            .state
+            .hooks
+            .write()
+            .await
+            .on_received(Arc::new(callback));
+    }
+
+    /// Registers a callback called with the entries handed off to a
+    /// neighbor joining the network next to this node, once they were
+    /// removed from it.
+    ///
+    /// Callbacks must not block.
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The function called with the handed off entries.
+    ///
+    pub async fn on_keys_handed_off<F>(&self, callback: F)
+    where
+        F: Fn(&[Entry]) + Send + Sync + 'static,
+    {
+        self.node
+            .state
+            .hooks
+            .write()
+            .await
+            .on_handed_off(Arc::new(callback));
+    }
 }
 
 // gRPC methods
@@ -90,20 +250,49 @@ impl PastryNode {
     pub async fn get_kv(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let response = self
             .node
-            .query(Request::new(QueryRequest {
-                from_id: 0,
-                matched_digits: 0,
-                hops: 0,
-                query_type: QueryType::Get.into(),
-                key: Sha256Hasher::hash_once(key),
-                value: None,
-            }))
+            .query(
+                self.node
+                    .local_request(QueryRequest::new(QueryType::Get, key.to_vec(), None)),
+            )
             .await?
             .into_inner();
 
         Ok(response.value)
     }
 
+    /// Retrieves a value and its version for the given key stored in the
+    /// Pastry network.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key for which the value is
+    ///   requested.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` which is:
+    ///
+    /// - `Ok(Some((Vec<u8>, Version)))` if the key exists, containing the
+    ///   associated value and the version it was written with.
+    /// - `Ok(None)` if the key does not exist.
+    /// - `Err(e)` where `e` encapsulates any error encountered during the
+    ///   operation.
+    ///
+    pub async fn get_kv_versioned(&self, key: &[u8]) -> Result<Option<(Vec<u8>, Version)>> {
+        let response = self
+            .node
+            .query(
+                self.node
+                    .local_request(QueryRequest::new(QueryType::Get, key.to_vec(), None)),
+            )
+            .await?
+            .into_inner();
+
+        Ok(response
+            .value
+            .map(|value| (value, response.version.unwrap_or_default().into())))
+    }
+
     /// Sets a value for a given key in the Pastry network.
     ///
     /// # Arguments
@@ -125,14 +314,11 @@ impl PastryNode {
     pub async fn set_kv(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
         let response = self
             .node
-            .query(Request::new(QueryRequest {
-                from_id: 0,
-                matched_digits: 0,
-                hops: 0,
-                query_type: QueryType::Set.into(),
-                key: Sha256Hasher::hash_once(key),
-                value: Some(value.to_vec()),
-            }))
+            .query(self.node.local_request(QueryRequest::new(
+                QueryType::Set,
+                key.to_vec(),
+                Some(value.to_vec()),
+            )))
             .await?
             .into_inner();
 
@@ -157,17 +343,87 @@ impl PastryNode {
     pub async fn delete_kv(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let response = self
             .node
-            .query(Request::new(QueryRequest {
-                from_id: 0,
-                matched_digits: 0,
-                hops: 0,
-                query_type: QueryType::Delete.into(),
-                key: Sha256Hasher::hash_once(key),
-                value: None,
-            }))
+            .query(self.node.local_request(QueryRequest::new(
+                QueryType::Delete,
+                key.to_vec(),
+                None,
+            )))
             .await?
             .into_inner();
 
         Ok(response.value)
     }
+
+    /// Finds the node owning the given key, without reading or writing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - A slice of bytes representing the key whose owner is
+    ///   requested.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the owner, its leaf set and the number of hops
+    /// the lookup was routed through.
+    ///
+    pub async fn lookup(&self, key: &[u8]) -> Result<Lookup> {
+        let response = self
+            .node
+            .lookup(self.node.local_request(LookupRequest::new(key)))
+            .await?
+            .into_inner();
+
+        Ok(response.into())
+    }
+
+    /// Retrieves the values associated with many keys in a single batch.
+    ///
+    /// Keys are grouped by their next hop and each group is forwarded as a
+    /// single request, which is split again at every hop.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys` - The keys for which the values are requested.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing, in the same order as `keys`, the value of each
+    /// key, `None` if the key does not exist, or the error its query failed
+    /// with.
+    ///
+    pub async fn get_many(&self, keys: &[&[u8]]) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        Ok(self
+            .node
+            .batch_query(self.node.local_request(BatchQueryRequest::get(keys)))
+            .await?
+            .into_inner()
+            .into_results())
+    }
+
+    /// Sets the values of many keys in a single batch.
+    ///
+    /// Keys are grouped by their next hop and each group is forwarded as a
+    /// single request, which is split again at every hop.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The key-value pairs to be set.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing, in the same order as `entries`, the value each
+    /// key had before, `None` if a new entry was created, or the error its
+    /// query failed with.
+    ///
+    pub async fn set_many(
+        &self,
+        entries: &[(&[u8], &[u8])],
+    ) -> Result<Vec<Result<Option<Vec<u8>>>>> {
+        Ok(self
+            .node
+            .batch_query(self.node.local_request(BatchQueryRequest::set(entries)))
+            .await?
+            .into_inner()
+            .into_results())
+    }
 }
@@ -0,0 +1,504 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::{self, Display},
+    time::Duration,
+};
+use tokio::time::timeout;
+
+use crate::{
+    admin::{NodeDump, StoredKey},
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::util::{get_nth_digit_in_u64_hex, get_num_matched_digits},
+    NodeInfo,
+};
+
+const CRAWL_TIMEOUT_SECONDS: u64 = 5;
+const LIST_KEYS_PAGE_SIZE: usize = 1000;
+
+/// A view of the whole ring, gathered by crawling it from a seed node.
+///
+#[derive(Debug, Clone, Default)]
+pub struct RingSnapshot {
+    /// The state of every reachable node, by ID.
+    pub nodes: BTreeMap<u64, NodeDump>,
+    /// The keys stored in every reachable node, if crawled with keys.
+    pub keys: HashMap<u64, Vec<StoredKey>>,
+    /// Nodes referenced by others that could not be reached, by ID.
+    pub unreachable: BTreeMap<u64, NodeInfo>,
+}
+
+/// A broken ring invariant.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A node's leaf set differs from its closest nodes in the ring.
+    LeafSetMismatch {
+        node: u64,
+        missing: Vec<u64>,
+        unexpected: Vec<u64>,
+    },
+    /// A node lists a neighbor in its leaf set that does not list it back.
+    AsymmetricLeafSet { node: u64, neighbor: u64 },
+    /// A node's successor does not have it as its predecessor.
+    SuccessorMismatch {
+        node: u64,
+        successor: u64,
+        predecessor: Option<u64>,
+    },
+    /// A node lists an unreachable node in its leaf set.
+    DeadLeafEntry { node: u64, entry: u64 },
+    /// A node lists an unreachable node in its routing table.
+    DeadRoutingEntry {
+        node: u64,
+        row: usize,
+        column: usize,
+        entry: u64,
+    },
+    /// A routing table entry does not share the prefix of its row and
+    /// column.
+    MisplacedRoutingEntry {
+        node: u64,
+        row: usize,
+        column: usize,
+        entry: u64,
+    },
+    /// A node stores a key outside its ownership range.
+    MisplacedKey {
+        node: u64,
+        key: Vec<u8>,
+        id: u64,
+        owner: u64,
+    },
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids = |ids: &[u64]| {
+            ids.iter()
+                .map(|id| format!("#{:016X}", id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match self {
+            Violation::LeafSetMismatch {
+                node,
+                missing,
+                unexpected,
+            } => write!(
+                f,
+                "#{:016X}: leaf set is missing [{}] and has unexpected [{}]",
+                node,
+                ids(missing),
+                ids(unexpected)
+            ),
+            Violation::AsymmetricLeafSet { node, neighbor } => write!(
+                f,
+                "#{:016X}: leaf set lists #{:016X}, which does not list it back",
+                node, neighbor
+            ),
+            Violation::SuccessorMismatch {
+                node,
+                successor,
+                predecessor,
+            } => write!(
+                f,
+                "#{:016X}: successor #{:016X} has predecessor {}",
+                node,
+                successor,
+                predecessor.map_or("none".to_owned(), |id| format!("#{:016X}", id))
+            ),
+            Violation::DeadLeafEntry { node, entry } => write!(
+                f,
+                "#{:016X}: leaf set lists unreachable node #{:016X}",
+                node, entry
+            ),
+            Violation::DeadRoutingEntry {
+                node,
+                row,
+                column,
+                entry,
+            } => write!(
+                f,
+                "#{:016X}: routing table entry ({:X}, {:X}) is unreachable node #{:016X}",
+                node, row, column, entry
+            ),
+            Violation::MisplacedRoutingEntry {
+                node,
+                row,
+                column,
+                entry,
+            } => write!(
+                f,
+                "#{:016X}: routing table entry ({:X}, {:X}) holds #{:016X}",
+                node, row, column, entry
+            ),
+            Violation::MisplacedKey {
+                node,
+                key,
+                id,
+                owner,
+            } => write!(
+                f,
+                "#{:016X}: stores key {:?} ({:016X}) owned by #{:016X}",
+                node,
+                String::from_utf8_lossy(key),
+                id,
+                owner
+            ),
+        }
+    }
+}
+
+/// Crawls the ring from a seed node, following the leaf sets and routing
+/// tables of every node reached.
+///
+/// # Arguments
+///
+/// * `seed` - The public address of any node in the ring.
+/// * `config` - The TLS settings and credentials to connect with.
+/// * `with_keys` - Whether to also list the keys stored in every node.
+///
+/// # Returns
+///
+/// A Result containing the snapshot of the ring, or an error if the seed
+/// node is unreachable.
+///
+pub async fn crawl(seed: &str, config: &ClientConfig, with_keys: bool) -> Result<RingSnapshot> {
+    let mut snapshot = RingSnapshot::default();
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from([seed.to_owned()]);
+    visited.insert(seed.to_owned());
+
+    while let Some(addr) = queue.pop_front() {
+        let (dump, keys) = match crawl_node(&addr, config, with_keys).await {
+            Ok(result) => result,
+            Err(err) if addr == seed => return Err(err),
+            Err(err) => {
+                log::warn!("Node {} is unreachable: {}", addr, err);
+                continue;
+            }
+        };
+
+        let neighbors = dump
+            .leaf_set
+            .iter()
+            .chain(dump.routing_table.iter().flatten().flatten());
+        for neighbor in neighbors {
+            if visited.insert(neighbor.pub_addr.clone()) {
+                queue.push_back(neighbor.pub_addr.clone());
+            }
+        }
+
+        if let Some(keys) = keys {
+            snapshot.keys.insert(dump.id, keys);
+        }
+        snapshot.nodes.insert(dump.id, dump);
+    }
+
+    // every referenced node that was not reached is unreachable
+    for dump in snapshot.nodes.values() {
+        let referenced = dump
+            .leaf_set
+            .iter()
+            .chain(dump.routing_table.iter().flatten().flatten());
+        for node in referenced {
+            if !snapshot.nodes.contains_key(&node.id) {
+                snapshot.unreachable.insert(node.id, node.clone());
+            }
+        }
+    }
+
+    Ok(snapshot)
+}
+
+async fn crawl_node(
+    addr: &str,
+    config: &ClientConfig,
+    with_keys: bool,
+) -> Result<(NodeDump, Option<Vec<StoredKey>>)> {
+    let crawl = async {
+        let mut client = PastryClient::connect_with_config(addr, config).await?;
+        let dump = client.dump_state().await?;
+
+        if !with_keys {
+            return Ok((dump, None));
+        }
+
+        let mut keys = Vec::new();
+        let mut start_after = None;
+        loop {
+            let page = client
+                .list_keys(start_after.as_deref(), LIST_KEYS_PAGE_SIZE)
+                .await?;
+            keys.extend(page.keys);
+
+            match page.next {
+                Some(next) => start_after = Some(next),
+                None => break,
+            }
+        }
+
+        Ok((dump, Some(keys)))
+    };
+
+    timeout(Duration::from_secs(CRAWL_TIMEOUT_SECONDS), crawl)
+        .await
+        .map_err(|_| Error::Internal(format!("crawling {} timed out", addr)))?
+}
+
+impl RingSnapshot {
+    /// Gets the node responsible for a key: the closest node counter
+    /// clockwise from it.
+    ///
+    pub fn owner(&self, key: u64) -> Option<u64> {
+        self.nodes
+            .range(..=key)
+            .next_back()
+            .or_else(|| self.nodes.iter().next_back())
+            .map(|(&id, _)| id)
+    }
+
+    /// Verifies the invariants of the ring: leaf sets hold the closest nodes
+    /// and are symmetric, successors and predecessors agree, routing table
+    /// entries are live and correctly prefixed, and every key is stored in
+    /// its owner.
+    ///
+    /// # Returns
+    ///
+    /// The violations found, empty if the ring is healthy.
+    ///
+    pub fn check(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let ring: Vec<u64> = self.nodes.keys().copied().collect();
+
+        for (idx, (&id, dump)) in self.nodes.iter().enumerate() {
+            let leaf_set: HashSet<u64> = dump.leaf_set.iter().map(|e| e.id).collect();
+
+            // leaf set holds exactly the k closest nodes on each side
+            let expected = expected_leaf_set(&ring, idx, dump.k);
+            let mut missing: Vec<u64> = expected.difference(&leaf_set).copied().collect();
+            let mut unexpected: Vec<u64> = leaf_set
+                .difference(&expected)
+                .filter(|id| !self.unreachable.contains_key(id))
+                .copied()
+                .collect();
+            if !missing.is_empty() || !unexpected.is_empty() {
+                missing.sort();
+                unexpected.sort();
+                violations.push(Violation::LeafSetMismatch {
+                    node: id,
+                    missing,
+                    unexpected,
+                });
+            }
+
+            for entry in &dump.leaf_set {
+                if self.unreachable.contains_key(&entry.id) {
+                    violations.push(Violation::DeadLeafEntry {
+                        node: id,
+                        entry: entry.id,
+                    });
+                } else if let Some(neighbor) = self.nodes.get(&entry.id) {
+                    if !neighbor.leaf_set.iter().any(|e| e.id == id) {
+                        violations.push(Violation::AsymmetricLeafSet {
+                            node: id,
+                            neighbor: entry.id,
+                        });
+                    }
+                }
+            }
+
+            // successor has this node as its predecessor
+            if let Some(successor) = successor(dump) {
+                if let Some(successor_dump) = self.nodes.get(&successor) {
+                    let predecessor = predecessor(successor_dump);
+                    if predecessor != Some(id) {
+                        violations.push(Violation::SuccessorMismatch {
+                            node: id,
+                            successor,
+                            predecessor,
+                        });
+                    }
+                }
+            }
+
+            for (row, entries) in dump.routing_table.iter().enumerate() {
+                for (column, entry) in entries.iter().enumerate() {
+                    let Some(entry) = entry else {
+                        continue;
+                    };
+
+                    if self.unreachable.contains_key(&entry.id) {
+                        violations.push(Violation::DeadRoutingEntry {
+                            node: id,
+                            row,
+                            column,
+                            entry: entry.id,
+                        });
+                    }
+
+                    if !is_routing_entry_valid(id, row, column, entry.id) {
+                        violations.push(Violation::MisplacedRoutingEntry {
+                            node: id,
+                            row,
+                            column,
+                            entry: entry.id,
+                        });
+                    }
+                }
+            }
+
+            for key in self.keys.get(&id).into_iter().flatten() {
+                if let Some(owner) = self.owner(key.id) {
+                    if owner != id {
+                        violations.push(Violation::MisplacedKey {
+                            node: id,
+                            key: key.key.clone(),
+                            id: key.id,
+                            owner,
+                        });
+                    }
+                }
+            }
+        }
+
+        violations
+    }
+}
+
+/// Gets the node and its `k` closest nodes on each side in a sorted ring.
+fn expected_leaf_set(ring: &[u64], idx: usize, k: usize) -> HashSet<u64> {
+    if ring.len() <= 2 * k + 1 {
+        return ring.iter().copied().collect();
+    }
+
+    (0..=2 * k)
+        .map(|offset| ring[(idx + ring.len() + offset - k) % ring.len()])
+        .collect()
+}
+
+fn successor(dump: &NodeDump) -> Option<u64> {
+    let idx = dump.leaf_set.iter().position(|e| e.id == dump.id)?;
+    let next = &dump.leaf_set[(idx + 1) % dump.leaf_set.len()];
+    (next.id != dump.id).then_some(next.id)
+}
+
+fn predecessor(dump: &NodeDump) -> Option<u64> {
+    let len = dump.leaf_set.len();
+    let idx = dump.leaf_set.iter().position(|e| e.id == dump.id)?;
+    let prev = &dump.leaf_set[(idx + len - 1) % len];
+    (prev.id != dump.id).then_some(prev.id)
+}
+
+/// Checks that an entry in row `row` and column `column` shares the first
+/// `row` digits with the node and has `column` as its next digit.
+fn is_routing_entry_valid(node: u64, row: usize, column: usize, entry: u64) -> bool {
+    let digit = get_nth_digit_in_u64_hex(entry, row).ok();
+    let matched = get_num_matched_digits(node, entry).ok();
+
+    digit == Some(column as u32) && (entry == node || matched == Some(row as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::{NodeState, StoreStats};
+
+    fn dump(id: u64, k: usize, leaf_set: &[u64]) -> NodeDump {
+        let info = |id: u64| NodeInfo::new(id, &format!("http://{:016X}", id));
+        let mut leaf_set = leaf_set.to_vec();
+        leaf_set.sort();
+
+        NodeDump {
+            id,
+            pub_addr: info(id).pub_addr,
+            state: NodeState::RoutingRequests,
+            uptime: Duration::ZERO,
+            version: String::new(),
+            k,
+            leaf_set: leaf_set.into_iter().map(info).collect(),
+            routing_table: vec![vec![None; 16]; 16],
+            store: StoreStats::default(),
+            transfers: Vec::new(),
+        }
+    }
+
+    fn snapshot(dumps: Vec<NodeDump>) -> RingSnapshot {
+        RingSnapshot {
+            nodes: dumps.into_iter().map(|d| (d.id, d)).collect(),
+            ..RingSnapshot::default()
+        }
+    }
+
+    #[test]
+    fn test_healthy_ring() {
+        let ring = snapshot(vec![
+            dump(10, 1, &[50, 10, 20]),
+            dump(20, 1, &[10, 20, 30]),
+            dump(30, 1, &[20, 30, 50]),
+            dump(50, 1, &[30, 50, 10]),
+        ]);
+
+        assert_eq!(ring.check(), Vec::new());
+        assert_eq!(ring.owner(5), Some(50));
+        assert_eq!(ring.owner(25), Some(20));
+    }
+
+    #[test]
+    fn test_broken_leaf_sets() {
+        let ring = snapshot(vec![
+            dump(10, 1, &[50, 10, 20]),
+            dump(20, 1, &[10, 20, 50]),
+            dump(30, 1, &[20, 30, 50]),
+            dump(50, 1, &[30, 50, 10]),
+        ]);
+
+        let violations = ring.check();
+        assert!(violations.contains(&Violation::LeafSetMismatch {
+            node: 20,
+            missing: vec![30],
+            unexpected: vec![50],
+        }));
+        assert!(violations.contains(&Violation::AsymmetricLeafSet {
+            node: 30,
+            neighbor: 20,
+        }));
+        assert!(violations.contains(&Violation::SuccessorMismatch {
+            node: 20,
+            successor: 50,
+            predecessor: Some(30),
+        }));
+    }
+
+    #[test]
+    fn test_misplaced_entries() {
+        let mut node = dump(0x1000_0000_0000_0000, 1, &[0x1000_0000_0000_0000]);
+        node.routing_table[0][2] = Some(NodeInfo::new(0x2000_0000_0000_0000, "http://a"));
+        node.routing_table[0][3] = Some(NodeInfo::new(0x4000_0000_0000_0000, "http://b"));
+
+        let mut ring = snapshot(vec![node]);
+        ring.keys.insert(
+            0x1000_0000_0000_0000,
+            vec![StoredKey {
+                id: 0x0100_0000_0000_0000,
+                key: b"key".to_vec(),
+                version: Default::default(),
+                value_size: 0,
+            }],
+        );
+
+        // a single node owns every key
+        let violations = ring.check();
+        assert_eq!(
+            violations,
+            vec![Violation::MisplacedRoutingEntry {
+                node: 0x1000_0000_0000_0000,
+                row: 0,
+                column: 3,
+                entry: 0x4000_0000_0000_0000,
+            }]
+        );
+    }
+}
@@ -0,0 +1,680 @@
+use log::info;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+    sync::watch,
+    task::JoinHandle,
+    time::Instant,
+};
+use tonic::{
+    codegen::Service,
+    transport::{server::Router, Channel, Endpoint, Uri},
+};
+
+use crate::{
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::{
+        dht::{
+            node::Node,
+            transport::{authority, MemoryTransport, ServeFuture, Transport},
+        },
+        hring::hasher::Sha256Hasher,
+    },
+    ring::{self, Violation},
+    Config, NodeInfo,
+};
+
+const SEED_ENV: &str = "PASTRY_SIM_SEED";
+const FIRST_PORT: u16 = 50000;
+const MAX_VIRTUAL_SECONDS: u64 = 3600;
+
+/// Faults injected into the messages between nodes. Every request a node
+/// sends to another is a message.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Faults {
+    /// The probability of a message being lost, so its sender fails to
+    /// reach the destination.
+    pub drop_rate: f64,
+    /// The smallest latency added to a message.
+    pub min_delay: Duration,
+    /// The largest latency added to a message. Latencies are drawn uniformly
+    /// between the bounds.
+    pub max_delay: Duration,
+}
+
+/// A deterministic simulation of a Pastry network.
+///
+/// Nodes run in a single thread on virtual time and reach each other
+/// through in-memory pipes. Node IDs, bootstrap nodes and injected faults
+/// are drawn from a seeded generator, so a scenario run twice with the same
+/// seed makes the same decisions and records the same event log. A failing
+/// scenario is replayed by running it again with the seed it failed with.
+///
+/// ```no_run
+/// # use pastry_dht::{error::Result, sim::{Faults, Simulation}, Config};
+/// # use std::time::Duration;
+/// # fn main() -> Result<()> {
+/// for seed in Simulation::seeds(16) {
+///     Simulation::run(seed, Config::new(4), |mut sim| async move {
+///         sim.add_nodes(32).await?;
+///         sim.set_faults(Faults {
+///             drop_rate: 0.01,
+///             min_delay: Duration::from_millis(1),
+///             max_delay: Duration::from_millis(50),
+///         })?;
+///         let id = sim.random_node().unwrap().id;
+///         sim.crash(id)?;
+///         sim.sleep(Duration::from_secs(10)).await;
+///         assert!(sim.check().await?.is_empty());
+///         Ok(())
+///     })?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+pub struct Simulation {
+    seed: u64,
+    config: Config,
+    rng: StdRng,
+    network: Arc<SimNetwork>,
+    nodes: BTreeMap<u64, SimNode>,
+    next_port: u16,
+}
+
+struct SimNode {
+    info: NodeInfo,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl Simulation {
+    /// The seeds to run a scenario with: `0..count`, or only the seed in the
+    /// `PASTRY_SIM_SEED` environment variable, to replay a failure.
+    ///
+    pub fn seeds(count: u64) -> Box<dyn Iterator<Item = u64>> {
+        match std::env::var(SEED_ENV).ok().and_then(|s| s.parse().ok()) {
+            Some(seed) => Box::new(std::iter::once(seed)),
+            None => Box::new(0..count),
+        }
+    }
+
+    /// Runs a scenario on a fresh single-threaded runtime with virtual time,
+    /// so sleeps and timeouts complete as soon as every task is idle. A
+    /// scenario still running after an hour of virtual time, such as one
+    /// where nodes deadlock, fails.
+    ///
+    /// Must not be called from within a Tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed every random decision is drawn from.
+    /// * `config` - The config of every node. Its transport is replaced by
+    ///   the simulated network.
+    /// * `scenario` - Builds the network, injects faults and checks it.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the event log, or the scenario's error tagged
+    /// with the seed. Panics in the scenario are raised again with the seed
+    /// added to their message.
+    ///
+    pub fn run<F, Fut>(seed: u64, config: Config, scenario: F) -> Result<Vec<String>>
+    where
+        F: FnOnce(Simulation) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()?;
+
+        info!("Running simulation with seed {}", seed);
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            runtime.block_on(async {
+                let sim = Simulation::new(seed, config);
+                let network = sim.network.clone();
+                let limit = Duration::from_secs(MAX_VIRTUAL_SECONDS);
+                match tokio::time::timeout(limit, scenario(sim)).await {
+                    Ok(res) => res.map(|_| network.log()),
+                    Err(_) => Err(Error::Internal(format!(
+                        "stalled after {:?} of virtual time",
+                        limit
+                    ))),
+                }
+            })
+        }));
+
+        match res {
+            Ok(Ok(log)) => Ok(log),
+            Ok(Err(err)) => Err(Error::Internal(format!(
+                "simulation with seed {} failed: {}",
+                seed, err
+            ))),
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+                panic!("simulation with seed {} panicked: {}", seed, message)
+            }
+        }
+    }
+
+    fn new(seed: u64, config: Config) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let network = SimNetwork::new(StdRng::seed_from_u64(rng.gen()));
+
+        Simulation {
+            seed,
+            config,
+            rng,
+            network: Arc::new(network),
+            nodes: BTreeMap::new(),
+            next_port: FIRST_PORT,
+        }
+    }
+
+    /// Gets the seed of the simulation.
+    ///
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Gets the seeded generator, for the scenario's own random choices.
+    ///
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Gets the running nodes, in ID order.
+    ///
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.nodes.values().map(|node| node.info.clone()).collect()
+    }
+
+    /// Picks a running node at random.
+    ///
+    pub fn random_node(&mut self) -> Option<NodeInfo> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let idx = self.rng.gen_range(0..self.nodes.len());
+        self.nodes.values().nth(idx).map(|node| node.info.clone())
+    }
+
+    /// Finds the running node responsible for a key: the node with the
+    /// largest ID not greater than the key's ID, wrapping around the ring.
+    ///
+    pub fn owner(&self, key: &[u8]) -> Option<NodeInfo> {
+        let id = Sha256Hasher::hash_once(key);
+        self.nodes
+            .range(..=id)
+            .next_back()
+            .or_else(|| self.nodes.iter().next_back())
+            .map(|(_, node)| node.info.clone())
+    }
+
+    /// Adds a node with a random ID, joining through a random running node.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new node, or an error if it failed to join.
+    ///
+    pub async fn add_node(&mut self) -> Result<NodeInfo> {
+        let id = self.rng.gen();
+        self.add_node_with_id(id).await
+    }
+
+    /// Adds nodes one after another.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new nodes, or the first join error.
+    ///
+    pub async fn add_nodes(&mut self, count: usize) -> Result<Vec<NodeInfo>> {
+        let mut infos = Vec::with_capacity(count);
+        for _ in 0..count {
+            infos.push(self.add_node().await?);
+        }
+        Ok(infos)
+    }
+
+    /// Adds a node with the given ID, joining through a random running node.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new node, or an error if it failed to join.
+    ///
+    pub async fn add_node_with_id(&mut self, id: u64) -> Result<NodeInfo> {
+        let addr: SocketAddr = format!("127.0.0.1:{}", self.next_port).parse()?;
+        self.next_port += 1;
+
+        let transport = SimTransport {
+            network: self.network.clone(),
+            local: Some(addr.to_string()),
+        };
+        let config = self.config.clone().with_transport(Arc::new(transport));
+        let node = Node::from_id(config, addr, addr, id)?;
+        let info = NodeInfo::new(node.id, &node.pub_addr);
+
+        let bootstrap_addr = self.random_node().map(|node| node.pub_addr);
+        self.network
+            .record(format!("#{:016X} joins at {}", id, addr));
+        let handle = node.bootstrap_and_serve(bootstrap_addr.as_deref()).await?;
+
+        self.nodes.insert(
+            id,
+            SimNode {
+                info: info.clone(),
+                handle,
+            },
+        );
+        Ok(info)
+    }
+
+    /// Crashes a node: it stops serving, its open connections are cut and
+    /// every message to or from it is lost from now on.
+    ///
+    pub fn crash(&mut self, id: u64) -> Result<()> {
+        let node = self
+            .nodes
+            .remove(&id)
+            .ok_or_else(|| Error::Value(format!("no running node #{:016X}", id)))?;
+        node.handle.abort();
+
+        let authority = node_authority(&node.info)?;
+        self.network
+            .update(format!("#{:016X} crashes", id), |state| {
+                state.crashed.insert(authority);
+            })
+    }
+
+    /// Partitions the network: the given nodes only reach each other, and
+    /// open connections across the partition are cut. Partitions stack until
+    /// healed.
+    ///
+    pub fn partition(&mut self, ids: &[u64]) -> Result<()> {
+        let authorities = ids
+            .iter()
+            .filter_map(|id| self.nodes.get(id))
+            .map(|node| node_authority(&node.info))
+            .collect::<Result<Vec<_>>>()?;
+
+        let event = format!(
+            "partition {{{}}}",
+            ids.iter()
+                .map(|id| format!("#{:016X}", id))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        self.network.update(event, |state| {
+            let group = state.partitions.values().max().map_or(1, |group| group + 1);
+            for authority in authorities {
+                state.partitions.insert(authority, group);
+            }
+        })
+    }
+
+    /// Removes every partition.
+    ///
+    pub fn heal(&mut self) -> Result<()> {
+        self.network.update("heal".to_owned(), |state| {
+            state.partitions.clear();
+        })
+    }
+
+    /// Sets the faults injected into messages from now on.
+    ///
+    pub fn set_faults(&mut self, faults: Faults) -> Result<()> {
+        if !(0.0..=1.0).contains(&faults.drop_rate) || faults.min_delay > faults.max_delay {
+            return Err(Error::Config(format!("invalid faults: {:?}", faults)));
+        }
+
+        self.network.update(format!("{:?}", faults), |state| {
+            state.faults = faults;
+        })
+    }
+
+    /// Lets the network run for a while of virtual time.
+    ///
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    /// Connects a client to a running node. Clients observe the network
+    /// from outside: their messages are never dropped, delayed or
+    /// partitioned, but crashed nodes do not answer them.
+    ///
+    pub async fn client(&self, id: u64) -> Result<PastryClient> {
+        let node = self
+            .nodes
+            .get(&id)
+            .ok_or_else(|| Error::Value(format!("no running node #{:016X}", id)))?;
+        PastryClient::connect_with_config(&node.info.pub_addr, &self.client_config()).await
+    }
+
+    /// Crawls the ring from the node with the lowest ID and checks its
+    /// consistency.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the violations found, or an error if no node is
+    /// running.
+    ///
+    pub async fn check(&self) -> Result<Vec<Violation>> {
+        let seed = self
+            .nodes
+            .values()
+            .next()
+            .ok_or_else(|| Error::Value("no running node".into()))?;
+        let snapshot = ring::crawl(&seed.info.pub_addr, &self.client_config(), true).await?;
+        Ok(snapshot.check())
+    }
+
+    /// Gets the events recorded so far: faults injected, partitions, crashes
+    /// and joins, stamped with virtual time.
+    ///
+    pub fn log(&self) -> Vec<String> {
+        self.network.log()
+    }
+
+    /// Records an event of the scenario's own, such as the outcome of a
+    /// query, so it is compared when replaying the scenario.
+    ///
+    pub fn record(&self, event: &str) {
+        self.network.record(event.to_owned());
+    }
+
+    fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new().with_transport(Arc::new(SimTransport {
+            network: self.network.clone(),
+            local: None,
+        }));
+        config.token = self.config.peer_token.clone();
+        config
+    }
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("seed", &self.seed)
+            .field("nodes", &self.nodes.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+fn node_authority(info: &NodeInfo) -> Result<String> {
+    let uri: Uri = info
+        .pub_addr
+        .parse()
+        .map_err(|_| Error::Internal(format!("invalid public address: {}", info.pub_addr)))?;
+    authority(&uri)
+}
+
+/// The state of the simulated network, shared by every node's transport.
+#[derive(Debug)]
+struct SimNetwork {
+    memory: MemoryTransport,
+    state: Mutex<NetworkState>,
+    /// Bumped on every crash or partition change, so open connections
+    /// re-check their link.
+    epoch: watch::Sender<u64>,
+    start: Instant,
+}
+
+#[derive(Debug)]
+struct NetworkState {
+    rng: StdRng,
+    faults: Faults,
+    crashed: HashSet<String>,
+    /// The partition of every partitioned node, by authority. Nodes outside
+    /// every partition are in partition 0.
+    partitions: HashMap<String, usize>,
+    log: Vec<String>,
+}
+
+impl SimNetwork {
+    fn new(rng: StdRng) -> Self {
+        SimNetwork {
+            memory: MemoryTransport::new(),
+            state: Mutex::new(NetworkState {
+                rng,
+                faults: Faults::default(),
+                crashed: HashSet::new(),
+                partitions: HashMap::new(),
+                log: Vec::new(),
+            }),
+            epoch: watch::channel(0).0,
+            start: Instant::now(),
+        }
+    }
+
+    fn log(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .map(|state| state.log.clone())
+            .unwrap_or_default()
+    }
+
+    fn stamp(&self, event: String) -> String {
+        format!("[{:>8}ms] {}", self.start.elapsed().as_millis(), event)
+    }
+
+    fn record(&self, event: String) {
+        if let Ok(mut state) = self.state.lock() {
+            state.log.push(self.stamp(event));
+        }
+    }
+
+    /// Changes the network and cuts the connections it breaks.
+    fn update(&self, event: String, f: impl FnOnce(&mut NetworkState)) -> Result<()> {
+        {
+            let mut state = self.state.lock()?;
+            f(&mut state);
+            state.log.push(self.stamp(event));
+        }
+        self.epoch.send_modify(|epoch| *epoch += 1);
+        Ok(())
+    }
+
+    fn is_linked(state: &NetworkState, from: Option<&str>, to: &str) -> bool {
+        let partition = |authority| state.partitions.get(authority).copied().unwrap_or(0);
+
+        !state.crashed.contains(to)
+            && from.is_none_or(|from| {
+                !state.crashed.contains(from) && partition(from) == partition(to)
+            })
+    }
+
+    fn linked(&self, from: Option<&str>, to: &str) -> bool {
+        self.state
+            .lock()
+            .map(|state| SimNetwork::is_linked(&state, from, to))
+            .unwrap_or(false)
+    }
+
+    /// Decides the fate of a message: the latency it is delivered with, or
+    /// an error if it is lost.
+    fn route(&self, from: Option<&str>, to: &str) -> io::Result<Duration> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        let refused = |reason| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("{} -> {} {}", from.unwrap_or("client"), to, reason),
+            )
+        };
+
+        if !SimNetwork::is_linked(&state, from, to) {
+            return Err(refused("unreachable"));
+        }
+
+        // clients observe the network from outside
+        let Some(from) = from else {
+            return Ok(Duration::ZERO);
+        };
+
+        let faults = state.faults;
+        if faults.drop_rate > 0.0 && state.rng.gen_bool(faults.drop_rate) {
+            let event = self.stamp(format!("dropped {} -> {}", from, to));
+            state.log.push(event);
+            return Err(refused("dropped"));
+        }
+
+        Ok(if faults.max_delay > faults.min_delay {
+            state.rng.gen_range(faults.min_delay..=faults.max_delay)
+        } else {
+            faults.min_delay
+        })
+    }
+}
+
+/// A node's or client's view of the simulated network.
+#[derive(Debug, Clone)]
+struct SimTransport {
+    network: Arc<SimNetwork>,
+    /// The authority of the node, or `None` for clients.
+    local: Option<String>,
+}
+
+#[tonic::async_trait]
+impl Transport for SimTransport {
+    fn bind(&self, addr: SocketAddr, pub_addr: &str, router: Router) -> Result<ServeFuture> {
+        self.network.memory.bind(addr, pub_addr, router)
+    }
+
+    async fn connect(&self, endpoint: Endpoint) -> Result<Channel> {
+        Ok(endpoint.connect_with_connector(self.clone()).await?)
+    }
+}
+
+impl Service<Uri> for SimTransport {
+    type Response = SimStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<SimStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let network = self.network.clone();
+        let from = self.local.clone();
+
+        Box::pin(async move {
+            let to = authority(&uri)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+            let delay = network.route(from.as_deref(), &to)?;
+            tokio::time::sleep(delay).await;
+
+            let inner = network.memory.open(&to)?;
+            Ok(SimStream::new(inner, network, from, to))
+        })
+    }
+}
+
+/// The client side of a connection, cut as soon as a crash or partition
+/// breaks its link.
+struct SimStream {
+    inner: DuplexStream,
+    network: Arc<SimNetwork>,
+    from: Option<String>,
+    to: String,
+    changed: Pin<Box<dyn Future<Output = ()> + Send>>,
+    severed: bool,
+}
+
+impl SimStream {
+    fn new(
+        inner: DuplexStream,
+        network: Arc<SimNetwork>,
+        from: Option<String>,
+        to: String,
+    ) -> Self {
+        let changed = SimStream::watch(&network);
+        SimStream {
+            inner,
+            network,
+            from,
+            to,
+            changed,
+            severed: false,
+        }
+    }
+
+    fn watch(network: &SimNetwork) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let mut epoch = network.epoch.subscribe();
+        Box::pin(async move {
+            let _ = epoch.changed().await;
+        })
+    }
+
+    /// Checks whether the link broke since the connection was opened.
+    fn poll_severed(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        while !self.severed && self.changed.as_mut().poll(cx).is_ready() {
+            self.severed = !self.network.linked(self.from.as_deref(), &self.to);
+            self.changed = SimStream::watch(&self.network);
+        }
+
+        if self.severed {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                format!("connection to {} cut", self.to),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for SimStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_severed(cx)?;
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SimStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.poll_severed(cx)?;
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.poll_severed(cx)?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,425 @@
+use log::{info, warn};
+use rand::Rng;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    client::{ClientConfig, PastryClient},
+    error::*,
+    internal::{
+        dht::{
+            node::Node,
+            service::grpc::{QueryRequest, QueryType},
+        },
+        hring::hasher::Sha256Hasher,
+    },
+    ring::{self, RingSnapshot, Violation},
+    Config, MemoryTransport, NodeInfo,
+};
+
+const DEFAULT_FIRST_PORT: u16 = 40000;
+const MAX_PORT_ATTEMPTS: u16 = 100;
+const CONVERGENCE_POLL_MILLIS: u64 = 100;
+
+/// Builds a `TestNetwork` of local nodes.
+///
+#[derive(Debug, Clone)]
+pub struct NetworkBuilder {
+    config: Config,
+    num_nodes: usize,
+    ids: Vec<u64>,
+    first_port: u16,
+    in_memory: bool,
+}
+
+impl NetworkBuilder {
+    /// Sets the number of nodes the network starts with.
+    ///
+    pub fn nodes(mut self, num_nodes: usize) -> Self {
+        self.num_nodes = num_nodes;
+        self
+    }
+
+    /// Sets the IDs of the first nodes, in joining order. Other nodes get
+    /// the hash of their address as ID.
+    ///
+    pub fn ids(mut self, ids: Vec<u64>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Sets the first port nodes listen on. Ports already in use are
+    /// skipped.
+    ///
+    pub fn first_port(mut self, port: u16) -> Self {
+        self.first_port = port;
+        self
+    }
+
+    /// Connects the nodes through a `MemoryTransport` instead of TCP
+    /// sockets, so the network runs without binding ports.
+    ///
+    pub fn in_memory(mut self) -> Self {
+        self.in_memory = true;
+        self
+    }
+
+    /// Starts the nodes, each joining through a random node already in the
+    /// network.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the running network.
+    ///
+    pub async fn build(self) -> Result<TestNetwork> {
+        let config = if self.in_memory {
+            self.config.with_transport(Arc::new(MemoryTransport::new()))
+        } else {
+            self.config
+        };
+
+        let mut network = TestNetwork {
+            config,
+            nodes: BTreeMap::new(),
+            killed: BTreeMap::new(),
+            next_port: self.first_port,
+        };
+
+        for i in 0..self.num_nodes.max(self.ids.len()) {
+            match self.ids.get(i) {
+                Some(&id) => network.add_node_with_id(id).await?,
+                None => network.add_node().await?,
+            };
+        }
+
+        info!("Created test network of {} nodes", network.nodes.len());
+        Ok(network)
+    }
+}
+
+/// A network of local nodes for tests, stopped when dropped.
+///
+/// ```no_run
+/// # use pastry_dht::{error::Result, testkit::TestNetwork, Config};
+/// # use std::time::Duration;
+/// # async fn example() -> Result<()> {
+/// let mut network = TestNetwork::builder(Config::new(4)).nodes(16).build().await?;
+///
+/// let owner = network.owner(b"key").unwrap();
+/// network.kill(owner.id).await?;
+/// network.wait_for_convergence(Duration::from_secs(10)).await?;
+///
+/// network.restart(owner.id).await?;
+/// network.wait_for_convergence(Duration::from_secs(10)).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+pub struct TestNetwork {
+    config: Config,
+    nodes: BTreeMap<u64, TestNode>,
+    killed: BTreeMap<u64, NodeInfo>,
+    next_port: u16,
+}
+
+struct TestNode {
+    info: NodeInfo,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl TestNetwork {
+    /// Starts building a network of nodes sharing a config.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The config of every node.
+    ///
+    /// # Returns
+    ///
+    /// A builder for a network of a single node on TCP.
+    ///
+    pub fn builder(config: Config) -> NetworkBuilder {
+        NetworkBuilder {
+            config,
+            num_nodes: 1,
+            ids: Vec::new(),
+            first_port: DEFAULT_FIRST_PORT,
+            in_memory: false,
+        }
+    }
+
+    /// Gets the config shared by every node.
+    ///
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Gets the running nodes, in ID order.
+    ///
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.nodes.values().map(|node| node.info.clone()).collect()
+    }
+
+    /// Gets the killed nodes that were not restarted, in ID order.
+    ///
+    pub fn killed(&self) -> Vec<NodeInfo> {
+        self.killed.values().cloned().collect()
+    }
+
+    /// Picks a running node at random.
+    ///
+    pub fn random_node(&self) -> Option<NodeInfo> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let idx = rand::thread_rng().gen_range(0..self.nodes.len());
+        self.nodes.values().nth(idx).map(|node| node.info.clone())
+    }
+
+    /// Finds the running node responsible for a key.
+    ///
+    pub fn owner(&self, key: &[u8]) -> Option<NodeInfo> {
+        self.owner_of_id(Sha256Hasher::hash_once(key))
+    }
+
+    /// Finds the running node responsible for an ID in the ring: the node
+    /// with the largest ID not greater than it, wrapping around.
+    ///
+    pub fn owner_of_id(&self, id: u64) -> Option<NodeInfo> {
+        self.nodes
+            .range(..=id)
+            .next_back()
+            .or_else(|| self.nodes.iter().next_back())
+            .map(|(_, node)| node.info.clone())
+    }
+
+    /// Adds a node with the hash of its address as ID.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new node.
+    ///
+    pub async fn add_node(&mut self) -> Result<NodeInfo> {
+        self.start_node(None).await
+    }
+
+    /// Adds a node with the given ID.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the new node.
+    ///
+    pub async fn add_node_with_id(&mut self, id: u64) -> Result<NodeInfo> {
+        self.start_node(Some(id)).await
+    }
+
+    async fn start_node(&mut self, id: Option<u64>) -> Result<NodeInfo> {
+        let mut last_err = None;
+        for _ in 0..MAX_PORT_ATTEMPTS {
+            let addr: SocketAddr = format!("0.0.0.0:{}", self.next_port).parse()?;
+            self.next_port += 1;
+
+            let node = match id {
+                Some(id) => Node::from_id(self.config.clone(), addr, addr, id)?,
+                None => Node::new(self.config.clone(), addr, addr)?,
+            };
+            match self.serve(node).await {
+                Ok(info) => return Ok(info),
+                Err(err) => {
+                    warn!("Error starting node on {}: {}", addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| Error::Internal("no port available".into())))
+    }
+
+    /// Joins a node through a random running node and serves it.
+    async fn serve(&mut self, node: Node) -> Result<NodeInfo> {
+        let info = NodeInfo::new(node.id, &node.pub_addr);
+        let bootstrap_addr = self.random_node().map(|node| node.pub_addr);
+        let handle = node.bootstrap_and_serve(bootstrap_addr.as_deref()).await?;
+
+        self.killed.remove(&info.id);
+        self.nodes.insert(
+            info.id,
+            TestNode {
+                info: info.clone(),
+                handle,
+            },
+        );
+        Ok(info)
+    }
+
+    /// Kills a node without notifying the others, as if it crashed. Its
+    /// keys are lost.
+    ///
+    pub async fn kill(&mut self, id: u64) -> Result<()> {
+        let node = self
+            .nodes
+            .remove(&id)
+            .ok_or_else(|| Error::Value(format!("no running node #{:016X}", id)))?;
+
+        node.handle.abort();
+        let _ = node.handle.await;
+        self.killed.insert(id, node.info);
+        Ok(())
+    }
+
+    /// Restarts a killed node with its ID and address, joining through a
+    /// random running node. It starts with an empty store.
+    ///
+    pub async fn restart(&mut self, id: u64) -> Result<NodeInfo> {
+        let info = self
+            .killed
+            .get(&id)
+            .ok_or_else(|| Error::Value(format!("no killed node #{:016X}", id)))?;
+
+        let addr: SocketAddr = info
+            .pub_addr
+            .trim_start_matches("http://")
+            .trim_start_matches("https://")
+            .parse()?;
+        let node = Node::from_id(self.config.clone(), addr, addr, id)?;
+        self.serve(node).await
+    }
+
+    /// Gets the settings clients of the network connect with, carrying the
+    /// config's transport and peer token.
+    ///
+    pub fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new().with_transport(self.config.transport.clone());
+        config.tls = self.config.tls.clone();
+        config.token = self.config.peer_token.clone();
+        config
+    }
+
+    /// Connects a client to a running node.
+    ///
+    pub async fn client(&self, id: u64) -> Result<PastryClient> {
+        let node = self
+            .nodes
+            .get(&id)
+            .ok_or_else(|| Error::Value(format!("no running node #{:016X}", id)))?;
+        PastryClient::connect_with_config(&node.info.pub_addr, &self.client_config()).await
+    }
+
+    /// Crawls the ring from the running node with the lowest ID.
+    ///
+    pub async fn crawl(&self, with_keys: bool) -> Result<RingSnapshot> {
+        let seed = self
+            .nodes
+            .values()
+            .next()
+            .ok_or_else(|| Error::Value("no running node".into()))?;
+        ring::crawl(&seed.info.pub_addr, &self.client_config(), with_keys).await
+    }
+
+    /// Waits until every running node is reachable from the others and the
+    /// ring is consistent, with keys stored in their owners.
+    ///
+    /// Routing tables are repaired lazily, so entries for killed nodes are
+    /// tolerated. Leaf sets are only repaired when a node routes through a
+    /// killed neighbor, so waiting also routes a lookup of every killed
+    /// leaf set entry through the nodes listing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait.
+    ///
+    /// # Returns
+    ///
+    /// An empty Result, or an error listing the remaining violations once
+    /// the timeout elapses.
+    ///
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let snapshot = self.crawl(true).await?;
+            let violations: Vec<Violation> = snapshot
+                .check()
+                .into_iter()
+                .filter(|violation| !matches!(violation, Violation::DeadRoutingEntry { .. }))
+                .collect();
+            let missing: Vec<u64> = self
+                .nodes
+                .keys()
+                .filter(|id| !snapshot.nodes.contains_key(id))
+                .copied()
+                .collect();
+
+            if violations.is_empty() && missing.is_empty() {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                let mut problems: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+                problems.extend(
+                    missing
+                        .iter()
+                        .map(|id| format!("#{:016X}: not reachable from the ring", id)),
+                );
+                return Err(Error::Internal(format!(
+                    "network did not converge in {:?}: {}",
+                    timeout,
+                    problems.join("; ")
+                )));
+            }
+
+            for violation in &violations {
+                if let Violation::DeadLeafEntry { node, entry } = violation {
+                    self.route_to_dead_entry(&snapshot, *node, *entry).await;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(CONVERGENCE_POLL_MILLIS)).await;
+        }
+    }
+
+    /// Routes a lookup of a dead leaf set entry's ID through a node, so the
+    /// node notices the failure and repairs its leaf set.
+    async fn route_to_dead_entry(&self, snapshot: &RingSnapshot, node: u64, entry: u64) {
+        let Some(dump) = snapshot.nodes.get(&node) else {
+            return;
+        };
+
+        let res = async {
+            let mut client = Node::connect_with_retry(&dump.pub_addr, &self.config).await?;
+            client
+                .query(QueryRequest {
+                    from_id: 0,
+                    matched_digits: 0,
+                    hops: 0,
+                    query_type: QueryType::Get.into(),
+                    key: entry,
+                    value: None,
+                    raw_key: Vec::new(),
+                    options: None,
+                })
+                .await?;
+            Ok::<_, Error>(())
+        };
+
+        if let Err(err) = res.await {
+            warn!(
+                "Routing to #{:016X} through #{:016X} failed: {}",
+                entry, node, err
+            );
+        }
+    }
+}
+
+impl Drop for TestNetwork {
+    fn drop(&mut self) {
+        for node in self.nodes.values() {
+            node.handle.abort();
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use serde::{Serialize, Serializer};
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{admin::NodeDump, error::*, ring::RingSnapshot};
+
+/// The overlay as a directed graph: an edge from a node to every node in
+/// its leaf set and routing table.
+///
+/// Nodes and edges are kept in ID order, so exports of the same overlay are
+/// identical and can be diffed over time.
+///
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Topology {
+    pub nodes: Vec<TopologyNode>,
+    pub edges: Vec<Edge>,
+}
+
+/// A node in the topology.
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopologyNode {
+    #[serde(serialize_with = "serialize_id")]
+    pub id: u64,
+    pub pub_addr: String,
+    /// Whether the node's own state is part of the topology. Nodes that are
+    /// only referenced by others have no outgoing edges.
+    pub crawled: bool,
+}
+
+/// A reference from a node's leaf set or routing table to another node.
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Edge {
+    #[serde(serialize_with = "serialize_id")]
+    pub from: u64,
+    #[serde(serialize_with = "serialize_id")]
+    pub to: u64,
+    #[serde(flatten)]
+    pub kind: EdgeKind,
+}
+
+/// Where a reference is held.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EdgeKind {
+    Leaf,
+    Route { row: usize, column: usize },
+}
+
+/// IDs are exported as hexadecimal strings, since JSON consumers may not
+/// handle 64-bit integers.
+fn serialize_id<S: Serializer>(id: &u64, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016X}", id))
+}
+
+impl Topology {
+    /// Builds the topology seen by a single node: the node and its edges to
+    /// the nodes in its leaf set and routing table.
+    ///
+    /// # Arguments
+    ///
+    /// * `dump` - The state of the node.
+    ///
+    /// # Returns
+    ///
+    /// The topology of the node.
+    ///
+    pub fn from_node(dump: &NodeDump) -> Self {
+        Topology::from_dumps([dump])
+    }
+
+    /// Builds the topology of a crawled ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The crawled ring.
+    ///
+    /// # Returns
+    ///
+    /// The topology of the ring.
+    ///
+    pub fn from_ring(snapshot: &RingSnapshot) -> Self {
+        Topology::from_dumps(snapshot.nodes.values())
+    }
+
+    fn from_dumps<'a>(dumps: impl IntoIterator<Item = &'a NodeDump>) -> Self {
+        let mut nodes = BTreeMap::new();
+        let mut referenced = BTreeMap::new();
+        let mut edges = Vec::new();
+
+        for dump in dumps {
+            nodes.insert(
+                dump.id,
+                TopologyNode {
+                    id: dump.id,
+                    pub_addr: dump.pub_addr.clone(),
+                    crawled: true,
+                },
+            );
+
+            let leaf_set = dump.leaf_set.iter().map(|entry| (EdgeKind::Leaf, entry));
+            let routing_table = dump
+                .routing_table
+                .iter()
+                .enumerate()
+                .flat_map(|(row, entries)| {
+                    entries
+                        .iter()
+                        .enumerate()
+                        .filter_map(move |(column, entry)| {
+                            entry
+                                .as_ref()
+                                .map(|entry| (EdgeKind::Route { row, column }, entry))
+                        })
+                });
+
+            for (kind, entry) in leaf_set.chain(routing_table) {
+                if entry.id == dump.id {
+                    continue;
+                }
+
+                edges.push(Edge {
+                    from: dump.id,
+                    to: entry.id,
+                    kind,
+                });
+                referenced.insert(entry.id, entry.pub_addr.clone());
+            }
+        }
+
+        for (id, pub_addr) in referenced {
+            nodes.entry(id).or_insert(TopologyNode {
+                id,
+                pub_addr,
+                crawled: false,
+            });
+        }
+
+        Topology {
+            nodes: nodes.into_values().collect(),
+            edges,
+        }
+    }
+
+    /// Exports the topology to JSON.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the JSON document, with IDs as hexadecimal
+    /// strings.
+    ///
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Exports the topology to a Graphviz DOT digraph. Leaf set edges are
+    /// dashed and routing table edges are labelled by row and column, in
+    /// hexadecimal. Nodes that were not crawled are drawn dashed.
+    ///
+    /// # Returns
+    ///
+    /// The DOT document.
+    ///
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph pastry {\n    node [shape=box];\n");
+
+        for node in &self.nodes {
+            let style = if node.crawled { "" } else { ", style=dashed" };
+            let _ = writeln!(
+                dot,
+                "    \"{:016X}\" [label=\"#{:016X}\\n{}\"{}];",
+                node.id, node.id, node.pub_addr, style
+            );
+        }
+
+        for edge in &self.edges {
+            let attrs = match edge.kind {
+                EdgeKind::Leaf => "style=dashed, color=gray".to_owned(),
+                EdgeKind::Route { row, column } => format!("label=\"{:X},{:X}\"", row, column),
+            };
+            let _ = writeln!(
+                dot,
+                "    \"{:016X}\" -> \"{:016X}\" [{}];",
+                edge.from, edge.to, attrs
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        admin::{NodeState, StoreStats},
+        NodeInfo,
+    };
+    use std::time::Duration;
+
+    fn dump(id: u64, leaf_set: &[u64]) -> NodeDump {
+        let info = |id: u64| NodeInfo::new(id, &format!("http://{:X}", id));
+        let mut routing_table = vec![vec![None; 16]; 16];
+        routing_table[0][(id >> 60) as usize] = Some(info(id));
+
+        NodeDump {
+            id,
+            pub_addr: info(id).pub_addr,
+            state: NodeState::RoutingRequests,
+            uptime: Duration::ZERO,
+            version: String::new(),
+            k: 1,
+            leaf_set: leaf_set.iter().map(|&id| info(id)).collect(),
+            routing_table,
+            store: StoreStats::default(),
+            transfers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_node_topology() -> Result<()> {
+        let mut node = dump(0x1000_0000_0000_0000, &[0x1000_0000_0000_0000]);
+        node.routing_table[0][2] = Some(NodeInfo::new(0x2000_0000_0000_0000, "http://2"));
+        node.leaf_set
+            .push(NodeInfo::new(0x2000_0000_0000_0000, "http://2"));
+
+        let topology = Topology::from_node(&node);
+        assert_eq!(
+            topology.nodes.iter().map(|n| n.crawled).collect::<Vec<_>>(),
+            vec![true, false]
+        );
+        assert_eq!(
+            topology.edges.iter().map(|e| e.kind).collect::<Vec<_>>(),
+            vec![EdgeKind::Leaf, EdgeKind::Route { row: 0, column: 2 }]
+        );
+
+        let dot = topology.to_dot();
+        assert!(dot
+            .contains("\"1000000000000000\" -> \"2000000000000000\" [style=dashed, color=gray];"));
+        assert!(dot.contains("\"1000000000000000\" -> \"2000000000000000\" [label=\"0,2\"];"));
+        assert!(dot.contains(
+            "\"2000000000000000\" [label=\"#2000000000000000\\nhttp://2\", style=dashed];"
+        ));
+
+        let json: serde_json::Value = serde_json::from_str(&topology.to_json()?).unwrap();
+        assert_eq!(json["nodes"][1]["id"], "2000000000000000");
+        assert_eq!(json["edges"][0]["kind"], "leaf");
+        assert_eq!(json["edges"][1]["kind"], "route");
+        assert_eq!(json["edges"][1]["row"], 0);
+        assert_eq!(json["edges"][1]["column"], 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ring_topology() {
+        let (a, b) = (0x1000_0000_0000_0000, 0x8000_0000_0000_0000);
+        let snapshot = RingSnapshot {
+            nodes: [(b, dump(b, &[a, b])), (a, dump(a, &[a, b]))]
+                .into_iter()
+                .collect(),
+            ..RingSnapshot::default()
+        };
+
+        let topology = Topology::from_ring(&snapshot);
+        assert_eq!(
+            topology.nodes.iter().map(|n| n.id).collect::<Vec<_>>(),
+            vec![a, b]
+        );
+        assert!(topology.nodes.iter().all(|n| n.crawled));
+        assert_eq!(
+            topology
+                .edges
+                .iter()
+                .map(|e| (e.from, e.to))
+                .collect::<Vec<_>>(),
+            vec![(a, b), (b, a)]
+        );
+    }
+}